@@ -1,6 +1,11 @@
+use std::fmt;
+use std::sync::Arc;
+
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use minijinja::machinery::parse;
+use minijinja::value::{Object, Value};
 use minijinja::{context, Environment, State};
+use serde::Serialize;
 
 fn do_parse() {
     parse(
@@ -35,6 +40,66 @@ fn do_render(env: &Environment) {
     .unwrap();
 }
 
+fn do_unique(env: &Environment, items: &[String]) {
+    let expr = env.compile_expression("items|unique|length").unwrap();
+    expr.eval(context! { items => black_box(items) }).unwrap();
+}
+
+fn do_filter_chain(env: &Environment, users: &Value) {
+    let expr = env
+        .compile_expression(
+            "users|selectattr('active')|map(attribute='name')|map('upper')|sort|join(',')",
+        )
+        .unwrap();
+    expr.eval(context! { users => black_box(users) }).unwrap();
+}
+
+#[derive(Debug)]
+struct Registry(Vec<String>);
+
+impl fmt::Display for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<registry>")
+    }
+}
+
+impl Object for Registry {
+    fn get_attr(&self, name: &str) -> Option<Value> {
+        self.0
+            .iter()
+            .find(|x| *x == name)
+            .map(|x| Value::from(x.as_str()))
+    }
+
+    fn attributes(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        Box::new(self.0.iter().map(|x| x.as_str()))
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+fn do_object_len(env: &Environment, registry: &Value) {
+    let expr = env
+        .compile_expression("registry|length if registry else 0")
+        .unwrap();
+    expr.eval(context! { registry => black_box(registry) })
+        .unwrap();
+}
+
+#[derive(Serialize)]
+struct Row {
+    name: &'static str,
+    active: bool,
+    score: u32,
+}
+
+fn do_shared_keys(env: &Environment) {
+    let expr = env.compile_expression("rows()|length").unwrap();
+    expr.eval(()).unwrap();
+}
+
 fn create_real_env() -> Environment<'static> {
     let mut env = Environment::new();
     env.add_template("footer.html", include_str!("../inputs/footer.html"))
@@ -56,6 +121,45 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         let env = create_real_env();
         b.iter(|| do_render(&env));
     });
+    c.bench_function("unique", |b| {
+        let env = Environment::new();
+        let items = (0..10_000)
+            .map(|idx| format!("item-{}", idx % 500))
+            .collect::<Vec<_>>();
+        b.iter(|| do_unique(&env, &items));
+    });
+    c.bench_function("filter_chain", |b| {
+        let env = Environment::new();
+        let users = Value::from(
+            (0..10_000)
+                .map(|idx| context!(name => format!("user-{}", idx), active => idx % 3 != 0))
+                .collect::<Vec<_>>(),
+        );
+        b.iter(|| do_filter_chain(&env, &users));
+    });
+    c.bench_function("object_len", |b| {
+        let env = Environment::new();
+        let registry = Value::from_object(Registry(
+            (0..100_000).map(|idx| format!("attr-{}", idx)).collect(),
+        ));
+        b.iter(|| do_object_len(&env, &registry));
+    });
+    c.bench_function("shared_keys", |b| {
+        let mut env = Environment::new();
+        let rows = Arc::new(
+            (0..100_000)
+                .map(|idx| Row {
+                    name: "row",
+                    active: idx % 2 == 0,
+                    score: idx,
+                })
+                .collect::<Vec<_>>(),
+        );
+        env.add_function("rows", move |_: &State| {
+            Ok(Value::from_serializable(&*rows))
+        });
+        b.iter(|| do_shared_keys(&env));
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
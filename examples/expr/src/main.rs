@@ -4,6 +4,7 @@
 use std::collections::BTreeMap;
 use std::env;
 
+use minijinja::value::to_json_pretty;
 use minijinja::{context, Environment};
 
 fn main() {
@@ -18,6 +19,6 @@ fn main() {
     let expr = env.compile_expression(&args[1]).unwrap();
     let env = std::env::vars().collect::<BTreeMap<_, _>>();
     let result = expr.eval(context!(env)).unwrap();
-    let serialized = serde_json::to_string_pretty(&result).unwrap();
+    let serialized = to_json_pretty(&result).unwrap();
     println!("{}", serialized);
 }
@@ -54,6 +54,7 @@ pub enum Stmt<'a> {
     Template(Spanned<Template<'a>>),
     EmitExpr(Spanned<EmitExpr<'a>>),
     EmitRaw(Spanned<EmitRaw<'a>>),
+    EmitComment(Spanned<EmitComment<'a>>),
     ForLoop(Spanned<ForLoop<'a>>),
     IfCond(Spanned<IfCond<'a>>),
     WithBlock(Spanned<WithBlock<'a>>),
@@ -82,6 +83,7 @@ impl<'a> fmt::Debug for Stmt<'a> {
             Stmt::Template(s) => fmt::Debug::fmt(s, f),
             Stmt::EmitExpr(s) => fmt::Debug::fmt(s, f),
             Stmt::EmitRaw(s) => fmt::Debug::fmt(s, f),
+            Stmt::EmitComment(s) => fmt::Debug::fmt(s, f),
             Stmt::ForLoop(s) => fmt::Debug::fmt(s, f),
             Stmt::IfCond(s) => fmt::Debug::fmt(s, f),
             Stmt::WithBlock(s) => fmt::Debug::fmt(s, f),
@@ -122,6 +124,7 @@ pub enum Expr<'a> {
     List(Spanned<List<'a>>),
     Map(Spanned<Map<'a>>),
     Kwargs(Spanned<Kwargs<'a>>),
+    Spread(Spanned<Spread<'a>>),
 }
 
 #[cfg(feature = "internal_debug")]
@@ -142,6 +145,7 @@ impl<'a> fmt::Debug for Expr<'a> {
             Expr::List(s) => fmt::Debug::fmt(s, f),
             Expr::Map(s) => fmt::Debug::fmt(s, f),
             Expr::Kwargs(s) => fmt::Debug::fmt(s, f),
+            Expr::Spread(s) => fmt::Debug::fmt(s, f),
         }
     }
 }
@@ -266,6 +270,14 @@ pub struct EmitExpr<'a> {
 #[cfg_attr(feature = "internal_debug", derive(Debug))]
 pub struct EmitRaw<'a> {
     pub raw: &'a str,
+    /// Set for `{% raw %}` blocks which must not be changed.
+    pub verbatim: bool,
+}
+
+/// Outputs the contents of a comment.
+#[cfg_attr(feature = "internal_debug", derive(Debug))]
+pub struct EmitComment<'a> {
+    pub comment: &'a str,
 }
 
 /// Looks up a variable.
@@ -423,7 +435,17 @@ impl<'a> Kwargs<'a> {
     }
 }
 
+/// Unpacks a value into call arguments (`*args` or `**kwargs`).
+#[cfg_attr(feature = "internal_debug", derive(Debug))]
+pub struct Spread<'a> {
+    pub expr: Expr<'a>,
+    pub kwargs: bool,
+}
+
 /// Creates a map of values.
+///
+/// A key can also be a [`Spread`] (`**other`) which merges another map
+/// into the result.  Such keys have no entry in `values`.
 #[cfg_attr(feature = "internal_debug", derive(Debug))]
 pub struct Map<'a> {
     pub keys: Vec<Expr<'a>>,
@@ -431,8 +453,14 @@ pub struct Map<'a> {
 }
 
 impl<'a> Map<'a> {
+    /// Checks if any other maps are merged into this one.
+    pub fn has_spread(&self) -> bool {
+        self.keys.len() != self.values.len()
+    }
+
     pub fn as_const(&self) -> Option<Value> {
-        if !self.keys.iter().all(|x| matches!(x, Expr::Const(_)))
+        if self.has_spread()
+            || !self.keys.iter().all(|x| matches!(x, Expr::Const(_)))
             || !self.values.iter().all(|x| matches!(x, Expr::Const(_)))
         {
             return None;
@@ -466,6 +494,11 @@ pub enum CallType<'ast, 'source> {
 }
 
 impl<'a> Call<'a> {
+    /// Checks if any of the arguments need to be unpacked.
+    pub fn has_spread(&self) -> bool {
+        self.args.iter().any(|x| matches!(x, Expr::Spread(_)))
+    }
+
     /// Try to isolate a method call.
     ///
     /// name + call and attribute lookup + call are really method
@@ -5,13 +5,10 @@ use crate::compiler::instructions::{
     Instruction, Instructions, LocalId, LOOP_FLAG_RECURSIVE, LOOP_FLAG_WITH_LOOP_VAR, MAX_LOCALS,
 };
 use crate::compiler::tokens::Span;
-use crate::error::Error;
+use crate::error::{Error, ErrorKind};
 use crate::output::CaptureMode;
 use crate::value::Value;
 
-#[cfg(test)]
-use similar_asserts::assert_eq;
-
 /// For the first `MAX_LOCALS` filters/tests, an ID is returned for faster lookups from the stack.
 fn get_local_id<'source>(ids: &mut BTreeMap<&'source str, LocalId>, name: &'source str) -> LocalId {
     if let Some(id) = ids.get(name) {
@@ -42,6 +39,7 @@ pub struct CodeGenerator<'source> {
     span_stack: Vec<Span>,
     filter_local_ids: BTreeMap<&'source str, LocalId>,
     test_local_ids: BTreeMap<&'source str, LocalId>,
+    trailing_newline: Option<&'source str>,
     #[cfg(feature = "multi-template")]
     has_extends: bool,
 }
@@ -57,11 +55,17 @@ impl<'source> CodeGenerator<'source> {
             span_stack: Vec::new(),
             filter_local_ids: BTreeMap::new(),
             test_local_ids: BTreeMap::new(),
+            trailing_newline: None,
             #[cfg(feature = "multi-template")]
             has_extends: false,
         }
     }
 
+    /// Sets the trailing newline the parser chopped off the template.
+    pub fn set_trailing_newline(&mut self, newline: Option<&'source str>) {
+        self.trailing_newline = newline;
+    }
+
     /// Sets the current location's line.
     pub fn set_line(&mut self, lineno: usize) {
         self.current_line = lineno;
@@ -233,6 +237,11 @@ impl<'source> CodeGenerator<'source> {
                 for node in &t.children {
                     ok!(self.compile_stmt(node));
                 }
+                // the parser chops off the trailing newline.  Whether it's
+                // kept is decided by the environment at runtime.
+                if let Some(newline) = self.trailing_newline.take() {
+                    self.add(Instruction::EmitTrailingNewline(newline));
+                }
                 #[cfg(feature = "multi-template")]
                 {
                     if self.has_extends {
@@ -245,7 +254,15 @@ impl<'source> CodeGenerator<'source> {
             }
             ast::Stmt::EmitRaw(raw) => {
                 self.set_line_from_span(raw.span());
-                self.add(Instruction::EmitRaw(raw.raw));
+                if raw.verbatim {
+                    self.add(Instruction::EmitRawVerbatim(raw.raw));
+                } else {
+                    self.add(Instruction::EmitRaw(raw.raw));
+                }
+            }
+            ast::Stmt::EmitComment(comment) => {
+                self.set_line_from_span(comment.span());
+                self.add(Instruction::EmitComment(comment.comment));
             }
             ast::Stmt::ForLoop(for_loop) => {
                 ok!(self.compile_for_loop(for_loop));
@@ -293,11 +310,15 @@ impl<'source> CodeGenerator<'source> {
             }
             ast::Stmt::FilterBlock(filter_block) => {
                 self.set_line_from_span(filter_block.span());
+                // the body gets its own scope so that the filter arguments
+                // are evaluated in the enclosing scope.
+                self.add(Instruction::PushWith);
                 self.add(Instruction::BeginCapture(CaptureMode::Capture));
                 for node in &filter_block.body {
                     ok!(self.compile_stmt(node));
                 }
                 self.add(Instruction::EndCapture);
+                self.add(Instruction::PopFrame);
                 ok!(self.compile_expr(&filter_block.filter));
                 self.add(Instruction::Emit);
             }
@@ -460,7 +481,7 @@ impl<'source> CodeGenerator<'source> {
                     if name == "super" && call.args.is_empty() {
                         self.add_with_span(Instruction::FastSuper, call.span());
                         return Ok(());
-                    } else if name == "loop" && call.args.len() == 1 {
+                    } else if name == "loop" && call.args.len() == 1 && !call.has_spread() {
                         ok!(self.compile_expr(&call.args[0]));
                         self.add(Instruction::FastRecurse);
                         return Ok(());
@@ -594,18 +615,7 @@ impl<'source> CodeGenerator<'source> {
                 }
                 self.end_if();
             }
-            ast::Expr::Filter(f) => {
-                self.push_span(f.span());
-                if let Some(ref expr) = f.expr {
-                    ok!(self.compile_expr(expr));
-                }
-                for arg in &f.args {
-                    ok!(self.compile_expr(arg));
-                }
-                let local_id = get_local_id(&mut self.filter_local_ids, f.name);
-                self.add(Instruction::ApplyFilter(f.name, f.args.len() + 1, local_id));
-                self.pop_span();
-            }
+            ast::Expr::Filter(f) => ok!(self.compile_filter(f, false)),
             ast::Expr::Test(f) => {
                 self.push_span(f.span());
                 ok!(self.compile_expr(&f.expr));
@@ -648,14 +658,30 @@ impl<'source> CodeGenerator<'source> {
                     self.add(Instruction::LoadConst(val));
                 } else {
                     self.set_line_from_span(m.span());
-                    assert_eq!(m.keys.len(), m.values.len());
-                    for (key, value) in m.keys.iter().zip(m.values.iter()) {
-                        ok!(self.compile_expr(key));
-                        ok!(self.compile_expr(value));
+                    if !m.has_spread() {
+                        for (key, value) in m.keys.iter().zip(m.values.iter()) {
+                            ok!(self.compile_expr(key));
+                            ok!(self.compile_expr(value));
+                        }
+                        self.add(Instruction::BuildMap(m.keys.len()));
+                    } else {
+                        let mut values = m.values.iter();
+                        self.add(Instruction::BuildMap(0));
+                        for key in &m.keys {
+                            if let ast::Expr::Spread(spread) = key {
+                                ok!(self.compile_expr(&spread.expr));
+                                self.add_with_span(Instruction::MapUpdate, spread.span());
+                            } else {
+                                ok!(self.compile_expr(key));
+                                ok!(self.compile_expr(values.next().unwrap()));
+                                self.add(Instruction::BuildMap(1));
+                                self.add(Instruction::MapUpdate);
+                            }
+                        }
                     }
-                    self.add(Instruction::BuildMap(m.keys.len()));
                 }
             }
+            ast::Expr::Spread(_) => unreachable!("argument unpacking outside of call"),
             ast::Expr::Kwargs(m) => {
                 if let Some(val) = m.as_const() {
                     self.add(Instruction::LoadConst(val));
@@ -672,14 +698,41 @@ impl<'source> CodeGenerator<'source> {
         Ok(())
     }
 
+    /// Compiles a filter.
+    ///
+    /// A filter that is directly applied to the result of another filter is
+    /// chained so that lazy sequences are passed from one to the other.
+    fn compile_filter(
+        &mut self,
+        f: &ast::Spanned<ast::Filter<'source>>,
+        chained: bool,
+    ) -> Result<(), Error> {
+        self.push_span(f.span());
+        match f.expr {
+            Some(ast::Expr::Filter(ref inner)) => ok!(self.compile_filter(inner, true)),
+            Some(ref expr) => ok!(self.compile_expr(expr)),
+            None => {}
+        }
+        for arg in &f.args {
+            ok!(self.compile_expr(arg));
+        }
+        let local_id = get_local_id(&mut self.filter_local_ids, f.name);
+        let arg_count = f.args.len() + 1;
+        self.add(if chained {
+            Instruction::ApplyChainedFilter(f.name, arg_count, local_id)
+        } else {
+            Instruction::ApplyFilter(f.name, arg_count, local_id)
+        });
+        self.pop_span();
+        Ok(())
+    }
+
     fn compile_call(&mut self, c: &ast::Spanned<ast::Call<'source>>) -> Result<(), Error> {
         self.push_span(c.span());
         match c.identify_call() {
             ast::CallType::Function(name) => {
-                for arg in &c.args {
-                    ok!(self.compile_expr(arg));
-                }
-                self.add(Instruction::CallFunction(name, c.args.len()));
+                let arg_count = ok!(self.compile_call_args(&c.args, c.span()));
+                self.add(Instruction::CallFunction(name, arg_count));
             }
             #[cfg(feature = "multi-template")]
             ast::CallType::Block(name) => {
@@ -689,23 +742,77 @@ impl<'source> CodeGenerator<'source> {
             }
             ast::CallType::Method(expr, name) => {
                 ok!(self.compile_expr(expr));
-                for arg in &c.args {
-                    ok!(self.compile_expr(arg));
-                }
-                self.add(Instruction::CallMethod(name, c.args.len() + 1));
+                let arg_count = ok!(self.compile_call_args(&c.args, c.span()));
+                self.add(Instruction::CallMethod(name, arg_count.map(|x| x + 1)));
             }
             ast::CallType::Object(expr) => {
                 ok!(self.compile_expr(expr));
-                for arg in &c.args {
-                    ok!(self.compile_expr(arg));
-                }
-                self.add(Instruction::CallObject(c.args.len() + 1));
+                let arg_count = ok!(self.compile_call_args(&c.args, c.span()));
+                self.add(Instruction::CallObject(arg_count.map(|x| x + 1)));
             }
         };
         self.pop_span();
         Ok(())
     }
 
+    /// Compiles the arguments of a call.
+    ///
+    /// Returns the number of arguments on the stack.  If arguments are
+    /// unpacked (`*args` / `**kwargs`) the arguments are instead packed
+    /// into a single list and `None` is returned.
+    fn compile_call_args(
+        &mut self,
+        args: &[ast::Expr<'source>],
+        span: Span,
+    ) -> Result<Option<u16>, Error> {
+        if !args.iter().any(|x| matches!(x, ast::Expr::Spread(_))) {
+            // one slot is reserved for the object of method calls
+            if args.len() >= u16::MAX as usize {
+                let mut err = Error::new(
+                    ErrorKind::SyntaxError,
+                    "too many arguments in function call",
+                );
+                err.set_filename_and_span(self.instructions.name(), span);
+                return Err(err);
+            }
+            for arg in args {
+                ok!(self.compile_expr(arg));
+            }
+            return Ok(Some(args.len() as u16));
+        }
+
+        self.add(Instruction::BuildList(0));
+        let mut has_kwargs = false;
+        for arg in args {
+            match arg {
+                ast::Expr::Spread(spread) if spread.kwargs => {
+                    if !has_kwargs {
+                        self.add(Instruction::BuildKwargs(0));
+                        has_kwargs = true;
+                    }
+                    ok!(self.compile_expr(&spread.expr));
+                    self.add_with_span(Instruction::MergeKwargs, spread.span());
+                }
+                ast::Expr::Spread(spread) => {
+                    ok!(self.compile_expr(&spread.expr));
+                    self.add_with_span(Instruction::ListExtend, spread.span());
+                }
+                ast::Expr::Kwargs(_) => {
+                    ok!(self.compile_expr(arg));
+                    has_kwargs = true;
+                }
+                _ => {
+                    ok!(self.compile_expr(arg));
+                    self.add(Instruction::ListAppend);
+                }
+            }
+        }
+        if has_kwargs {
+            self.add(Instruction::ListAppend);
+        }
+        Ok(None)
+    }
+
     fn compile_bin_op(&mut self, c: &ast::Spanned<ast::BinOp<'source>>) -> Result<(), Error> {
         self.push_span(c.span());
         let instr = match c.op {
@@ -1,5 +1,4 @@
-#[cfg(feature = "internal_debug")]
-use std::fmt;
+use std::fmt::{self, Write};
 
 #[cfg(test)]
 use similar_asserts::assert_eq;
@@ -21,12 +20,20 @@ pub type LocalId = u8;
 pub const MAX_LOCALS: usize = 50;
 
 /// Represents an instruction for the VM.
-#[cfg_attr(feature = "internal_debug", derive(Debug))]
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub enum Instruction<'source> {
     /// Emits raw source
     EmitRaw(&'source str),
 
+    /// Emits raw source that must not be changed (`{% raw %}` blocks)
+    EmitRawVerbatim(&'source str),
+
+    /// Emits the contents of a comment with the comment delimiters.
+    EmitComment(&'source str),
+
+    /// Emits the trailing newline of a template if it should be kept.
+    EmitTrailingNewline(&'source str),
+
     /// Stores a variable (only possible in for loops)
     StoreLocal(&'source str),
 
@@ -60,6 +67,15 @@ pub enum Instruction<'source> {
     /// Appends to the list.
     ListAppend,
 
+    /// Extends the list with the items of an iterable.
+    ListExtend,
+
+    /// Merges a map into kwargs, failing on duplicate keys.
+    MergeKwargs,
+
+    /// Merges the items of a map into the map below it.
+    MapUpdate,
+
     /// Add the top two values
     Add,
 
@@ -118,6 +134,9 @@ pub enum Instruction<'source> {
     /// Apply a filter.
     ApplyFilter(&'source str, usize, LocalId),
 
+    /// Apply a filter whose result is the value of another filter.
+    ApplyChainedFilter(&'source str, usize, LocalId),
+
     /// Perform a filter.
     PerformTest(&'source str, usize, LocalId),
 
@@ -166,13 +185,17 @@ pub enum Instruction<'source> {
     EndCapture,
 
     /// Calls a global function
-    CallFunction(&'source str, usize),
+    ///
+    /// If the argument count is `None` the arguments were packed into a
+    /// list due to argument unpacking and need to be spread at runtime.
+    /// This is the same for the other call instructions.
+    CallFunction(&'source str, Option<u16>),
 
     /// Calls a method
-    CallMethod(&'source str, usize),
+    CallMethod(&'source str, Option<u16>),
 
     /// Calls an object
-    CallObject(usize),
+    CallObject(Option<u16>),
 
     /// Duplicates the top item
     DupTop,
@@ -400,6 +423,23 @@ impl<'source> Instructions<'source> {
     pub fn is_empty(&self) -> bool {
         self.instructions.is_empty()
     }
+
+    /// Writes one line per instruction with its offset and source line.
+    pub(crate) fn write_listing(&self, out: &mut String) -> fmt::Result {
+        let mut last_line = None;
+        for (idx, instr) in self.instructions.iter().enumerate() {
+            ok!(write!(out, "{:>05x} | {:?}", idx, instr));
+            let line = self.get_line(idx);
+            if line != last_line {
+                if let Some(line) = line {
+                    ok!(write!(out, "  [line {}]", line));
+                }
+            }
+            last_line = line;
+            ok!(out.write_char('\n'));
+        }
+        Ok(())
+    }
 }
 
 #[cfg(feature = "internal_debug")]
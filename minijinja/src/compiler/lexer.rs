@@ -7,6 +7,17 @@ use crate::utils::{memchr, memstr, unescape};
 #[cfg(test)]
 use similar_asserts::assert_eq;
 
+/// Controls the whitespace and comment handling of the lexer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WhitespaceConfig {
+    /// Removes the first newline after a block tag.
+    pub trim_blocks: bool,
+    /// Strips spaces and tabs from the start of a line to a block tag.
+    pub lstrip_blocks: bool,
+    /// Produces comment tokens instead of skipping comments.
+    pub keep_comments: bool,
+}
+
 enum LexerState {
     Template,
     InVariable,
@@ -120,6 +131,15 @@ impl<'s> TokenizerState<'s> {
         skipped
     }
 
+    /// Skips over a single newline if one follows.
+    fn skip_newline(&mut self) {
+        if self.rest.starts_with("\r\n") {
+            self.advance(2);
+        } else if self.rest.starts_with('\n') {
+            self.advance(1);
+        }
+    }
+
     #[inline(always)]
     fn loc(&self) -> (usize, usize) {
         (self.current_line, self.current_col)
@@ -231,10 +251,26 @@ impl<'s> TokenizerState<'s> {
     }
 }
 
+/// Strips the whitespace in front of a block tag if it's the only thing on
+/// its line.  `at_line_start` indicates if the data starts at a new line.
+fn lstrip_block_lead(lead: &str, at_line_start: bool) -> &str {
+    let line_start = match lead.rfind('\n') {
+        Some(idx) => idx + 1,
+        None if at_line_start => 0,
+        None => return lead,
+    };
+    if lead[line_start..].bytes().all(|c| c == b' ' || c == b'\t') {
+        &lead[..line_start]
+    } else {
+        lead
+    }
+}
+
 /// Tokenizes without whitespace handling.
 fn tokenize_raw(
     input: &str,
     in_expr: bool,
+    config: WhitespaceConfig,
 ) -> impl Iterator<Item = Result<(Token<'_>, Span), Error>> {
     let mut state = TokenizerState {
         rest: input,
@@ -271,7 +307,7 @@ fn tokenize_raw(
                     Some("{%") => {
                         // raw blocks require some special handling.  If we are at the beginning of a raw
                         // block we want to skip everything until {% endraw %} completely ignoring iterior
-                        // syntax and emit the entire raw block as TemplateData.
+                        // syntax and emit the entire raw block as RawData.
                         if let Some(mut ptr) = skip_basic_tag(&state.rest[2..], "raw") {
                             ptr += 2;
                             while let Some(block) = memstr(&state.rest.as_bytes()[ptr..], b"{%") {
@@ -279,21 +315,29 @@ fn tokenize_raw(
                                 if let Some(endraw) = skip_basic_tag(&state.rest[ptr..], "endraw") {
                                     let result = &state.rest[..ptr + endraw];
                                     state.advance(ptr + endraw);
-                                    return Some(Ok((
-                                        Token::TemplateData(result),
-                                        state.span(old_loc),
-                                    )));
+                                    let span = state.span(old_loc);
+                                    if config.trim_blocks {
+                                        state.skip_newline();
+                                    }
+                                    return Some(Ok((Token::RawData(result), span)));
                                 }
                             }
                             return Some(Err(state.syntax_error("unexpected end of raw block")));
                         }
 
-                        let ws = if state.rest.as_bytes().get(2) == Some(&b'-') {
-                            state.advance(3);
-                            true
-                        } else {
-                            state.advance(2);
-                            false
+                        let ws = match state.rest.as_bytes().get(2) {
+                            Some(b'-') => {
+                                state.advance(3);
+                                true
+                            }
+                            Some(b'+') => {
+                                state.advance(3);
+                                false
+                            }
+                            _ => {
+                                state.advance(2);
+                                false
+                            }
                         };
 
                         state.stack.push(LexerState::InBlock);
@@ -301,7 +345,16 @@ fn tokenize_raw(
                     }
                     Some("{#") => {
                         if let Some(comment_end) = memstr(state.rest.as_bytes(), b"#}") {
+                            let comment = &state.rest[2..comment_end];
                             state.advance(comment_end + 2);
+                            // kept comments are output and left untrimmed
+                            if config.keep_comments {
+                                return Some(Ok((Token::Comment(comment), state.span(old_loc))));
+                            }
+                            if config.trim_blocks {
+                                state.skip_newline();
+                            }
+                            continue;
                         } else {
                             return Some(Err(state.syntax_error("unexpected end of comment")));
                         }
@@ -309,10 +362,25 @@ fn tokenize_raw(
                     _ => {}
                 }
 
-                let lead = match find_marker(state.rest) {
+                let at_line_start = {
+                    let offset = input.len() - state.rest.len();
+                    offset == 0 || input[..offset].ends_with('\n')
+                };
+                let mut lead = match find_marker(state.rest) {
                     Some(start) => state.advance(start),
                     None => state.advance(state.rest.len()),
                 };
+                let strip_marker = match state.rest.get(..2) {
+                    Some("{%") => true,
+                    Some("{#") => !config.keep_comments,
+                    _ => false,
+                };
+                if config.lstrip_blocks
+                    && strip_marker
+                    && state.rest.as_bytes().get(2) != Some(&b'+')
+                {
+                    lead = lstrip_block_lead(lead, at_line_start);
+                }
                 return Some(Ok((Token::TemplateData(lead), state.span(old_loc))));
             }
             Some(LexerState::InBlock | LexerState::InVariable) => {
@@ -341,10 +409,19 @@ fn tokenize_raw(
                         state.advance(3);
                         return Some(Ok((Token::BlockEnd(true), state.span(old_loc))));
                     }
+                    if let Some("+%}") = state.rest.get(..3) {
+                        state.stack.pop();
+                        state.advance(3);
+                        return Some(Ok((Token::BlockEnd(false), state.span(old_loc))));
+                    }
                     if let Some("%}") = state.rest.get(..2) {
                         state.stack.pop();
                         state.advance(2);
-                        return Some(Ok((Token::BlockEnd(false), state.span(old_loc))));
+                        let span = state.span(old_loc);
+                        if config.trim_blocks {
+                            state.skip_newline();
+                        }
+                        return Some(Ok((Token::BlockEnd(false), span)));
                     }
                 } else {
                     if let Some("-}}") = state.rest.get(..3) {
@@ -460,11 +537,21 @@ fn whitespace_filter<'a, I: Iterator<Item = Result<(Token<'a>, Span), Error>>>(
 }
 
 /// Tokenizes the source.
+#[cfg(feature = "unstable_machinery")]
 pub fn tokenize(
     input: &str,
     in_expr: bool,
 ) -> impl Iterator<Item = Result<(Token<'_>, Span), Error>> {
-    whitespace_filter(tokenize_raw(input, in_expr))
+    tokenize_with_config(input, in_expr, WhitespaceConfig::default())
+}
+
+/// Tokenizes the source with the given whitespace configuration.
+pub fn tokenize_with_config(
+    input: &str,
+    in_expr: bool,
+    config: WhitespaceConfig,
+) -> impl Iterator<Item = Result<(Token<'_>, Span), Error>> {
+    whitespace_filter(tokenize_raw(input, in_expr, config))
 }
 
 #[test]
@@ -486,14 +573,14 @@ fn test_is_basic_tag() {
 #[test]
 fn test_basic_identifiers() {
     fn assert_ident(s: &str) {
-        match tokenize_raw(s, true).next() {
+        match tokenize_raw(s, true, WhitespaceConfig::default()).next() {
             Some(Ok((Token::Ident(ident), _))) if ident == s => {}
             _ => panic!("did not get a matching token result: {:?}", s),
         }
     }
 
     fn assert_not_ident(s: &str) {
-        let res = tokenize_raw(s, true).collect::<Result<Vec<_>, _>>();
+        let res = tokenize_raw(s, true, WhitespaceConfig::default()).collect::<Result<Vec<_>, _>>();
         if let Ok(tokens) = res {
             if let &[(Token::Ident(_), _)] = &tokens[..] {
                 panic!("got a single ident for {:?}", s)
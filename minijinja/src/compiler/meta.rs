@@ -1,10 +1,13 @@
 use std::collections::HashSet;
 
 use crate::compiler::ast;
+use crate::compiler::tokens::Span;
 
 struct AssignmentTracker<'a> {
     out: HashSet<&'a str>,
     assigned: Vec<HashSet<&'a str>>,
+    filters: Vec<(&'a str, Span)>,
+    tests: Vec<(&'a str, Span)>,
 }
 
 impl<'a> AssignmentTracker<'a> {
@@ -16,73 +19,106 @@ impl<'a> AssignmentTracker<'a> {
         self.assigned.last_mut().unwrap().insert(name);
     }
 
+    #[cfg(feature = "macros")]
     fn push(&mut self) {
         self.assigned.push(Default::default());
     }
 
+    #[cfg(feature = "macros")]
     fn pop(&mut self) {
         self.assigned.pop();
     }
 }
 
-/// Finds all variables that need to be captured as closure for a macro.
-pub fn find_macro_closure<'a>(m: &ast::Macro<'a>) -> HashSet<&'a str> {
-    fn visit_expr_opt<'a>(expr: &Option<ast::Expr<'a>>, state: &mut AssignmentTracker<'a>) {
-        if let Some(expr) = expr {
-            visit_expr(expr, state);
-        }
+fn visit_expr_opt<'a>(expr: &Option<ast::Expr<'a>>, state: &mut AssignmentTracker<'a>) {
+    if let Some(expr) = expr {
+        visit_expr(expr, state);
     }
+}
 
-    fn visit_expr<'a>(expr: &ast::Expr<'a>, state: &mut AssignmentTracker<'a>) {
-        match expr {
-            ast::Expr::Var(var) => {
-                if !state.is_assigned(var.id) {
-                    state.out.insert(var.id);
-                    state.assign(var.id);
-                }
-            }
-            ast::Expr::Const(_) => {}
-            ast::Expr::UnaryOp(expr) => visit_expr(&expr.expr, state),
-            ast::Expr::BinOp(expr) => {
-                visit_expr(&expr.left, state);
-                visit_expr(&expr.right, state);
-            }
-            ast::Expr::IfExpr(expr) => {
-                visit_expr(&expr.test_expr, state);
-                visit_expr(&expr.true_expr, state);
-                visit_expr_opt(&expr.false_expr, state);
-            }
-            ast::Expr::Filter(expr) => {
-                visit_expr_opt(&expr.expr, state);
-                expr.args.iter().for_each(|x| visit_expr(x, state));
+fn visit_expr<'a>(expr: &ast::Expr<'a>, state: &mut AssignmentTracker<'a>) {
+    match expr {
+        ast::Expr::Var(var) => {
+            if !state.is_assigned(var.id) {
+                state.out.insert(var.id);
+                state.assign(var.id);
             }
-            ast::Expr::Test(expr) => {
-                visit_expr(&expr.expr, state);
-                expr.args.iter().for_each(|x| visit_expr(x, state));
-            }
-            ast::Expr::GetAttr(expr) => visit_expr(&expr.expr, state),
-            ast::Expr::GetItem(expr) => {
-                visit_expr(&expr.expr, state);
-                visit_expr(&expr.subscript_expr, state);
-            }
-            ast::Expr::Slice(slice) => {
-                visit_expr_opt(&slice.start, state);
-                visit_expr_opt(&slice.stop, state);
-                visit_expr_opt(&slice.step, state);
-            }
-            ast::Expr::Call(expr) => {
-                visit_expr(&expr.expr, state);
-                expr.args.iter().for_each(|x| visit_expr(x, state));
-            }
-            ast::Expr::List(expr) => expr.items.iter().for_each(|x| visit_expr(x, state)),
-            ast::Expr::Map(expr) => expr.keys.iter().zip(expr.values.iter()).for_each(|(k, v)| {
-                visit_expr(k, state);
-                visit_expr(v, state);
-            }),
-            ast::Expr::Kwargs(expr) => expr.pairs.iter().for_each(|(_, v)| visit_expr(v, state)),
         }
+        ast::Expr::Const(_) => {}
+        ast::Expr::UnaryOp(expr) => visit_expr(&expr.expr, state),
+        ast::Expr::BinOp(expr) => {
+            visit_expr(&expr.left, state);
+            visit_expr(&expr.right, state);
+        }
+        ast::Expr::IfExpr(expr) => {
+            visit_expr(&expr.test_expr, state);
+            visit_expr(&expr.true_expr, state);
+            visit_expr_opt(&expr.false_expr, state);
+        }
+        ast::Expr::Filter(expr) => {
+            state.filters.push((expr.name, expr.span()));
+            visit_expr_opt(&expr.expr, state);
+            expr.args.iter().for_each(|x| visit_expr(x, state));
+        }
+        ast::Expr::Test(expr) => {
+            state.tests.push((expr.name, expr.span()));
+            visit_expr(&expr.expr, state);
+            expr.args.iter().for_each(|x| visit_expr(x, state));
+        }
+        ast::Expr::GetAttr(expr) => visit_expr(&expr.expr, state),
+        ast::Expr::GetItem(expr) => {
+            visit_expr(&expr.expr, state);
+            visit_expr(&expr.subscript_expr, state);
+        }
+        ast::Expr::Slice(slice) => {
+            visit_expr_opt(&slice.start, state);
+            visit_expr_opt(&slice.stop, state);
+            visit_expr_opt(&slice.step, state);
+        }
+        ast::Expr::Call(expr) => {
+            visit_expr(&expr.expr, state);
+            expr.args.iter().for_each(|x| visit_expr(x, state));
+        }
+        ast::Expr::List(expr) => expr.items.iter().for_each(|x| visit_expr(x, state)),
+        ast::Expr::Map(expr) => expr
+            .keys
+            .iter()
+            .chain(expr.values.iter())
+            .for_each(|x| visit_expr(x, state)),
+        ast::Expr::Kwargs(expr) => expr.pairs.iter().for_each(|(_, v)| visit_expr(v, state)),
+        ast::Expr::Spread(expr) => visit_expr(&expr.expr, state),
+    }
+}
+
+/// Information about an expression gathered without evaluating it.
+pub struct ExprInfo<'a> {
+    /// The variables referenced by the expression.
+    pub undeclared: HashSet<&'a str>,
+    /// The filters applied by the expression.
+    pub filters: Vec<(&'a str, Span)>,
+    /// The tests performed by the expression.
+    pub tests: Vec<(&'a str, Span)>,
+}
+
+/// Finds the referenced variables, filters and tests of an expression.
+pub fn inspect_expr<'a>(expr: &ast::Expr<'a>) -> ExprInfo<'a> {
+    let mut state = AssignmentTracker {
+        out: HashSet::new(),
+        assigned: vec![Default::default()],
+        filters: Vec::new(),
+        tests: Vec::new(),
+    };
+    visit_expr(expr, &mut state);
+    ExprInfo {
+        undeclared: state.out,
+        filters: state.filters,
+        tests: state.tests,
     }
+}
 
+/// Finds all variables that need to be captured as closure for a macro.
+#[cfg(feature = "macros")]
+pub fn find_macro_closure<'a>(m: &ast::Macro<'a>) -> HashSet<&'a str> {
     fn assign_nested<'a>(expr: &ast::Expr<'a>, state: &mut AssignmentTracker<'a>) {
         match expr {
             ast::Expr::Var(var) => state.assign(var.id),
@@ -98,7 +134,7 @@ pub fn find_macro_closure<'a>(m: &ast::Macro<'a>) -> HashSet<&'a str> {
                 stmt.children.iter().for_each(|x| walk(x, state));
             }
             ast::Stmt::EmitExpr(expr) => visit_expr(&expr.expr, state),
-            ast::Stmt::EmitRaw(_) => {}
+            ast::Stmt::EmitRaw(_) | ast::Stmt::EmitComment(_) => {}
             ast::Stmt::ForLoop(stmt) => {
                 state.push();
                 state.assign("loop");
@@ -176,6 +212,8 @@ pub fn find_macro_closure<'a>(m: &ast::Macro<'a>) -> HashSet<&'a str> {
     let mut state = AssignmentTracker {
         out: HashSet::new(),
         assigned: vec![Default::default()],
+        filters: Vec::new(),
+        tests: Vec::new(),
     };
 
     m.args.iter().for_each(|arg| assign_nested(arg, &mut state));
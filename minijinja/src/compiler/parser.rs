@@ -1,12 +1,13 @@
 use std::fmt;
 
 use crate::compiler::ast::{self, Spanned};
-use crate::compiler::lexer::tokenize;
+use crate::compiler::lexer::{tokenize_with_config, WhitespaceConfig};
 use crate::compiler::tokens::{Span, Token};
 use crate::error::{Error, ErrorKind};
 use crate::value::Value;
 
-const MAX_RECURSION: usize = 150;
+/// The default for [`ParserConfig::max_nesting`].
+pub const DEFAULT_MAX_NESTING: usize = 150;
 const RESERVED_NAMES: [&str; 8] = [
     "true", "True", "false", "False", "none", "None", "loop", "self",
 ];
@@ -94,8 +95,9 @@ struct TokenStream<'a> {
 
 impl<'a> TokenStream<'a> {
     /// Tokenize a template
-    pub fn new(source: &'a str, in_expr: bool) -> TokenStream<'a> {
-        let mut iter = Box::new(tokenize(source, in_expr)) as Box<dyn Iterator<Item = _>>;
+    pub fn new(source: &'a str, in_expr: bool, config: WhitespaceConfig) -> TokenStream<'a> {
+        let mut iter =
+            Box::new(tokenize_with_config(source, in_expr, config)) as Box<dyn Iterator<Item = _>>;
         let current = iter.next();
         TokenStream {
             iter,
@@ -148,11 +150,33 @@ impl<'a> TokenStream<'a> {
     }
 }
 
+/// Controls the whitespace handling and the limits of the parser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserConfig {
+    /// The whitespace handling of the lexer.
+    pub whitespace: WhitespaceConfig,
+    /// The maximum nesting depth of tags and expressions.
+    pub max_nesting: usize,
+    /// The maximum size of the template source in bytes.
+    pub max_size: Option<usize>,
+}
+
+impl Default for ParserConfig {
+    fn default() -> ParserConfig {
+        ParserConfig {
+            whitespace: WhitespaceConfig::default(),
+            max_nesting: DEFAULT_MAX_NESTING,
+            max_size: None,
+        }
+    }
+}
+
 struct Parser<'a> {
     stream: TokenStream<'a>,
     #[allow(unused)]
     in_macro: bool,
     depth: usize,
+    max_depth: usize,
 }
 
 macro_rules! binop {
@@ -200,10 +224,13 @@ macro_rules! unaryop {
 macro_rules! with_recursion_guard {
     ($parser:expr, $expr:expr) => {{
         $parser.depth += 1;
-        if $parser.depth > MAX_RECURSION {
+        if $parser.depth > $parser.max_depth {
             return Err(Error::new(
-                ErrorKind::SyntaxError,
-                "template exceeds maximum recusion limits",
+                ErrorKind::LimitExceeded,
+                format!(
+                    "template exceeds maximum nesting depth of {}",
+                    $parser.max_depth
+                ),
             ));
         }
         let rv = $expr;
@@ -213,11 +240,12 @@ macro_rules! with_recursion_guard {
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(source: &'a str, in_expr: bool) -> Parser<'a> {
+    pub fn new(source: &'a str, in_expr: bool, config: ParserConfig) -> Parser<'a> {
         Parser {
-            stream: TokenStream::new(source, in_expr),
+            stream: TokenStream::new(source, in_expr, config.whitespace),
             in_macro: false,
             depth: 0,
+            max_depth: config.max_nesting,
         }
     }
 
@@ -395,7 +423,7 @@ impl<'a> Parser<'a> {
                     }
                 }
                 Some((Token::ParenOpen, _)) => {
-                    let args = ok!(self.parse_args());
+                    let args = ok!(self.parse_args(true));
                     expr = ast::Expr::Call(Spanned::new(
                         ast::Call { expr, args },
                         self.stream.expand_span(span),
@@ -417,7 +445,7 @@ impl<'a> Parser<'a> {
                     let (name, span) =
                         expect_token!(self, Token::Ident(name) => name, "identifier");
                     let args = if matches_token!(self, Token::ParenOpen) {
-                        ok!(self.parse_args())
+                        ok!(self.parse_args(false))
                     } else {
                         Vec::new()
                     };
@@ -436,7 +464,7 @@ impl<'a> Parser<'a> {
                     let (name, span) =
                         expect_token!(self, Token::Ident(name) => name, "identifier");
                     let args = if matches_token!(self, Token::ParenOpen) {
-                        ok!(self.parse_args())
+                        ok!(self.parse_args(false))
                     } else {
                         Vec::new()
                     };
@@ -460,22 +488,48 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
-    fn parse_args(&mut self) -> Result<Vec<ast::Expr<'a>>, Error> {
+    fn parse_args(&mut self, allow_spread: bool) -> Result<Vec<ast::Expr<'a>>, Error> {
         let mut args = Vec::new();
         let mut first_span = None;
         let mut kwargs = Vec::new();
+        let mut kwargs_spreads = Vec::new();
 
         expect_token!(self, Token::ParenOpen, "`(`");
         loop {
             if skip_token!(self, Token::ParenClose) {
                 break;
             }
-            if !args.is_empty() || !kwargs.is_empty() {
+            if !args.is_empty() || !kwargs.is_empty() || !kwargs_spreads.is_empty() {
                 expect_token!(self, Token::Comma, "`,`");
                 if skip_token!(self, Token::ParenClose) {
                     break;
                 }
             }
+
+            // argument unpacking
+            let span = self.stream.current_span();
+            if allow_spread && skip_token!(self, Token::Pow) {
+                let expr = ok!(self.parse_expr_noif());
+                kwargs_spreads.push(ast::Expr::Spread(Spanned::new(
+                    ast::Spread { expr, kwargs: true },
+                    self.stream.expand_span(span),
+                )));
+                continue;
+            } else if allow_spread && skip_token!(self, Token::Mul) {
+                if !kwargs.is_empty() || !kwargs_spreads.is_empty() {
+                    syntax_error!("argument unpacking after keyword arg");
+                }
+                let expr = ok!(self.parse_expr_noif());
+                args.push(ast::Expr::Spread(Spanned::new(
+                    ast::Spread {
+                        expr,
+                        kwargs: false,
+                    },
+                    self.stream.expand_span(span),
+                )));
+                continue;
+            }
+
             let expr = ok!(self.parse_expr());
 
             // keyword argument
@@ -486,7 +540,7 @@ impl<'a> Parser<'a> {
                     }
                     kwargs.push((var.id, ok!(self.parse_expr_noif())));
                 }
-                _ if !kwargs.is_empty() => {
+                _ if !kwargs.is_empty() || !kwargs_spreads.is_empty() => {
                     return Err(Error::new(
                         ErrorKind::SyntaxError,
                         "non-keyword arg after keyword arg",
@@ -504,6 +558,7 @@ impl<'a> Parser<'a> {
                 self.stream.expand_span(first_span.unwrap()),
             )));
         };
+        args.extend(kwargs_spreads);
 
         Ok(args)
     }
@@ -568,6 +623,15 @@ impl<'a> Parser<'a> {
                     break;
                 }
             }
+            let span = self.stream.current_span();
+            if skip_token!(self, Token::Pow) {
+                let expr = ok!(self.parse_expr_noif());
+                keys.push(ast::Expr::Spread(Spanned::new(
+                    ast::Spread { expr, kwargs: true },
+                    self.stream.expand_span(span),
+                )));
+                continue;
+            }
             keys.push(ok!(self.parse_expr()));
             expect_token!(self, Token::Colon, "`:`");
             values.push(ok!(self.parse_expr()));
@@ -793,6 +857,7 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_set(&mut self) -> Result<SetParseResult<'a>, Error> {
+        let span = self.stream.current_span();
         let (target, in_paren) = if skip_token!(self, Token::ParenOpen) {
             let assign = ok!(self.parse_assignment());
             expect_token!(self, Token::ParenClose, "`)`");
@@ -801,6 +866,30 @@ impl<'a> Parser<'a> {
             (ok!(self.parse_assign_name()), false)
         };
 
+        // an unparenthesized tuple target: {% set a, b = pair %}
+        let (target, in_paren) = if !in_paren && matches_token!(self, Token::Comma) {
+            let mut items = vec![target];
+            while skip_token!(self, Token::Comma) {
+                if matches_token!(self, Token::Assign) {
+                    break;
+                }
+                items.push(if skip_token!(self, Token::ParenOpen) {
+                    let rv = ok!(self.parse_assignment());
+                    expect_token!(self, Token::ParenClose, "`)`");
+                    rv
+                } else {
+                    ok!(self.parse_assign_name())
+                });
+            }
+            let list = ast::List { items };
+            (
+                ast::Expr::List(Spanned::new(list, self.stream.expand_span(span))),
+                true,
+            )
+        } else {
+            (target, in_paren)
+        };
+
         if !in_paren && matches_token!(self, Token::BlockEnd(..) | Token::Pipe) {
             let filter = if skip_token!(self, Token::Pipe) {
                 Some(ok!(self.parse_filter_chain()))
@@ -862,7 +951,7 @@ impl<'a> Parser<'a> {
             }
             let (name, span) = expect_token!(self, Token::Ident(name) => name, "identifier");
             let args = if matches_token!(self, Token::ParenOpen) {
-                ok!(self.parse_args())
+                ok!(self.parse_args(false))
             } else {
                 Vec::new()
             };
@@ -985,9 +1074,24 @@ impl<'a> Parser<'a> {
         let mut rv = Vec::new();
         while let Some((token, span)) = ok!(self.stream.next()) {
             match token {
-                Token::TemplateData(raw) => {
-                    rv.push(ast::Stmt::EmitRaw(Spanned::new(ast::EmitRaw { raw }, span)))
-                }
+                Token::TemplateData(raw) => rv.push(ast::Stmt::EmitRaw(Spanned::new(
+                    ast::EmitRaw {
+                        raw,
+                        verbatim: false,
+                    },
+                    span,
+                ))),
+                Token::RawData(raw) => rv.push(ast::Stmt::EmitRaw(Spanned::new(
+                    ast::EmitRaw {
+                        raw,
+                        verbatim: true,
+                    },
+                    span,
+                ))),
+                Token::Comment(comment) => rv.push(ast::Stmt::EmitComment(Spanned::new(
+                    ast::EmitComment { comment },
+                    span,
+                ))),
                 Token::VariableStart(_) => {
                     let expr = ok!(self.parse_expr());
                     rv.push(ast::Stmt::EmitExpr(Spanned::new(
@@ -1013,36 +1117,98 @@ impl<'a> Parser<'a> {
         Ok(rv)
     }
 
-    pub fn parse(&mut self) -> Result<ast::Stmt<'a>, Error> {
+    pub fn parse(&mut self, source: &'a str) -> Result<(ast::Stmt<'a>, Option<&'a str>), Error> {
         let span = self.stream.last_span();
-        Ok(ast::Stmt::Template(Spanned::new(
-            ast::Template {
-                children: ok!(self.subparse(&|_| false)),
-            },
-            self.stream.expand_span(span),
-        )))
+        let mut children = ok!(self.subparse(&|_| false));
+        let mut span = self.stream.expand_span(span);
+
+        // the trailing newline is chopped off unless whitespace control or
+        // trim_blocks already removed it.  If it survived it's the end of the
+        // last piece of template data.
+        let mut trailing_newline = None;
+        if let Some(ast::Stmt::EmitRaw(raw)) = children.last() {
+            let ends_source = raw.raw.as_ptr() as usize + raw.raw.len()
+                == source.as_ptr() as usize + source.len();
+            let data = raw.raw;
+            let chopped = data.strip_suffix('\n').unwrap_or(data);
+            let chopped = chopped.strip_suffix('\r').unwrap_or(chopped);
+            if ends_source && chopped.len() < data.len() {
+                trailing_newline = Some(&data[chopped.len()..]);
+                let raw_span = raw.span();
+                let end = end_of_data(raw_span, chopped);
+                span.end_line = end.end_line;
+                span.end_col = end.end_col;
+                children.pop();
+                if !chopped.is_empty() {
+                    children.push(ast::Stmt::EmitRaw(Spanned::new(
+                        ast::EmitRaw {
+                            raw: chopped,
+                            verbatim: false,
+                        },
+                        end,
+                    )));
+                }
+            }
+        }
+
+        Ok((
+            ast::Stmt::Template(Spanned::new(ast::Template { children }, span)),
+            trailing_newline,
+        ))
+    }
+}
+
+/// Returns the span of the given template data starting at `span`.
+fn end_of_data(span: Span, data: &str) -> Span {
+    let mut rv = span;
+    match data.rfind('\n') {
+        Some(idx) => {
+            rv.end_line = span.start_line + data.matches('\n').count();
+            rv.end_col = data[idx + 1..].chars().count();
+        }
+        None => {
+            rv.end_line = span.start_line;
+            rv.end_col = span.start_col + data.chars().count();
+        }
     }
+    rv
 }
 
 /// Parses a template
+#[cfg(feature = "unstable_machinery")]
 pub fn parse<'source, 'name>(
     source: &'source str,
     filename: &'name str,
 ) -> Result<ast::Stmt<'source>, Error> {
-    // we want to chop off a single newline at the end.  This means that a template
-    // by default does not end in a newline which is a useful property to allow
-    // inline templates to work.  If someone wants a trailing newline the expectation
-    // is that the user adds it themselves for achieve consistency.
-    let mut source = source;
-    if source.ends_with('\n') {
-        source = &source[..source.len() - 1];
-    }
-    if source.ends_with('\r') {
-        source = &source[..source.len() - 1];
+    parse_template(source, filename, ParserConfig::default()).map(|(stmt, _)| stmt)
+}
+
+/// Parses a template and returns the trailing newline that was chopped off.
+///
+/// A single newline at the end of the template is removed so that a template
+/// by default does not end in a newline, which is a useful property for
+/// inline templates.  Whether it is emitted after all is decided at runtime
+/// with [`set_keep_trailing_newline`](crate::Environment::set_keep_trailing_newline).
+/// If the newline is already removed by whitespace control or `trim_blocks`
+/// there is no trailing newline.
+pub(crate) fn parse_template<'source>(
+    source: &'source str,
+    filename: &str,
+    config: ParserConfig,
+) -> Result<(ast::Stmt<'source>, Option<&'source str>), Error> {
+    if let Some(max_size) = config.max_size {
+        if source.len() > max_size {
+            let mut err = Error::new(
+                ErrorKind::LimitExceeded,
+                format!("template exceeds maximum size of {} bytes", max_size),
+            );
+            err.set_filename_and_line(filename, 1);
+            return Err(err);
+        }
     }
 
-    let mut parser = Parser::new(source, false);
-    parser.parse().map_err(|mut err| {
+    let mut parser = Parser::new(source, false, config);
+    parser.parse(source).map_err(|mut err| {
         if err.line().is_none() {
             err.set_filename_and_span(filename, parser.stream.last_span())
         }
@@ -1052,7 +1218,7 @@ pub fn parse<'source, 'name>(
 
 /// Parses an expression
 pub fn parse_expr(source: &str) -> Result<ast::Expr<'_>, Error> {
-    let mut parser = Parser::new(source, true);
+    let mut parser = Parser::new(source, true, ParserConfig::default());
     parser.parse_expr().map_err(|mut err| {
         if err.line().is_none() {
             err.set_filename_and_span("<expression>", parser.stream.last_span())
@@ -6,6 +6,10 @@ use std::fmt;
 pub enum Token<'a> {
     /// Raw template data.
     TemplateData(&'a str),
+    /// A `{% raw %}` block that is emitted unchanged.
+    RawData(&'a str),
+    /// The contents of a comment (only produced if comments are kept).
+    Comment(&'a str),
     /// Variable block start (with or without whitespace removal).
     VariableStart(bool),
     /// Variable block start (with or without whitespace removal).
@@ -80,6 +84,8 @@ impl<'a> fmt::Display for Token<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Token::TemplateData(_) => write!(f, "template-data"),
+            Token::RawData(_) => write!(f, "raw-data"),
+            Token::Comment(_) => write!(f, "comment"),
             Token::VariableStart(_) => write!(f, "start of variable block"),
             Token::VariableEnd(_) => write!(f, "end of variable block"),
             Token::BlockStart(_) => write!(f, "start of block"),
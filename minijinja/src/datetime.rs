@@ -0,0 +1,396 @@
+//! Minimal date and time support for the `now()` functions and the
+//! `datetimeformat` filter.
+//!
+//! This deliberately does not pull in a date library.  Times are kept as
+//! seconds since the unix epoch and are converted into calendar dates with
+//! the proleptic gregorian calendar.  Time zones are limited to UTC and
+//! fixed offsets.
+use std::fmt::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, ErrorKind};
+use crate::value::{Object, Value};
+
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Month names in the style of the Associated Press.
+const AP_MONTHS: [&str; 12] = [
+    "Jan.", "Feb.", "March", "April", "May", "June", "July", "Aug.", "Sept.", "Oct.", "Nov.",
+    "Dec.",
+];
+
+/// A point in time as returned by `now()` and `utcnow()`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DateTime {
+    secs: i64,
+    nanos: u32,
+}
+
+/// The calendar fields of a [`DateTime`] in a specific time zone.
+struct Fields {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    nanos: u32,
+    weekday: usize,
+    ordinal: u32,
+    offset: i32,
+}
+
+impl DateTime {
+    pub fn from_system_time(time: SystemTime) -> DateTime {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(dur) => DateTime {
+                secs: dur.as_secs() as i64,
+                nanos: dur.subsec_nanos(),
+            },
+            Err(err) => {
+                let dur = err.duration();
+                let mut secs = -(dur.as_secs() as i64);
+                let mut nanos = dur.subsec_nanos();
+                if nanos > 0 {
+                    secs -= 1;
+                    nanos = 1_000_000_000 - nanos;
+                }
+                DateTime { secs, nanos }
+            }
+        }
+    }
+
+    pub fn from_timestamp(ts: f64) -> Result<DateTime, Error> {
+        // keep well within the range where days can be computed without overflow
+        if !ts.is_finite() || ts.abs() > 1e15 {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "timestamp out of range",
+            ));
+        }
+        let secs = ts.floor();
+        Ok(DateTime {
+            secs: secs as i64,
+            nanos: ((ts - secs) * 1e9) as u32,
+        })
+    }
+
+    pub fn timestamp(&self) -> f64 {
+        self.secs as f64 + self.nanos as f64 / 1e9
+    }
+
+    fn fields(&self, offset: i32) -> Fields {
+        let secs = self.secs + offset as i64;
+        let days = secs.div_euclid(86400);
+        let time = secs.rem_euclid(86400) as u32;
+        let (year, month, day) = civil_from_days(days);
+        Fields {
+            year,
+            month,
+            day,
+            hour: time / 3600,
+            minute: time / 60 % 60,
+            second: time % 60,
+            nanos: self.nanos,
+            weekday: (days + 3).rem_euclid(7) as usize,
+            ordinal: (days - days_from_civil(year, 1, 1)) as u32 + 1,
+            offset,
+        }
+    }
+
+    /// Formats the time with a `strftime` style format string.
+    pub fn format(&self, format: &str, offset: i32) -> Result<String, Error> {
+        let f = self.fields(offset);
+        let mut rv = String::with_capacity(format.len() * 2);
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                rv.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => write!(rv, "{:04}", f.year),
+                Some('y') => write!(rv, "{:02}", f.year.rem_euclid(100)),
+                Some('m') => write!(rv, "{:02}", f.month),
+                Some('d') => write!(rv, "{:02}", f.day),
+                Some('e') => write!(rv, "{:2}", f.day),
+                Some('j') => write!(rv, "{:03}", f.ordinal),
+                Some('H') => write!(rv, "{:02}", f.hour),
+                Some('I') => write!(rv, "{:02}", (f.hour + 11) % 12 + 1),
+                Some('M') => write!(rv, "{:02}", f.minute),
+                Some('S') => write!(rv, "{:02}", f.second),
+                Some('f') => write!(rv, "{:06}", f.nanos / 1000),
+                Some('p') => rv.write_str(if f.hour < 12 { "AM" } else { "PM" }),
+                Some('a') => rv.write_str(&WEEKDAYS[f.weekday][..3]),
+                Some('A') => rv.write_str(WEEKDAYS[f.weekday]),
+                Some('u') => write!(rv, "{}", f.weekday + 1),
+                Some('b') => rv.write_str(&MONTHS[f.month as usize - 1][..3]),
+                Some('B') => rv.write_str(MONTHS[f.month as usize - 1]),
+                Some('F') => write!(rv, "{:04}-{:02}-{:02}", f.year, f.month, f.day),
+                Some('T') => write!(rv, "{:02}:{:02}:{:02}", f.hour, f.minute, f.second),
+                Some('s') => write!(rv, "{}", self.secs),
+                Some('z') => write_offset(&mut rv, f.offset, ""),
+                Some('Z') => {
+                    if f.offset == 0 {
+                        rv.write_str("UTC")
+                    } else {
+                        rv.push_str("UTC");
+                        write_offset(&mut rv, f.offset, ":")
+                    }
+                }
+                Some('%') => rv.write_char('%'),
+                Some(other) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        format!("unsupported format specifier %{}", other),
+                    ))
+                }
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        "format string ends with a lone %",
+                    ))
+                }
+            }
+            .unwrap();
+        }
+        Ok(rv)
+    }
+
+    /// Formats the time with a Django style format string.
+    ///
+    /// Unknown characters are copied verbatim and a backslash escapes the
+    /// following character.
+    pub fn format_django(&self, format: &str, offset: i32) -> String {
+        let f = self.fields(offset);
+        let mut rv = String::with_capacity(format.len() * 2);
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                'd' => write!(rv, "{:02}", f.day),
+                'j' => write!(rv, "{}", f.day),
+                'S' => rv.write_str(match f.day {
+                    1 | 21 | 31 => "st",
+                    2 | 22 => "nd",
+                    3 | 23 => "rd",
+                    _ => "th",
+                }),
+                'D' => rv.write_str(&WEEKDAYS[f.weekday][..3]),
+                'l' => rv.write_str(WEEKDAYS[f.weekday]),
+                'w' => write!(rv, "{}", (f.weekday + 1) % 7),
+                'z' => write!(rv, "{}", f.ordinal),
+                'm' => write!(rv, "{:02}", f.month),
+                'n' => write!(rv, "{}", f.month),
+                'M' => rv.write_str(&MONTHS[f.month as usize - 1][..3]),
+                'b' => rv.write_str(&MONTHS[f.month as usize - 1][..3].to_ascii_lowercase()),
+                'F' => rv.write_str(MONTHS[f.month as usize - 1]),
+                'N' => rv.write_str(AP_MONTHS[f.month as usize - 1]),
+                'y' => write!(rv, "{:02}", f.year.rem_euclid(100)),
+                'Y' => write!(rv, "{:04}", f.year),
+                'H' => write!(rv, "{:02}", f.hour),
+                'G' => write!(rv, "{}", f.hour),
+                'h' => write!(rv, "{:02}", (f.hour + 11) % 12 + 1),
+                'g' => write!(rv, "{}", (f.hour + 11) % 12 + 1),
+                'i' => write!(rv, "{:02}", f.minute),
+                's' => write!(rv, "{:02}", f.second),
+                'u' => write!(rv, "{:06}", f.nanos / 1000),
+                'A' => rv.write_str(if f.hour < 12 { "AM" } else { "PM" }),
+                'a' => rv.write_str(if f.hour < 12 { "a.m." } else { "p.m." }),
+                'U' => write!(rv, "{}", self.secs),
+                'O' => write_offset(&mut rv, f.offset, ""),
+                '\\' => match chars.next() {
+                    Some(escaped) => rv.write_char(escaped),
+                    None => Ok(()),
+                },
+                other => rv.write_char(other),
+            }
+            .unwrap();
+        }
+        rv
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let d = self.fields(0);
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            d.year, d.month, d.day, d.hour, d.minute, d.second
+        )
+    }
+}
+
+impl Object for DateTime {
+    fn get_attr(&self, name: &str) -> Option<Value> {
+        let f = self.fields(0);
+        Some(match name {
+            "year" => Value::from(f.year),
+            "month" => Value::from(f.month),
+            "day" => Value::from(f.day),
+            "hour" => Value::from(f.hour),
+            "minute" => Value::from(f.minute),
+            "second" => Value::from(f.second),
+            "microsecond" => Value::from(f.nanos / 1000),
+            "weekday" => Value::from(f.weekday),
+            "timestamp" => Value::from(self.timestamp()),
+            _ => return None,
+        })
+    }
+
+    fn attributes(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        Box::new(
+            [
+                "year",
+                "month",
+                "day",
+                "hour",
+                "minute",
+                "second",
+                "microsecond",
+                "weekday",
+                "timestamp",
+            ]
+            .into_iter(),
+        )
+    }
+}
+
+/// Parses a time zone into an offset from UTC in seconds.
+///
+/// Accepts `UTC` (or `Z`) and fixed offsets in the forms `+HH`, `+HHMM`
+/// and `+HH:MM`.
+pub fn parse_tz(tz: &str) -> Result<i32, Error> {
+    fn parse(tz: &str) -> Option<i32> {
+        if tz.eq_ignore_ascii_case("utc") || tz == "Z" {
+            return Some(0);
+        }
+        let sign = match tz.as_bytes().first()? {
+            b'+' => 1,
+            b'-' => -1,
+            _ => return None,
+        };
+        let rest = tz[1..].replace(':', "");
+        if !rest.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let (hours, minutes) = match rest.len() {
+            2 => (rest.parse::<i32>().ok()?, 0),
+            4 => (rest[..2].parse().ok()?, rest[2..].parse().ok()?),
+            _ => return None,
+        };
+        if hours > 23 || minutes > 59 {
+            return None;
+        }
+        Some(sign * (hours * 3600 + minutes * 60))
+    }
+
+    parse(tz).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!(
+                "unknown time zone {:?}, only UTC and fixed offsets are supported",
+                tz
+            ),
+        )
+    })
+}
+
+fn write_offset(rv: &mut String, offset: i32, sep: &str) -> fmt::Result {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs();
+    write!(
+        rv,
+        "{}{:02}{}{:02}",
+        sign,
+        offset / 3600,
+        sep,
+        offset / 60 % 60
+    )
+}
+
+/// Converts days since the unix epoch into a (year, month, day) tuple.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Converts a date into days since the unix epoch.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[test]
+fn test_civil_roundtrip() {
+    for days in [-719_468, -1, 0, 1, 59, 60, 11_016, 19_358, 2_932_896] {
+        let (y, m, d) = civil_from_days(days);
+        assert_eq!(days_from_civil(y, m, d), days);
+    }
+    assert_eq!(civil_from_days(0), (1970, 1, 1));
+    assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+    assert_eq!(civil_from_days(-1), (1969, 12, 31));
+}
+
+#[test]
+fn test_format_django() {
+    // 2000-02-29 13:05:09 UTC, a tuesday
+    let dt = DateTime::from_timestamp(951_829_509.0).unwrap();
+    assert_eq!(dt.format_django("N j, Y", 0), "Feb. 29, 2000");
+    assert_eq!(
+        dt.format_django("D d/m/y H:i:s", 0),
+        "Tue 29/02/00 13:05:09"
+    );
+    assert_eq!(
+        dt.format_django("l, F jS g:i a w z", 0),
+        "Tuesday, February 29th 1:05 p.m. 2 60"
+    );
+    assert_eq!(dt.format_django("G:i O", 7200), "15:05 +0200");
+    assert_eq!(dt.format_django("\\Y\\m Y", 0), "Ym 2000");
+}
+
+#[test]
+fn test_parse_tz() {
+    assert_eq!(parse_tz("UTC").unwrap(), 0);
+    assert_eq!(parse_tz("+02:00").unwrap(), 7200);
+    assert_eq!(parse_tz("-0530").unwrap(), -19800);
+    assert_eq!(parse_tz("+01").unwrap(), 3600);
+    assert!(parse_tz("Europe/Vienna").is_err());
+    assert!(parse_tz("+25:00").is_err());
+}
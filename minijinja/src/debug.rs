@@ -13,6 +13,53 @@ pub(crate) struct DebugInfo {
     pub(crate) referenced_locals: BTreeMap<String, Value>,
 }
 
+/// A single frame in the template trace of an error.
+///
+/// Frames are recorded when an error propagates out of an include or a macro
+/// call and point to the line that descended into the next template.
+pub(crate) struct TraceFrame {
+    pub(crate) name: String,
+    pub(crate) line: usize,
+    pub(crate) source_line: Option<String>,
+}
+
+impl TraceFrame {
+    pub fn new(name: &str, line: usize, source: Option<&str>) -> TraceFrame {
+        TraceFrame {
+            name: name.to_string(),
+            line,
+            source_line: source
+                .and_then(|source| source.lines().nth(line.saturating_sub(1)))
+                .map(|line| line.trim().to_string()),
+        }
+    }
+}
+
+pub(super) fn render_trace(f: &mut fmt::Formatter, trace: &[TraceFrame]) -> fmt::Result {
+    ok!(write!(f, "\ntemplate trace (most recent call last):"));
+    let mut idx = 0;
+    while let Some(frame) = trace.get(idx) {
+        ok!(write!(f, "\n  {}:{}", frame.name, frame.line));
+        if let Some(ref source_line) = frame.source_line {
+            ok!(write!(f, "\n    {}", source_line));
+        }
+        // collapse recursion into a single frame like Python does
+        let repeated = trace[idx + 1..]
+            .iter()
+            .take_while(|x| x.name == frame.name && x.line == frame.line)
+            .count();
+        if repeated > 0 {
+            ok!(write!(
+                f,
+                "\n  [previous frame repeated {} more times]",
+                repeated
+            ));
+        }
+        idx += repeated + 1;
+    }
+    Ok(())
+}
+
 struct VarPrinter<'x>(&'x BTreeMap<String, Value>);
 
 impl<'x> fmt::Debug for VarPrinter<'x> {
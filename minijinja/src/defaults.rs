@@ -6,7 +6,7 @@ use crate::filters::{self, BoxedFilter};
 use crate::output::Output;
 use crate::tests::{self, BoxedTest};
 use crate::utils::{write_escaped, AutoEscape};
-use crate::value::Value;
+use crate::value::{Value, ValueRepr};
 use crate::vm::State;
 
 pub(crate) fn no_auto_escape(_: &str) -> AutoEscape {
@@ -30,6 +30,18 @@ pub fn default_auto_escape_callback(name: &str) -> AutoEscape {
     }
 }
 
+/// Auto escaping for Django compatible environments.
+///
+/// Like Django this escapes HTML for all templates, except for the formats
+/// that [`default_auto_escape_callback`] escapes differently.
+#[cfg(feature = "builtins")]
+pub(crate) fn django_auto_escape_callback(name: &str) -> AutoEscape {
+    match default_auto_escape_callback(name) {
+        AutoEscape::None => AutoEscape::Html,
+        other => other,
+    }
+}
+
 /// The default formatter.
 ///
 /// This formatter takes a value and directly writes it into the output format
@@ -44,7 +56,19 @@ pub fn default_auto_escape_callback(name: &str) -> AutoEscape {
 )]
 /// * [`None`](AutoEscape::None): no escaping
 /// * [`Custom(..)`](AutoEscape::Custom): results in an error
+///
+/// Floats are rounded to the number of digits configured with
+/// [`set_float_precision`](crate::Environment::set_float_precision).  This is
+/// the only place where the precision is applied.
 pub fn escape_formatter(out: &mut Output, state: &State, value: &Value) -> Result<(), Error> {
+    if let (ValueRepr::F64(val), Some(digits)) = (&value.0, state.env().float_precision()) {
+        if val.is_finite() {
+            let rounded = format!("{:.*e}", digits.max(1) - 1, val).parse::<f64>();
+            if let Ok(rounded) = rounded {
+                return write_escaped(out, state.auto_escape(), &Value::from(rounded));
+            }
+        }
+    }
     write_escaped(out, state.auto_escape(), value)
 }
 
@@ -55,44 +79,112 @@ pub(crate) fn get_builtin_filters() -> BTreeMap<Cow<'static, str>, filters::Boxe
     rv.insert("e".into(), BoxedFilter::new(filters::escape));
     #[cfg(feature = "builtins")]
     {
+        rv.insert("forceescape".into(), BoxedFilter::new(filters::forceescape));
+        rv.insert("escape_js".into(), BoxedFilter::new(filters::escape_js));
         rv.insert("lower".into(), BoxedFilter::new(filters::lower));
+        rv.insert("nl2br".into(), BoxedFilter::new(filters::nl2br));
         rv.insert("upper".into(), BoxedFilter::new(filters::upper));
         rv.insert("title".into(), BoxedFilter::new(filters::title));
+        rv.insert("capitalize".into(), BoxedFilter::new(filters::capitalize));
         rv.insert("replace".into(), BoxedFilter::new(filters::replace));
         rv.insert("length".into(), BoxedFilter::new(filters::length));
         rv.insert("count".into(), BoxedFilter::new(filters::length));
+        rv.insert("wordcount".into(), BoxedFilter::new(filters::wordcount));
+        rv.insert(
+            "truncatewords".into(),
+            BoxedFilter::new(filters::truncatewords),
+        );
+        rv.insert("ljust".into(), BoxedFilter::new(filters::ljust));
+        rv.insert("rjust".into(), BoxedFilter::new(filters::rjust));
+        rv.insert("center".into(), BoxedFilter::new(filters::center));
+        rv.insert("indent".into(), BoxedFilter::new(filters::indent));
+        rv.insert("pluralize".into(), BoxedFilter::new(filters::pluralize));
         rv.insert("dictsort".into(), BoxedFilter::new(filters::dictsort));
+        rv.insert("sort".into(), BoxedFilter::new_lazy(filters::sort));
+        rv.insert("unique".into(), BoxedFilter::new_lazy(filters::unique));
         rv.insert("items".into(), BoxedFilter::new(filters::items));
         rv.insert("reverse".into(), BoxedFilter::new(filters::reverse));
         rv.insert("trim".into(), BoxedFilter::new(filters::trim));
-        rv.insert("join".into(), BoxedFilter::new(filters::join));
+        rv.insert("join".into(), BoxedFilter::new_lazy(filters::join));
         rv.insert("default".into(), BoxedFilter::new(filters::default));
         rv.insert("round".into(), BoxedFilter::new(filters::round));
+        rv.insert(
+            "format_number".into(),
+            BoxedFilter::new(filters::format_number),
+        );
         rv.insert("abs".into(), BoxedFilter::new(filters::abs));
         rv.insert("first".into(), BoxedFilter::new(filters::first));
         rv.insert("last".into(), BoxedFilter::new(filters::last));
         rv.insert("d".into(), BoxedFilter::new(filters::default));
         rv.insert("list".into(), BoxedFilter::new(filters::list));
+        rv.insert("string".into(), BoxedFilter::new(filters::string));
         rv.insert("bool".into(), BoxedFilter::new(filters::bool));
         rv.insert("batch".into(), BoxedFilter::new(filters::batch));
         rv.insert("slice".into(), BoxedFilter::new(filters::slice));
+        rv.insert("attr".into(), BoxedFilter::new(filters::attr));
+        rv.insert("get_path".into(), BoxedFilter::new(filters::get_path));
+        rv.insert("map".into(), BoxedFilter::new_lazy(filters::map));
+        rv.insert(
+            "selectattr".into(),
+            BoxedFilter::new_lazy(filters::selectattr),
+        );
+        rv.insert("groupby".into(), BoxedFilter::new_lazy(filters::groupby));
+        rv.insert("shuffle".into(), BoxedFilter::new(filters::shuffle));
+        rv.insert("random".into(), BoxedFilter::new(filters::random));
         #[cfg(feature = "json")]
         {
             rv.insert("tojson".into(), BoxedFilter::new(filters::tojson));
+            rv.insert("fromjson".into(), BoxedFilter::new(filters::fromjson));
+        }
+        #[cfg(feature = "yaml")]
+        {
+            rv.insert("toyaml".into(), BoxedFilter::new(filters::toyaml));
+        }
+        #[cfg(feature = "regex")]
+        {
+            rv.insert(
+                "regex_replace".into(),
+                BoxedFilter::new(filters::regex_replace),
+            );
         }
         #[cfg(feature = "urlencode")]
         {
             rv.insert("urlencode".into(), BoxedFilter::new(filters::urlencode));
         }
+        #[cfg(feature = "time")]
+        {
+            rv.insert(
+                "datetimeformat".into(),
+                BoxedFilter::new(filters::datetimeformat),
+            );
+        }
     }
 
     rv
 }
 
+/// Returns the additional filters of Django compatible environments.
+#[cfg(feature = "builtins")]
+pub(crate) fn get_django_filters() -> BTreeMap<Cow<'static, str>, filters::BoxedFilter> {
+    let mut rv = BTreeMap::new();
+    rv.insert(
+        "default_if_none".into(),
+        BoxedFilter::new(filters::default_if_none),
+    );
+    rv.insert("yesno".into(), BoxedFilter::new(filters::yesno));
+    rv.insert("linebreaks".into(), BoxedFilter::new(filters::linebreaks));
+    #[cfg(feature = "time")]
+    {
+        rv.insert("date".into(), BoxedFilter::new(filters::date));
+    }
+    rv
+}
+
 pub(crate) fn get_builtin_tests() -> BTreeMap<Cow<'static, str>, BoxedTest> {
     let mut rv = BTreeMap::new();
     rv.insert("undefined".into(), BoxedTest::new(tests::is_undefined));
     rv.insert("defined".into(), BoxedTest::new(tests::is_defined));
+    rv.insert("none".into(), BoxedTest::new(tests::is_none));
     #[cfg(feature = "builtins")]
     {
         rv.insert("odd".into(), BoxedTest::new(tests::is_odd));
@@ -101,11 +193,17 @@ pub(crate) fn get_builtin_tests() -> BTreeMap<Cow<'static, str>, BoxedTest> {
         rv.insert("string".into(), BoxedTest::new(tests::is_string));
         rv.insert("sequence".into(), BoxedTest::new(tests::is_sequence));
         rv.insert("mapping".into(), BoxedTest::new(tests::is_mapping));
+        rv.insert("callable".into(), BoxedTest::new(tests::is_callable));
         rv.insert(
             "startingwith".into(),
             BoxedTest::new(tests::is_startingwith),
         );
         rv.insert("endingwith".into(), BoxedTest::new(tests::is_endingwith));
+        #[cfg(feature = "regex")]
+        {
+            rv.insert("matching".into(), BoxedTest::new(tests::is_matching));
+            rv.insert("search".into(), BoxedTest::new(tests::is_search));
+        }
     }
     rv
 }
@@ -128,6 +226,22 @@ pub(crate) fn get_globals() -> BTreeMap<Cow<'static, str>, Value> {
             "debug".into(),
             BoxedFunction::new(functions::debug).to_value(),
         );
+        rv.insert(
+            "cycler".into(),
+            BoxedFunction::new(functions::cycler).to_value(),
+        );
+        rv.insert(
+            "joiner".into(),
+            BoxedFunction::new(functions::joiner).to_value(),
+        );
+        #[cfg(feature = "time")]
+        {
+            rv.insert("now".into(), BoxedFunction::new(functions::now).to_value());
+            rv.insert(
+                "utcnow".into(),
+                BoxedFunction::new(functions::utcnow).to_value(),
+            );
+        }
     }
 
     rv
@@ -1,17 +1,21 @@
 use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::fmt;
+use std::mem;
 use std::sync::Arc;
 
 use serde::Serialize;
 
 use crate::compiler::codegen::CodeGenerator;
+use crate::compiler::lexer::WhitespaceConfig;
+use crate::compiler::meta::inspect_expr;
 use crate::compiler::parser::parse_expr;
-use crate::error::{attach_basic_debug_info, Error};
+use crate::compiler::parser::ParserConfig;
+use crate::error::{attach_basic_debug_info, Error, ErrorKind};
 use crate::expression::Expression;
 use crate::output::Output;
 use crate::template::{CompiledTemplate, Template};
-use crate::utils::{AutoEscape, BTreeMapKeysDebug};
+use crate::utils::{AutoEscape, BTreeMapKeysDebug, UndefinedBehavior};
 use crate::value::{FunctionArgs, FunctionResult, Value};
 use crate::vm::{State, Vm};
 use crate::{defaults, filters, functions, tests};
@@ -37,6 +41,12 @@ impl<'source> fmt::Debug for Source<'source> {
 
 type AutoEscapeFunc = dyn Fn(&str) -> AutoEscape + Sync + Send;
 type FormatterFunc = dyn Fn(&mut Output, &State, &Value) -> Result<(), Error> + Sync + Send;
+type PathJoinFunc = dyn Fn(&str, &str) -> String + Sync + Send;
+type UndefinedVariableFunc = dyn Fn(&State, &str) -> Option<Value> + Sync + Send;
+type UnknownMethodFunc =
+    dyn Fn(&State, &Value, &str, &[Value]) -> Option<Result<Value, Error>> + Sync + Send;
+#[cfg(feature = "time")]
+type ClockFunc = dyn Fn() -> std::time::SystemTime + Sync + Send;
 
 /// An abstraction that holds the engine configuration.
 ///
@@ -63,11 +73,27 @@ environment it's recommended to turn on the `source` feature and to use the
 #[derive(Clone)]
 pub struct Environment<'source> {
     templates: Source<'source>,
+    // borrowed templates that failed to compile after a config change
+    broken_templates: BTreeMap<&'source str, &'source str>,
     filters: BTreeMap<Cow<'source, str>, filters::BoxedFilter>,
     tests: BTreeMap<Cow<'source, str>, tests::BoxedTest>,
     pub(crate) globals: BTreeMap<Cow<'source, str>, Value>,
     default_auto_escape: Arc<AutoEscapeFunc>,
+    text_mode_auto_escape: Option<Arc<AutoEscapeFunc>>,
     formatter: Arc<FormatterFunc>,
+    path_join: Option<Arc<PathJoinFunc>>,
+    undefined_variable: Option<Arc<UndefinedVariableFunc>>,
+    unknown_method: Option<Arc<UnknownMethodFunc>>,
+    keep_trailing_newline: bool,
+    trim_output_whitespace: bool,
+    parser_config: ParserConfig,
+    undefined_behavior: UndefinedBehavior,
+    random_seed: Option<u64>,
+    float_precision: Option<usize>,
+    max_output_size: Option<usize>,
+    comment_delimiters: (Cow<'source, str>, Cow<'source, str>),
+    #[cfg(feature = "time")]
+    clock: Option<Arc<ClockFunc>>,
     #[cfg(feature = "debug")]
     debug: bool,
 }
@@ -99,16 +125,58 @@ impl<'source> Environment<'source> {
     pub fn new() -> Environment<'source> {
         Environment {
             templates: Source::Borrowed(Default::default()),
+            broken_templates: BTreeMap::new(),
             filters: defaults::get_builtin_filters(),
             tests: defaults::get_builtin_tests(),
             globals: defaults::get_globals(),
             default_auto_escape: Arc::new(defaults::default_auto_escape_callback),
+            text_mode_auto_escape: None,
             formatter: Arc::new(defaults::escape_formatter),
+            path_join: None,
+            undefined_variable: None,
+            unknown_method: None,
+            keep_trailing_newline: false,
+            trim_output_whitespace: false,
+            parser_config: ParserConfig::default(),
+            undefined_behavior: UndefinedBehavior::default(),
+            random_seed: None,
+            float_precision: None,
+            max_output_size: None,
+            comment_delimiters: (Cow::Borrowed(""), Cow::Borrowed("")),
+            #[cfg(feature = "time")]
+            clock: None,
             #[cfg(feature = "debug")]
             debug: cfg!(debug_assertions),
         }
     }
 
+    /// Creates an environment that eases migrating from Django templates.
+    ///
+    /// This is an environment with the same defaults as [`new`](Self::new)
+    /// and additionally the Django style filters `default_if_none`, `yesno`,
+    /// `linebreaks` and (with the `time` feature) `date`.  The `pluralize`
+    /// filter is available in all environments.  Like in Django, HTML auto
+    /// escaping is enabled for all templates other than the formats the
+    /// default auto escape callback escapes differently.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// let env = Environment::new_django_compat();
+    /// let rv = env.render_str(
+    ///     "{{ subscribed|yesno('on,off') }} {{ name|default_if_none('<none>') }}",
+    ///     context!(subscribed => true, name => ()),
+    /// ).unwrap();
+    /// assert_eq!(rv, "on &lt;none&gt;");
+    /// ```
+    #[cfg(feature = "builtins")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn new_django_compat() -> Environment<'source> {
+        let mut rv = Environment::new();
+        rv.filters.extend(defaults::get_django_filters());
+        rv.set_auto_escape_callback(defaults::django_auto_escape_callback);
+        rv
+    }
+
     /// Creates a completely empty environment.
     ///
     /// This environment has no filters, no templates, no globals and no default
@@ -116,11 +184,26 @@ impl<'source> Environment<'source> {
     pub fn empty() -> Environment<'source> {
         Environment {
             templates: Source::Borrowed(Default::default()),
+            broken_templates: BTreeMap::new(),
             filters: Default::default(),
             tests: Default::default(),
             globals: Default::default(),
             default_auto_escape: Arc::new(defaults::no_auto_escape),
+            text_mode_auto_escape: None,
             formatter: Arc::new(defaults::escape_formatter),
+            path_join: None,
+            undefined_variable: None,
+            unknown_method: None,
+            keep_trailing_newline: false,
+            trim_output_whitespace: false,
+            parser_config: ParserConfig::default(),
+            undefined_behavior: UndefinedBehavior::default(),
+            random_seed: None,
+            float_precision: None,
+            max_output_size: None,
+            comment_delimiters: (Cow::Borrowed(""), Cow::Borrowed("")),
+            #[cfg(feature = "time")]
+            clock: None,
             #[cfg(feature = "debug")]
             debug: cfg!(debug_assertions),
         }
@@ -142,8 +225,13 @@ impl<'source> Environment<'source> {
     pub fn add_template(&mut self, name: &'source str, source: &'source str) -> Result<(), Error> {
         match self.templates {
             Source::Borrowed(ref mut map) => {
-                let compiled_template = ok!(CompiledTemplate::from_name_and_source(name, source));
+                let compiled_template = ok!(CompiledTemplate::from_name_and_source_with_config(
+                    name,
+                    source,
+                    self.parser_config
+                ));
                 map.insert(name, Arc::new(compiled_template));
+                self.broken_templates.remove(name);
                 Ok(())
             }
             #[cfg(feature = "source")]
@@ -156,6 +244,7 @@ impl<'source> Environment<'source> {
         match self.templates {
             Source::Borrowed(ref mut map) => {
                 map.remove(name);
+                self.broken_templates.remove(name);
             }
             #[cfg(feature = "source")]
             Source::Owned(ref mut source) => {
@@ -170,6 +259,12 @@ impl<'source> Environment<'source> {
     /// [`add_template`](Environment::add_template) beforehand.  If the template was
     /// not loaded an error of kind `TemplateNotFound` is returned.
     ///
+    /// The returned [`Template`] is a cheap handle that borrows the compiled
+    /// template from the environment.  It can be copied, passed around and
+    /// rendered any number of times without looking it up again, but it cannot
+    /// outlive the environment and the environment cannot be modified while
+    /// the handle is alive.
+    ///
     /// ```
     /// # use minijinja::{Environment, context};
     /// let mut env = Environment::new();
@@ -180,6 +275,15 @@ impl<'source> Environment<'source> {
     pub fn get_template(&self, name: &str) -> Result<Template<'_>, Error> {
         let compiled = match &self.templates {
             Source::Borrowed(ref map) => {
+                if let Some((name, source)) = self.broken_templates.get_key_value(name) {
+                    // compile again to report why the template fails to
+                    // compile with the current config.
+                    ok!(CompiledTemplate::from_name_and_source_with_config(
+                        name,
+                        source,
+                        self.parser_config
+                    ));
+                }
                 ok!(map.get(name).ok_or_else(|| Error::new_not_found(name)))
             }
             #[cfg(feature = "source")]
@@ -211,7 +315,11 @@ impl<'source> Environment<'source> {
 
     fn _render_str(&self, source: &str, root: Value) -> Result<String, Error> {
         let name = "<string>";
-        let compiled = ok!(CompiledTemplate::from_name_and_source(name, source));
+        let compiled = ok!(CompiledTemplate::from_name_and_source_with_config(
+            name,
+            source,
+            self.parser_config
+        ));
         let mut rv = String::new();
         Vm::new(self)
             .eval(
@@ -249,6 +357,65 @@ impl<'source> Environment<'source> {
         F: Fn(&str) -> AutoEscape + 'static + Sync + Send,
     {
         self.default_auto_escape = Arc::new(f);
+        self.text_mode_auto_escape = None;
+    }
+
+    /// Sets the auto escaping for templates with a specific file extension.
+    ///
+    /// This is a convenient alternative to
+    /// [`set_auto_escape_callback`](Self::set_auto_escape_callback) when only
+    /// the behavior for some extensions should change.  Templates with the
+    /// given extension (with or without leading dot) use the given mode, all
+    /// other templates continue to use the previously configured callback.
+    /// For an environment created with [`Environment::new`] that is
+    /// [`default_auto_escape_callback`](defaults::default_auto_escape_callback)
+    /// which already covers `.html`, `.htm` and `.xml` amongst others.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, AutoEscape, context};
+    /// let mut env = Environment::new();
+    /// env.set_auto_escape_for_extension("svg", AutoEscape::Html);
+    /// env.set_auto_escape_for_extension(".xml", AutoEscape::None);
+    /// env.add_template("icon.svg", "{{ title }}").unwrap();
+    /// env.add_template("feed.xml", "{{ title }}").unwrap();
+    /// let ctx = context!(title => "<b>");
+    /// assert_eq!(env.get_template("icon.svg").unwrap().render(&ctx).unwrap(), "&lt;b&gt;");
+    /// assert_eq!(env.get_template("feed.xml").unwrap().render(&ctx).unwrap(), "<b>");
+    /// ```
+    pub fn set_auto_escape_for_extension(&mut self, extension: &str, auto_escape: AutoEscape) {
+        let extension = extension.trim_start_matches('.').to_string();
+        let fallback = self.default_auto_escape.clone();
+        self.text_mode_auto_escape = None;
+        self.default_auto_escape = Arc::new(move |name| match name.rsplit_once('.') {
+            Some((_, ext)) if ext == extension => auto_escape,
+            _ => fallback(name),
+        });
+    }
+
+    /// Sets a callback to join template paths.
+    ///
+    /// The callback is invoked with the name of the template doing the loading
+    /// and the name of the template to load whenever a template is referenced
+    /// by `{% include %}`, `{% extends %}` or `{% import %}`.  It returns the
+    /// name of the template to load which makes it possible to resolve
+    /// relative paths.  Without a callback names are used unchanged.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// # let mut env = Environment::new();
+    /// env.set_path_join_callback(|parent, name| {
+    ///     match (name.strip_prefix("./"), parent.rsplit_once('/')) {
+    ///         (Some(name), Some((dir, _))) => format!("{}/{}", dir, name),
+    ///         (Some(name), None) => name.to_string(),
+    ///         (None, _) => name.to_string(),
+    ///     }
+    /// });
+    /// ```
+    pub fn set_path_join_callback<F>(&mut self, f: F)
+    where
+        F: Fn(&str, &str) -> String + 'static + Sync + Send,
+    {
+        self.path_join = Some(Arc::new(f));
     }
 
     /// Sets a different formatter function.
@@ -315,6 +482,419 @@ impl<'source> Environment<'source> {
         self.debug
     }
 
+    /// Preserve the trailing newline when rendering templates.
+    ///
+    /// The default is `false`, which causes a single newline, if present, to be
+    /// stripped from the end of the template.
+    pub fn set_keep_trailing_newline(&mut self, yes: bool) {
+        self.keep_trailing_newline = yes;
+    }
+
+    pub(crate) fn keep_trailing_newline(&self) -> bool {
+        self.keep_trailing_newline
+    }
+
+    /// Collapse blank lines in the rendered output.
+    ///
+    /// The default is `false`.  When enabled, runs of lines that only contain
+    /// whitespace which are produced by the template source are collapsed into
+    /// a single empty line.  This is useful to get rid of the blank lines that
+    /// control blocks leave behind.  The contents of `{% raw %}` blocks and
+    /// values emitted with `{{ ... }}` are written unchanged.  This only
+    /// applies to the final output: output captured by `{% set %}` blocks,
+    /// macros or filter blocks is not collapsed.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// let mut env = Environment::new();
+    /// env.set_trim_output_whitespace(true);
+    /// let rv = env.render_str(
+    ///     "<ul>\n{% for x in seq %}\n{% if x is odd %}\n  <li>{{ x }}</li>\n{% endif %}\n{% endfor %}\n</ul>",
+    ///     context!(seq => [1, 2, 3]),
+    /// ).unwrap();
+    /// assert_eq!(rv, "<ul>\n\n  <li>1</li>\n\n  <li>3</li>\n\n</ul>");
+    /// ```
+    pub fn set_trim_output_whitespace(&mut self, yes: bool) {
+        self.trim_output_whitespace = yes;
+    }
+
+    pub(crate) fn trim_output_whitespace(&self) -> bool {
+        self.trim_output_whitespace
+    }
+
+    /// Changes the undefined behavior.
+    ///
+    /// This changes the runtime behavior of [`undefined`](Value::UNDEFINED)
+    /// values in the template engine.  For more information see
+    /// [`UndefinedBehavior`].  The default is
+    /// [`UndefinedBehavior::Lenient`].
+    ///
+    /// ```
+    /// # use minijinja::{Environment, UndefinedBehavior, context};
+    /// let mut env = Environment::new();
+    /// env.set_undefined_behavior(UndefinedBehavior::SemiStrict);
+    /// let ctx = context!(user => context!(name => "Peter"));
+    /// let rv = env.render_str("[{{ user.profile.bio }}]", ctx.clone()).unwrap();
+    /// assert_eq!(rv, "[]");
+    /// assert!(env.render_str("{{ missing.name }}", ctx).is_err());
+    /// ```
+    pub fn set_undefined_behavior(&mut self, behavior: UndefinedBehavior) {
+        self.undefined_behavior = behavior;
+    }
+
+    /// Returns the current undefined behavior.
+    pub fn undefined_behavior(&self) -> UndefinedBehavior {
+        self.undefined_behavior
+    }
+
+    /// Sets a callback that provides values for undefined variables.
+    ///
+    /// The callback is invoked with the name of a variable whenever looking
+    /// it up in a template would produce an undefined value.  If it returns
+    /// a value that value is used instead, if it returns `None` the variable
+    /// stays undefined and the [undefined behavior](Self::set_undefined_behavior)
+    /// applies as usual.  Note that variables the callback provides a value for
+    /// are also considered defined by the `defined` test.  Attribute and item
+    /// lookups on values are not affected.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, value::Value};
+    /// let mut env = Environment::new();
+    /// env.set_undefined_variable_callback(|_state, name| {
+    ///     Some(Value::from(format!("<missing {}>", name)))
+    /// });
+    /// let rv = env.render_str("Hello {{ user }}!", ()).unwrap();
+    /// assert_eq!(rv, "Hello <missing user>!");
+    /// ```
+    pub fn set_undefined_variable_callback<F>(&mut self, f: F)
+    where
+        F: Fn(&State, &str) -> Option<Value> + 'static + Sync + Send,
+    {
+        self.undefined_variable = Some(Arc::new(f));
+    }
+
+    /// Sets a callback that handles calls to unknown methods.
+    ///
+    /// The callback is invoked with the value, the name of the method and the
+    /// arguments whenever a template calls a method that the value does not
+    /// provide.  For [dynamic objects](crate::value::Object) this is the case
+    /// if [`call_method`](crate::value::Object::call_method) fails with
+    /// [`ErrorKind::UnknownMethod`](crate::ErrorKind::UnknownMethod).  If the
+    /// callback returns `None` the original error is reported.
+    ///
+    /// The callback is only consulted for that error kind.  An object that
+    /// reports unknown methods with a different kind (for instance
+    /// [`ErrorKind::InvalidOperation`](crate::ErrorKind::InvalidOperation))
+    /// fails with its own error and the callback is not invoked.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, value::Value};
+    /// let mut env = Environment::new();
+    /// env.set_unknown_method_callback(|_state, value, name, _args| {
+    ///     match name {
+    ///         "shout" => Some(Ok(Value::from(value.to_string().to_uppercase()))),
+    ///         _ => None,
+    ///     }
+    /// });
+    /// let rv = env.render_str("{{ 'hello'.shout() }}", ()).unwrap();
+    /// assert_eq!(rv, "HELLO");
+    /// ```
+    pub fn set_unknown_method_callback<F>(&mut self, f: F)
+    where
+        F: Fn(&State, &Value, &str, &[Value]) -> Option<Result<Value, Error>>
+            + 'static
+            + Sync
+            + Send,
+    {
+        self.unknown_method = Some(Arc::new(f));
+    }
+
+    /// Sets the seed for the `random` and `shuffle` filters.
+    ///
+    /// By default no seed is set and the filters produce random results.
+    /// Setting a seed makes them deterministic which is useful for tests.
+    /// A `seed` argument to the filters takes precedence over this seed.
+    pub fn set_random_seed(&mut self, seed: Option<u64>) {
+        self.random_seed = seed;
+    }
+
+    #[cfg(feature = "builtins")]
+    pub(crate) fn random_seed(&self) -> Option<u64> {
+        self.random_seed
+    }
+
+    /// Sets the number of significant digits for printing floats.
+    ///
+    /// By default floats are printed with the shortest representation that
+    /// reads back as the same number so `{{ 0.1 + 0.2 }}` prints
+    /// `0.30000000000000004`.  With a precision of 15 digits the same
+    /// expression prints `0.3`.  The values themselves retain their full
+    /// precision.
+    ///
+    /// The precision is applied by the
+    /// [`escape_formatter`](crate::escape_formatter) and as such only to floats
+    /// that are printed directly with `{{ ... }}`.  Floats converted to strings
+    /// within an expression (for instance with the `~` operator or the
+    /// `string` and `join` filters) and floats within sequences or maps are
+    /// not rounded.  A custom formatter installed with
+    /// [`set_formatter`](Self::set_formatter) does not round floats unless it
+    /// defers to the default formatter.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.set_float_precision(Some(15));
+    /// assert_eq!(env.render_str("{{ 0.1 + 0.2 }}", ()).unwrap(), "0.3");
+    /// ```
+    pub fn set_float_precision(&mut self, digits: Option<usize>) {
+        self.float_precision = digits;
+    }
+
+    pub(crate) fn float_precision(&self) -> Option<usize> {
+        self.float_precision
+    }
+
+    /// Sets the clock used by the `now()` and `utcnow()` functions.
+    ///
+    /// By default the system clock is used.  Overriding it is mostly useful
+    /// to render templates that show the current time reproducibly in tests.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// use std::time::{Duration, UNIX_EPOCH};
+    /// let mut env = Environment::new();
+    /// env.set_clock(|| UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+    /// assert_eq!(
+    ///     env.render_str("{{ now()|datetimeformat('%Y-%m-%d') }}", ()).unwrap(),
+    ///     "2001-09-09"
+    /// );
+    /// ```
+    #[cfg(feature = "time")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "time")))]
+    pub fn set_clock<F>(&mut self, f: F)
+    where
+        F: Fn() -> std::time::SystemTime + Send + Sync + 'static,
+    {
+        self.clock = Some(Arc::new(f));
+    }
+
+    #[cfg(all(feature = "builtins", feature = "time"))]
+    pub(crate) fn now(&self) -> std::time::SystemTime {
+        match self.clock {
+            Some(ref clock) => clock(),
+            None => std::time::SystemTime::now(),
+        }
+    }
+
+    /// Remove the first newline after a block.
+    ///
+    /// This is disabled by default.  Individual blocks can opt out of this by
+    /// ending with `+%}` instead of `%}`.  This also covers `{% block %}` tags,
+    /// so that with template inheritance an empty block overridden by a child
+    /// template does not leave blank lines behind, the same as in Jinja2.
+    /// Changing this setting recompiles already loaded templates.
+    pub fn set_trim_blocks(&mut self, yes: bool) {
+        let config = WhitespaceConfig {
+            trim_blocks: yes,
+            ..self.parser_config.whitespace
+        };
+        self.set_whitespace_config(config);
+    }
+
+    /// Remove leading spaces and tabs from the start of a line to a block.
+    ///
+    /// This is disabled by default.  Individual blocks can opt out of this by
+    /// starting with `{%+` instead of `{%`.  Changing this setting recompiles
+    /// already loaded templates.
+    pub fn set_lstrip_blocks(&mut self, yes: bool) {
+        let config = WhitespaceConfig {
+            lstrip_blocks: yes,
+            ..self.parser_config.whitespace
+        };
+        self.set_whitespace_config(config);
+    }
+
+    /// Emits the contents of comments into the output.
+    ///
+    /// By default `{# ... #}` comments are stripped from templates.  When this
+    /// is enabled the text between the comment markers is written to the
+    /// output verbatim, wrapped in the delimiters configured with
+    /// [`set_comment_delimiters`](Self::set_comment_delimiters).  This is
+    /// useful for generating annotated files such as configuration files.
+    /// Changing this setting recompiles already loaded templates.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.set_keep_comments(true);
+    /// env.set_comment_delimiters("#", "");
+    /// assert_eq!(
+    ///     env.render_str("{# the port #}\nport = 80", ()).unwrap(),
+    ///     "# the port \nport = 80"
+    /// );
+    /// ```
+    pub fn set_keep_comments(&mut self, yes: bool) {
+        let config = WhitespaceConfig {
+            keep_comments: yes,
+            ..self.parser_config.whitespace
+        };
+        self.set_whitespace_config(config);
+    }
+
+    /// Sets the prefix and suffix written around kept comments.
+    ///
+    /// This only has an effect if comments are kept with
+    /// [`set_keep_comments`](Self::set_keep_comments).  By default both are
+    /// empty so only the comment contents are written.
+    pub fn set_comment_delimiters<P, S>(&mut self, prefix: P, suffix: S)
+    where
+        P: Into<Cow<'source, str>>,
+        S: Into<Cow<'source, str>>,
+    {
+        self.comment_delimiters = (prefix.into(), suffix.into());
+    }
+
+    pub(crate) fn comment_delimiters(&self) -> (&str, &str) {
+        (&self.comment_delimiters.0, &self.comment_delimiters.1)
+    }
+
+    /// Configures the environment for generating text formats.
+    ///
+    /// Text mode is a convenient preset for generating formats such as YAML,
+    /// JSON or plain text where stray whitespace from template tags is a
+    /// problem and HTML escaping is not wanted.  Enabling it turns on
+    /// [`set_trim_blocks`](Self::set_trim_blocks) and
+    /// [`set_lstrip_blocks`](Self::set_lstrip_blocks) and disables auto
+    /// escaping.  Disabling it turns the two whitespace settings off again and
+    /// restores the auto escape callback that was active before text mode was
+    /// enabled, unless the callback was changed in the meantime.  The trailing
+    /// newline behavior is left untouched and can be controlled with
+    /// [`set_keep_trailing_newline`](Self::set_keep_trailing_newline).
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// let mut env = Environment::new();
+    /// env.set_text_mode(true);
+    /// env.add_template("list.yml", "items:
+    ///   {% for x in items %}
+    ///   - {{ x }}
+    ///   {% endfor %}
+    /// ").unwrap();
+    /// let tmpl = env.get_template("list.yml").unwrap();
+    /// let rv = tmpl.render(context!(items => ["<a>", "b"])).unwrap();
+    /// assert_eq!(rv, "items:\n  - <a>\n  - b\n");
+    /// ```
+    pub fn set_text_mode(&mut self, yes: bool) {
+        self.set_whitespace_config(WhitespaceConfig {
+            trim_blocks: yes,
+            lstrip_blocks: yes,
+            ..self.parser_config.whitespace
+        });
+        if yes {
+            if self.text_mode_auto_escape.is_none() {
+                let old = mem::replace(
+                    &mut self.default_auto_escape,
+                    Arc::new(defaults::no_auto_escape),
+                );
+                self.text_mode_auto_escape = Some(old);
+            }
+        } else if let Some(old) = self.text_mode_auto_escape.take() {
+            self.default_auto_escape = old;
+        }
+    }
+
+    /// Sets the maximum nesting depth of templates.
+    ///
+    /// Templates with tags or expressions nested deeper than this fail to
+    /// load with an error of kind [`LimitExceeded`](ErrorKind::LimitExceeded).
+    /// The default is 150.  Because the parser is recursive, very high limits
+    /// can overflow the stack on pathological input.  The limit only applies
+    /// to templates loaded after the change.
+    pub fn set_max_template_nesting(&mut self, depth: usize) {
+        self.set_parser_config(ParserConfig {
+            max_nesting: depth,
+            ..self.parser_config
+        });
+    }
+
+    /// Sets the maximum size of template sources in bytes.
+    ///
+    /// Templates exceeding this size fail to load with an error of kind
+    /// [`LimitExceeded`](ErrorKind::LimitExceeded).  By default there is no
+    /// limit.  The limit only applies to templates loaded after the change.
+    pub fn set_max_template_size(&mut self, size: Option<usize>) {
+        self.set_parser_config(ParserConfig {
+            max_size: size,
+            ..self.parser_config
+        });
+    }
+
+    /// Sets the maximum size of the rendered output in bytes.
+    ///
+    /// Rendering a template that produces more output than this fails with
+    /// an error of kind [`LimitExceeded`](ErrorKind::LimitExceeded) once the
+    /// limit is reached.  This applies to [`render`](crate::Template::render)
+    /// as well as [`render_to_write`](crate::Template::render_to_write) and
+    /// also bounds the output captured by blocks such as `{% set %}` and
+    /// `{% filter %}` or rendered by macros.  By default there is no limit.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, ErrorKind};
+    /// let mut env = Environment::new();
+    /// env.set_max_output_size(Some(1024));
+    /// let err = env
+    ///     .render_str("{% for x in range(1000) %}{{ x }}{% endfor %}", ())
+    ///     .unwrap_err();
+    /// assert_eq!(err.kind(), ErrorKind::LimitExceeded);
+    /// ```
+    pub fn set_max_output_size(&mut self, size: Option<usize>) {
+        self.max_output_size = size;
+    }
+
+    pub(crate) fn max_output_size(&self) -> Option<usize> {
+        self.max_output_size
+    }
+
+    fn set_whitespace_config(&mut self, config: WhitespaceConfig) {
+        self.set_parser_config(ParserConfig {
+            whitespace: config,
+            ..self.parser_config
+        });
+    }
+
+    fn set_parser_config(&mut self, config: ParserConfig) {
+        let recompile = self.parser_config.whitespace != config.whitespace;
+        self.parser_config = config;
+        match self.templates {
+            Source::Borrowed(ref mut map) => {
+                if !recompile {
+                    return;
+                }
+                // templates failing to compile with the new config (for
+                // instance because of lowered limits) report the error when
+                // they are requested next.
+                let mut loaded = map
+                    .values()
+                    .map(|tmpl| (tmpl.instructions.name(), tmpl.instructions.source()))
+                    .collect::<Vec<_>>();
+                loaded.extend(mem::take(&mut self.broken_templates));
+                map.clear();
+                for (name, source) in loaded {
+                    match CompiledTemplate::from_name_and_source_with_config(name, source, config) {
+                        Ok(compiled) => {
+                            map.insert(name, Arc::new(compiled));
+                        }
+                        Err(_) => {
+                            self.broken_templates.insert(name, source);
+                        }
+                    }
+                }
+            }
+            #[cfg(feature = "source")]
+            Source::Owned(ref mut source) => source.set_parser_config(config),
+        }
+    }
+
     /// Sets the template source for the environment.
     ///
     /// This helps when working with dynamically loaded templates.  The
@@ -329,7 +909,8 @@ impl<'source> Environment<'source> {
     /// For more information see [`Source`](crate::source::Source).
     #[cfg(feature = "source")]
     #[cfg_attr(docsrs, doc(cfg(feature = "source")))]
-    pub fn set_source(&mut self, source: crate::source::Source) {
+    pub fn set_source(&mut self, mut source: crate::source::Source) {
+        source.set_parser_config(self.parser_config);
         self.templates = Source::Owned(source);
     }
 
@@ -349,16 +930,41 @@ impl<'source> Environment<'source> {
     /// receive the output.  This lets one use the expressions of the language
     /// be used as a minimal scripting language.  For more information and an
     /// example see [`Expression`].
+    ///
+    /// Filters and tests used by the expression must be registered on the
+    /// environment, otherwise an error of kind
+    /// [`UnknownFilter`](crate::ErrorKind::UnknownFilter) or
+    /// [`UnknownTest`](crate::ErrorKind::UnknownTest) is returned right away
+    /// rather than when the expression is evaluated.
     pub fn compile_expression(&self, expr: &'source str) -> Result<Expression<'_, 'source>, Error> {
         attach_basic_debug_info(self._compile_expression(expr), expr)
     }
 
     fn _compile_expression(&self, expr: &'source str) -> Result<Expression<'_, 'source>, Error> {
         let ast = ok!(parse_expr(expr));
+        let info = inspect_expr(&ast);
+        for &(name, span) in &info.filters {
+            if self.get_filter(name).is_none() {
+                let mut err = Error::new(
+                    ErrorKind::UnknownFilter,
+                    format!("filter {} is unknown", name),
+                );
+                err.set_filename_and_span("<expression>", span);
+                return Err(err);
+            }
+        }
+        for &(name, span) in &info.tests {
+            if self.get_test(name).is_none() {
+                let mut err =
+                    Error::new(ErrorKind::UnknownTest, format!("test {} is unknown", name));
+                err.set_filename_and_span("<expression>", span);
+                return Err(err);
+            }
+        }
         let mut gen = CodeGenerator::new("<expression>", expr);
         ok!(gen.compile_expr(&ast));
         let (instructions, _) = gen.finish();
-        Ok(Expression::new(self, instructions))
+        Ok(Expression::new(self, instructions, info.undeclared))
     }
 
     /// Adds a new filter function.
@@ -379,6 +985,35 @@ impl<'source> Environment<'source> {
             .insert(name.into(), filters::BoxedFilter::new(f));
     }
 
+    /// Registers an existing filter under an additional name.
+    ///
+    /// Both names refer to the same implementation.  This fails with
+    /// [`UnknownFilter`](crate::ErrorKind::UnknownFilter) if no filter named `target`
+    /// is registered.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.add_filter_alias("capitalise", "capitalize").unwrap();
+    /// assert_eq!(env.render_str("{{ 'hello'|capitalise }}", ()).unwrap(), "Hello");
+    /// ```
+    pub fn add_filter_alias<N>(&mut self, alias: N, target: &str) -> Result<(), Error>
+    where
+        N: Into<Cow<'source, str>>,
+    {
+        let filter = match self.filters.get(target) {
+            Some(filter) => filter.clone(),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::UnknownFilter,
+                    format!("filter {} is unknown", target),
+                ))
+            }
+        };
+        self.filters.insert(alias.into(), filter);
+        Ok(())
+    }
+
     /// Removes a filter by name.
     pub fn remove_filter(&mut self, name: &str) {
         self.filters.remove(name);
@@ -400,6 +1035,36 @@ impl<'source> Environment<'source> {
         self.tests.insert(name.into(), tests::BoxedTest::new(f));
     }
 
+    /// Registers an existing test under an additional name.
+    ///
+    /// Both names refer to the same implementation and can be used with `is`
+    /// as well as `is not`.  This fails with
+    /// [`UnknownTest`](crate::ErrorKind::UnknownTest) if no test named `target` is
+    /// registered.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.add_test_alias("nothing", "none").unwrap();
+    /// assert_eq!(env.render_str("{{ 42 is not nothing }}", ()).unwrap(), "true");
+    /// ```
+    pub fn add_test_alias<N>(&mut self, alias: N, target: &str) -> Result<(), Error>
+    where
+        N: Into<Cow<'source, str>>,
+    {
+        let test = match self.tests.get(target) {
+            Some(test) => test.clone(),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::UnknownTest,
+                    format!("test {} is unknown", target),
+                ))
+            }
+        };
+        self.tests.insert(alias.into(), test);
+        Ok(())
+    }
+
     /// Removes a test by name.
     pub fn remove_test(&mut self, name: &str) {
         self.tests.remove(name);
@@ -424,6 +1089,10 @@ impl<'source> Environment<'source> {
     }
 
     /// Adds a global variable.
+    ///
+    /// Globals are available in all templates.  They are consulted after
+    /// locals and the render context, so a variable of the same name in the
+    /// context shadows the global.
     pub fn add_global<N>(&mut self, name: N, value: Value)
     where
         N: Into<Cow<'source, str>>,
@@ -436,7 +1105,34 @@ impl<'source> Environment<'source> {
         self.globals.remove(name);
     }
 
-    /// Looks up a function.
+    /// Returns an iterator over the names of all registered filters.
+    ///
+    /// Aliases (like `e` for `escape`) are listed under each of their names.
+    pub fn filters(&self) -> impl Iterator<Item = &str> {
+        self.filters.keys().map(|x| x.as_ref())
+    }
+
+    /// Returns an iterator over the names of all registered tests.
+    pub fn tests(&self) -> impl Iterator<Item = &str> {
+        self.tests.keys().map(|x| x.as_ref())
+    }
+
+    /// Returns an iterator over the names of all registered global functions.
+    ///
+    /// This only includes globals registered as functions (for instance with
+    /// [`add_function`](Self::add_function)), not other global variables.
+    pub fn functions(&self) -> impl Iterator<Item = &str> {
+        self.globals
+            .iter()
+            .filter(|(_, value)| {
+                value
+                    .downcast_object_ref::<functions::BoxedFunction>()
+                    .is_some()
+            })
+            .map(|(name, _)| name.as_ref())
+    }
+
+    /// Looks up a global.
     pub(crate) fn get_global(&self, name: &str) -> Option<Value> {
         self.globals.get(name).cloned()
     }
@@ -451,6 +1147,35 @@ impl<'source> Environment<'source> {
         self.tests.get(name)
     }
 
+    /// Joins a template name with the name of the template referencing it.
+    #[cfg(feature = "multi-template")]
+    pub(crate) fn join_template_path<'s>(&self, name: &'s str, parent: &'s str) -> Cow<'s, str> {
+        match self.path_join {
+            Some(ref path_join) => Cow::Owned(path_join(parent, name)),
+            None => Cow::Borrowed(name),
+        }
+    }
+
+    /// Asks the undefined variable callback for a value of a variable.
+    pub(crate) fn resolve_undefined_variable(&self, state: &State, name: &str) -> Option<Value> {
+        self.undefined_variable
+            .as_ref()
+            .and_then(|callback| callback(state, name))
+    }
+
+    /// Asks the unknown method callback to handle a method call.
+    pub(crate) fn resolve_unknown_method(
+        &self,
+        state: &State,
+        value: &Value,
+        name: &str,
+        args: &[Value],
+    ) -> Option<Result<Value, Error>> {
+        self.unknown_method
+            .as_ref()
+            .and_then(|callback| callback(state, value, name, args))
+    }
+
     pub(crate) fn get_initial_auto_escape(&self, name: &str) -> AutoEscape {
         (self.default_auto_escape)(name)
     }
@@ -50,6 +50,10 @@ struct ErrorRepr {
     source: Option<Box<dyn std::error::Error + Send + Sync>>,
     #[cfg(feature = "debug")]
     debug_info: Option<crate::debug::DebugInfo>,
+    #[cfg(feature = "debug")]
+    trace: Vec<crate::debug::TraceFrame>,
+    #[cfg(feature = "debug")]
+    needs_caller_frame: bool,
 }
 
 impl fmt::Debug for Error {
@@ -108,6 +112,8 @@ pub enum ErrorKind {
     SyntaxError,
     /// A template was not found.
     TemplateNotFound,
+    /// A template exists but could not be loaded.
+    CannotLoad,
     /// Too many arguments were passed to a function.
     TooManyArguments,
     /// A expected argument was missing
@@ -118,6 +124,8 @@ pub enum ErrorKind {
     UnknownTest,
     /// A function is unknown
     UnknownFunction,
+    /// A method is unknown
+    UnknownMethod,
     /// A bad escape sequence in a string was encountered.
     BadEscape,
     /// An operation on an undefined value was attempted.
@@ -132,6 +140,11 @@ pub enum ErrorKind {
     CannotUnpack,
     /// Failed writing output.
     WriteFailure,
+    /// A template exceeds the configured size or nesting limits or its
+    /// rendered output exceeds the configured output size.
+    LimitExceeded,
+    /// Not able to deserialize a value into the requested type.
+    CannotDeserialize,
 }
 
 impl ErrorKind {
@@ -142,11 +155,13 @@ impl ErrorKind {
             ErrorKind::InvalidOperation => "invalid operation",
             ErrorKind::SyntaxError => "syntax error",
             ErrorKind::TemplateNotFound => "template not found",
+            ErrorKind::CannotLoad => "could not load template",
             ErrorKind::TooManyArguments => "too many arguments",
             ErrorKind::MissingArgument => "missing argument",
             ErrorKind::UnknownFilter => "unknown filter",
             ErrorKind::UnknownFunction => "unknown function",
             ErrorKind::UnknownTest => "unknown test",
+            ErrorKind::UnknownMethod => "unknown method",
             ErrorKind::BadEscape => "bad string escape",
             ErrorKind::UndefinedError => "undefined value",
             ErrorKind::BadSerialization => "could not serialize to internal format",
@@ -154,6 +169,8 @@ impl ErrorKind {
             ErrorKind::EvalBlock => "could not render block",
             ErrorKind::CannotUnpack => "cannot unpack",
             ErrorKind::WriteFailure => "failed to write output",
+            ErrorKind::LimitExceeded => "template exceeds limits",
+            ErrorKind::CannotDeserialize => "cannot deserialize",
         }
     }
 }
@@ -176,6 +193,9 @@ impl fmt::Display for Error {
         }
         #[cfg(feature = "debug")]
         {
+            if !self.repr.trace.is_empty() {
+                ok!(crate::debug::render_trace(f, &self.repr.trace));
+            }
             if f.alternate() {
                 if let Some(info) = self.debug_info() {
                     ok!(crate::debug::render_debug_info(
@@ -206,6 +226,10 @@ impl Error {
                 source: None,
                 #[cfg(feature = "debug")]
                 debug_info: None,
+                #[cfg(feature = "debug")]
+                trace: Vec::new(),
+                #[cfg(feature = "debug")]
+                needs_caller_frame: false,
             }),
         }
     }
@@ -235,6 +259,44 @@ impl Error {
         self
     }
 
+    /// Looks for an error of type `E` in the chain of sources.
+    ///
+    /// This walks the sources attached with [`with_source`](Self::with_source)
+    /// (and their own sources) and returns the first one that is of the
+    /// given type.  This can be used to recover a custom error that a filter,
+    /// test or function returned once rendering failed.
+    ///
+    /// ```rust
+    /// # use minijinja::{Environment, Error, ErrorKind};
+    /// #[derive(Debug)]
+    /// struct NotAllowed;
+    ///
+    /// impl std::fmt::Display for NotAllowed {
+    ///     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    ///         write!(f, "not allowed")
+    ///     }
+    /// }
+    ///
+    /// impl std::error::Error for NotAllowed {}
+    ///
+    /// let mut env = Environment::new();
+    /// env.add_function("check", || -> Result<(), Error> {
+    ///     Err(Error::new(ErrorKind::InvalidOperation, "check failed").with_source(NotAllowed))
+    /// });
+    /// let err = env.render_str("{{ check() }}", ()).unwrap_err();
+    /// assert!(err.downcast_source_ref::<NotAllowed>().is_some());
+    /// ```
+    pub fn downcast_source_ref<E: std::error::Error + 'static>(&self) -> Option<&E> {
+        let mut source = std::error::Error::source(self);
+        while let Some(err) = source {
+            if let Some(rv) = err.downcast_ref::<E>() {
+                return Some(rv);
+            }
+            source = err.source();
+        }
+        None
+    }
+
     /// Returns the error kind
     pub fn kind(&self) -> ErrorKind {
         self.repr.kind
@@ -276,6 +338,45 @@ impl Error {
     pub(crate) fn attach_debug_info(&mut self, value: crate::debug::DebugInfo) {
         self.repr.debug_info = Some(value);
     }
+
+    /// Records that this error is leaving a template through an include or
+    /// a macro call.
+    ///
+    /// The location of the error becomes the innermost frame of the template
+    /// trace and the frame of the caller is added with
+    /// [`add_caller_frame`](Self::add_caller_frame) once the error reaches
+    /// the calling template.
+    #[cfg(all(feature = "debug", any(feature = "macros", feature = "multi-template")))]
+    pub(crate) fn leave_template(&mut self) {
+        if self.repr.trace.is_empty() {
+            if let (Some(name), Some(line)) = (self.name(), self.line()) {
+                let frame = crate::debug::TraceFrame::new(
+                    name,
+                    line,
+                    self.debug_info().and_then(|x| x.source()),
+                );
+                self.repr.trace.push(frame);
+            }
+        }
+        self.repr.needs_caller_frame = !self.repr.trace.is_empty();
+    }
+
+    /// Moves the template trace of another error into this one.
+    #[cfg(all(feature = "debug", feature = "multi-template"))]
+    pub(crate) fn take_trace(&mut self, other: &mut Error) {
+        self.repr.trace = std::mem::take(&mut other.repr.trace);
+        self.repr.needs_caller_frame = std::mem::take(&mut other.repr.needs_caller_frame);
+    }
+
+    /// Adds the frame of the calling template if the error just left a template.
+    #[cfg(feature = "debug")]
+    pub(crate) fn add_caller_frame(&mut self, name: &str, line: usize, source: &str) {
+        if self.repr.needs_caller_frame {
+            let frame = crate::debug::TraceFrame::new(name, line, Some(source));
+            self.repr.trace.insert(0, frame);
+            self.repr.needs_caller_frame = false;
+        }
+    }
 }
 
 impl std::error::Error for Error {
@@ -296,6 +397,10 @@ impl From<ErrorKind> for Error {
                 source: None,
                 #[cfg(feature = "debug")]
                 debug_info: None,
+                #[cfg(feature = "debug")]
+                trace: Vec::new(),
+                #[cfg(feature = "debug")]
+                needs_caller_frame: false,
             }),
         }
     }
@@ -316,6 +421,16 @@ impl serde::ser::Error for Error {
     }
 }
 
+#[cfg(feature = "deserialization")]
+impl serde::de::Error for Error {
+    fn custom<T>(msg: T) -> Self
+    where
+        T: fmt::Display,
+    {
+        Error::new(ErrorKind::CannotDeserialize, msg.to_string())
+    }
+}
+
 pub fn attach_basic_debug_info<T>(rv: Result<T, Error>, source: &str) -> Result<T, Error> {
     #[cfg(feature = "debug")]
     {
@@ -1,6 +1,8 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
 
+#[cfg(feature = "deserialization")]
+use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 use crate::compiler::instructions::Instructions;
@@ -33,6 +35,7 @@ use crate::vm::Vm;
 pub struct Expression<'env, 'source> {
     env: &'env Environment<'source>,
     instructions: Instructions<'source>,
+    referenced_names: HashSet<&'source str>,
 }
 
 impl<'env, 'source> fmt::Debug for Expression<'env, 'source> {
@@ -47,8 +50,31 @@ impl<'env, 'source> Expression<'env, 'source> {
     pub(crate) fn new(
         env: &'env Environment<'source>,
         instructions: Instructions<'source>,
+        referenced_names: HashSet<&'source str>,
     ) -> Expression<'env, 'source> {
-        Expression { env, instructions }
+        Expression {
+            env,
+            instructions,
+            referenced_names,
+        }
+    }
+
+    /// Returns the names of all variables referenced by the expression.
+    ///
+    /// This includes globals such as functions.  It can be used to validate
+    /// an expression without evaluating it, for instance to point out names
+    /// that will not be provided by the context.
+    ///
+    /// ```rust
+    /// # use minijinja::Environment;
+    /// let env = Environment::new();
+    /// let expr = env.compile_expression("user.age > min_age").unwrap();
+    /// let mut names = expr.referenced_names().into_iter().collect::<Vec<_>>();
+    /// names.sort();
+    /// assert_eq!(names, ["min_age", "user"]);
+    /// ```
+    pub fn referenced_names(&self) -> HashSet<&'source str> {
+        self.referenced_names.clone()
     }
 
     /// Evaluates the expression with some context.
@@ -60,6 +86,26 @@ impl<'env, 'source> Expression<'env, 'source> {
         self._eval(Value::from_serializable(&ctx))
     }
 
+    /// Evaluates the expression and deserializes the result.
+    ///
+    /// This works like [`eval`](Self::eval) but converts the resulting value
+    /// into `T`.  If the value does not match the expected type an error of
+    /// kind [`CannotDeserialize`](crate::ErrorKind::CannotDeserialize) is
+    /// returned.
+    ///
+    /// ```rust
+    /// # use minijinja::{Environment, context};
+    /// let env = Environment::new();
+    /// let expr = env.compile_expression("price * quantity").unwrap();
+    /// let total: f64 = expr.eval_as(context!(price => 2.5, quantity => 4)).unwrap();
+    /// assert_eq!(total, 10.0);
+    /// ```
+    #[cfg(feature = "deserialization")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "deserialization")))]
+    pub fn eval_as<T: DeserializeOwned, S: Serialize>(&self, ctx: S) -> Result<T, Error> {
+        T::deserialize(ok!(self._eval(Value::from_serializable(&ctx))))
+    }
+
     fn _eval(&self, root: Value) -> Result<Value, Error> {
         Ok(ok!(Vm::new(self.env).eval(
             &self.instructions,
@@ -72,6 +72,12 @@
 //! this module.  Note though that these functions are not to be
 //! called from Rust code as their exact interface (arguments and return types)
 //! might change from one MiniJinja version to another.
+//!
+//! The string filters [`upper`], [`lower`], [`title`], [`capitalize`] and
+//! [`trim`] cannot introduce characters that need escaping and keep values
+//! that are marked as [`safe`] safe.  [`replace`] escapes its arguments when
+//! applied to a safe value so that the result stays safe as well.  Other
+//! filters produce unsafe strings which are escaped again on output.
 use std::sync::Arc;
 
 use crate::error::Error;
@@ -83,7 +89,12 @@ use crate::{AutoEscape, Output};
 type FilterFunc = dyn Fn(&State, &[Value]) -> Result<Value, Error> + Sync + Send + 'static;
 
 #[derive(Clone)]
-pub(crate) struct BoxedFilter(Arc<FilterFunc>);
+pub(crate) struct BoxedFilter {
+    func: Arc<FilterFunc>,
+    // set for built-in filters that consume lazy sequences themselves
+    #[cfg(feature = "builtins")]
+    lazy: bool,
+}
 
 /// A utility trait that represents filters.
 ///
@@ -159,6 +170,34 @@ pub(crate) struct BoxedFilter(Arc<FilterFunc>);
 /// ```jinja
 /// {{ "|".join(1, 2, 3) }} -> 1|2|3
 /// ```
+///
+/// # Errors
+///
+/// Filters that validate their input can return an [`Error`] with a fitting
+/// [`ErrorKind`](crate::ErrorKind) and message.  The error is propagated out of
+/// the render call with the name of the template and the line of the filter
+/// call attached.
+///
+/// ```
+/// # use minijinja::Environment;
+/// # let mut env = Environment::new();
+/// use minijinja::{Error, ErrorKind};
+///
+/// fn percent(value: f64) -> Result<String, Error> {
+///     if !(0.0..=1.0).contains(&value) {
+///         return Err(Error::new(
+///             ErrorKind::InvalidOperation,
+///             format!("{} is not a fraction", value),
+///         ));
+///     }
+///     Ok(format!("{}%", value * 100.0))
+/// }
+///
+/// env.add_filter("percent", percent);
+/// let err = env.render_str("\n{{ 1.5|percent }}", ()).unwrap_err();
+/// assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+/// assert_eq!(err.line(), Some(2));
+/// ```
 pub trait Filter<Rv, Args>: Send + Sync + 'static {
     /// Applies a filter to value with the given arguments.
     ///
@@ -199,15 +238,61 @@ impl BoxedFilter {
         Rv: FunctionResult,
         Args: for<'a> FunctionArgs<'a>,
     {
-        BoxedFilter(Arc::new(move |state, args| -> Result<Value, Error> {
-            f.apply_to(ok!(Args::from_values(Some(state), args)), SealedMarker)
-                .into_result()
-        }))
+        BoxedFilter {
+            func: Arc::new(move |state, args| -> Result<Value, Error> {
+                f.apply_to(ok!(Args::from_values(Some(state), args)), SealedMarker)
+                    .into_result()
+            }),
+            #[cfg(feature = "builtins")]
+            lazy: false,
+        }
+    }
+
+    /// Creates a new boxed filter that accepts lazy sequences.
+    #[cfg(feature = "builtins")]
+    pub fn new_lazy<F, Rv, Args>(f: F) -> BoxedFilter
+    where
+        F: Filter<Rv, Args> + for<'a> Filter<Rv, <Args as FunctionArgs<'a>>::Output>,
+        Rv: FunctionResult,
+        Args: for<'a> FunctionArgs<'a>,
+    {
+        BoxedFilter {
+            lazy: true,
+            ..BoxedFilter::new(f)
+        }
     }
 
     /// Applies the filter to a value and argument.
     pub fn apply_to(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
-        (self.0)(state, args)
+        let rv = ok!(self.apply_chained(state, args));
+        #[cfg(feature = "builtins")]
+        {
+            builtins::LazySeq::collect(state, rv)
+        }
+        #[cfg(not(feature = "builtins"))]
+        {
+            Ok(rv)
+        }
+    }
+
+    /// Applies the filter to a value that is passed on to another filter.
+    ///
+    /// Unlike [`apply_to`](Self::apply_to) this hands lazy sequences of the
+    /// built-in sequence filters to the next filter as they are so that a
+    /// chain of filters only collects the items once.
+    pub fn apply_chained(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
+        #[cfg(feature = "builtins")]
+        {
+            if let Some(first) = args
+                .first()
+                .filter(|x| !self.lazy && builtins::LazySeq::is_lazy(x))
+            {
+                let mut args = args.to_vec();
+                args[0] = ok!(builtins::LazySeq::collect(state, first.clone()));
+                return (self.func)(state, &args);
+            }
+        }
+        (self.func)(state, args)
     }
 }
 
@@ -250,22 +335,189 @@ mod builtins {
     use super::*;
 
     use crate::error::ErrorKind;
-    use crate::value::{ValueKind, ValueRepr};
+    use crate::utils::HtmlEscape;
+    use crate::value::{Object, Rest, ValueKind, ValueRepr};
     use std::borrow::Cow;
-    use std::fmt::Write;
+    use std::cmp::Ordering;
+    use std::fmt::{self, Write};
     use std::mem;
 
     #[cfg(test)]
     use similar_asserts::assert_eq;
 
+    /// Upper bound for strings that filters build from sizes passed by the
+    /// template if the environment has no maximum output size configured.
+    const MAX_GENERATED_SIZE: usize = 64 * 1024 * 1024;
+
+    /// Validates the size of a string a filter is about to build.
+    ///
+    /// `None` stands for a size computation that overflowed.
+    fn check_generated_size(state: &State, size: Option<usize>) -> Result<usize, Error> {
+        let limit = state.env().max_output_size().unwrap_or(MAX_GENERATED_SIZE);
+        match size {
+            Some(size) if size <= limit => Ok(size),
+            Some(size) => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!(
+                    "result of {} bytes exceeds the limit of {} bytes",
+                    size, limit
+                ),
+            )),
+            None => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "result is too large",
+            )),
+        }
+    }
+
+    /// HTML escapes a string, even if it was marked as safe.
+    ///
+    /// Unlike [`escape`] this filter ignores the safe marker of a value and
+    /// always applies HTML escaping.  This is useful to re-escape content which
+    /// was previously trusted.  The return value is marked as safe.
+    ///
+    /// ```jinja
+    /// {{ "<b>bold</b>"|safe|forceescape }}
+    ///   -> &lt;b&gt;bold&lt;&#x2f;b&gt;
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn forceescape(v: Cow<'_, str>) -> Value {
+        Value::from_safe_string(HtmlEscape(&v).to_string())
+    }
+
+    /// Escapes a string for use in a JavaScript string literal.
+    ///
+    /// Quotes, backslashes and control characters are replaced with their
+    /// escape sequences and `<`, `>` and `&` become unicode escapes so that
+    /// the value cannot end a surrounding `<script>` tag.  Unlike
+    /// `tojson` this escapes the contents of a string rather than producing
+    /// a JSON document, so the quotes have to be written in the template.
+    /// The return value is marked as safe.
+    ///
+    /// ```jinja
+    /// <script>
+    ///   const greeting = "{{ greeting|escape_js }}";
+    /// </script>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn escape_js(v: Cow<'_, str>) -> Value {
+        let mut rv = String::with_capacity(v.len());
+        for c in v.chars() {
+            match c {
+                '\\' => rv.push_str("\\\\"),
+                '"' => rv.push_str("\\\""),
+                '\'' => rv.push_str("\\'"),
+                '`' => rv.push_str("\\`"),
+                '\n' => rv.push_str("\\n"),
+                '\r' => rv.push_str("\\r"),
+                '\t' => rv.push_str("\\t"),
+                c if c.is_control() || matches!(c, '<' | '>' | '&' | '\u{2028}' | '\u{2029}') => {
+                    write!(rv, "\\u{:04X}", c as u32).unwrap();
+                }
+                c => rv.push(c),
+            }
+        }
+        Value::from_safe_string(rv)
+    }
+
+    /// Converts newlines into HTML line breaks.
+    ///
+    /// Every newline (`\n` or `\r\n`) is replaced with `<br>` followed by a
+    /// newline.  Unless the value is already marked as safe it's HTML escaped
+    /// first if HTML auto escaping is enabled.  The result is marked as safe so
+    /// that the inserted tags are not escaped again.  With other auto escape
+    /// formats such as JSON the result is escaped like any other string.
+    ///
+    /// ```jinja
+    /// <p>{{ comment.text|nl2br }}</p>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn nl2br(state: &State, v: Value) -> Value {
+        map_insert_html(state, &v, |text| {
+            let mut rv = String::with_capacity(text.len());
+            for (idx, line) in text.split('\n').enumerate() {
+                if idx > 0 {
+                    rv.push_str("<br>\n");
+                }
+                rv.push_str(line.strip_suffix('\r').unwrap_or(line));
+            }
+            rv
+        })
+    }
+
+    /// Applies a string transformation that inserts HTML tags.
+    ///
+    /// With HTML auto escaping the input is escaped first unless it's safe
+    /// and the result is marked as safe.  Without auto escaping the result is
+    /// marked as safe as well.  For other formats the result is left to the
+    /// regular output escaping.
+    fn map_insert_html<F: FnOnce(&str) -> String>(state: &State, v: &Value, f: F) -> Value {
+        let text = v.to_cowstr();
+        match state.auto_escape() {
+            AutoEscape::Html if !v.is_safe() => {
+                Value::from_safe_string(f(&HtmlEscape(&text).to_string()))
+            }
+            AutoEscape::Html | AutoEscape::None => Value::from_safe_string(f(&text)),
+            _ => Value::from(f(&text)),
+        }
+    }
+
+    /// Converts text into HTML paragraphs and line breaks.
+    ///
+    /// Paragraphs are separated by blank lines and wrapped in `<p>` tags,
+    /// single newlines within a paragraph are replaced with `<br>`.  Escaping
+    /// works like in [`nl2br`].  This filter is registered by
+    /// [`Environment::new_django_compat`](crate::Environment::new_django_compat).
+    ///
+    /// ```jinja
+    /// <div class="comment">{{ comment.text|linebreaks }}</div>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn linebreaks(state: &State, v: Value) -> Value {
+        map_insert_html(state, &v, |text| {
+            let mut paragraphs = Vec::new();
+            let mut current = Vec::new();
+            for line in text.split('\n') {
+                let line = line.strip_suffix('\r').unwrap_or(line);
+                if line.is_empty() {
+                    if !current.is_empty() {
+                        paragraphs.push(format!("<p>{}</p>", current.join("<br>")));
+                        current.clear();
+                    }
+                } else {
+                    current.push(line);
+                }
+            }
+            if !current.is_empty() {
+                paragraphs.push(format!("<p>{}</p>", current.join("<br>")));
+            }
+            paragraphs.join("\n\n")
+        })
+    }
+
+    /// Applies a string transformation that cannot introduce characters that
+    /// need escaping, so the safe marker of the input is retained.
+    fn map_keep_safe<F: FnOnce(&str) -> String>(v: &Value, f: F) -> Value {
+        // missing variables are passed on so that they can still be defaulted
+        if let ValueRepr::Missing(_) = v.0 {
+            return v.clone();
+        }
+        let rv = f(&v.to_cowstr());
+        if v.is_safe() {
+            Value::from_safe_string(rv)
+        } else {
+            Value::from(rv)
+        }
+    }
+
     /// Converts a value to uppercase.
     ///
     /// ```jinja
     /// <h1>{{ chapter.title|upper }}</h1>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn upper(v: Cow<'_, str>) -> String {
-        v.to_uppercase()
+    pub fn upper(v: &Value) -> Value {
+        map_keep_safe(v, |s| s.to_uppercase())
     }
 
     /// Converts a value to lowercase.
@@ -274,31 +526,56 @@ mod builtins {
     /// <h1>{{ chapter.title|lower }}</h1>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn lower(v: Cow<'_, str>) -> String {
-        v.to_lowercase()
+    pub fn lower(v: &Value) -> Value {
+        map_keep_safe(v, |s| s.to_lowercase())
     }
 
     /// Converts a value to title case.
     ///
+    /// Every character that is not alphanumeric starts a new word.  Case
+    /// mapping follows the Unicode rules.
+    ///
     /// ```jinja
     /// <h1>{{ chapter.title|title }}</h1>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn title(v: Cow<'_, str>) -> String {
-        let mut rv = String::new();
-        let mut capitalize = true;
-        for c in v.chars() {
-            if c.is_ascii_punctuation() || c.is_whitespace() {
-                rv.push(c);
-                capitalize = true;
-            } else if capitalize {
-                write!(rv, "{}", c.to_uppercase()).unwrap();
-                capitalize = false;
-            } else {
-                write!(rv, "{}", c.to_lowercase()).unwrap();
+    pub fn title(v: &Value) -> Value {
+        map_keep_safe(v, |s| {
+            let mut rv = String::new();
+            let mut capitalize = true;
+            for c in s.chars() {
+                if !c.is_alphanumeric() {
+                    rv.push(c);
+                    capitalize = true;
+                } else if capitalize {
+                    write!(rv, "{}", c.to_uppercase()).unwrap();
+                    capitalize = false;
+                } else {
+                    write!(rv, "{}", c.to_lowercase()).unwrap();
+                }
             }
-        }
-        rv
+            rv
+        })
+    }
+
+    /// Convert the string with all its characters lowercased
+    /// apart from the first char which is uppercased.
+    ///
+    /// ```jinja
+    /// <h1>{{ chapter.title|capitalize }}</h1>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn capitalize(v: &Value) -> Value {
+        map_keep_safe(v, |s| {
+            let mut chars = s.chars();
+            match chars.next() {
+                None => "".into(),
+                Some(f) => f
+                    .to_uppercase()
+                    .chain(chars.as_str().to_lowercase().chars())
+                    .collect(),
+            }
+        })
     }
 
     /// Does a string replace.
@@ -309,19 +586,35 @@ mod builtins {
     /// {{ "Hello World"|replace("Hello", "Goodbye") }}
     ///   -> Goodbye World
     /// ```
+    ///
+    /// If the value is marked as safe and auto escaping is enabled, the
+    /// arguments are escaped before the replacement unless they are marked
+    /// as safe themselves.  The result then stays safe.
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn replace(
-        _state: &State,
-        v: Cow<'_, str>,
-        from: Cow<'_, str>,
-        to: Cow<'_, str>,
-    ) -> String {
-        v.replace(&from as &str, &to as &str)
+    pub fn replace(state: &State, v: &Value, from: &Value, to: &Value) -> Result<Value, Error> {
+        if !v.is_safe() {
+            return Ok(Value::from(
+                v.to_cowstr().replace(&*from.to_cowstr(), &to.to_cowstr()),
+            ));
+        }
+        let (from, to) = if matches!(state.auto_escape(), AutoEscape::None) {
+            (from.clone(), to.clone())
+        } else {
+            (
+                ok!(escape(state, from.clone())),
+                ok!(escape(state, to.clone())),
+            )
+        };
+        Ok(Value::from_safe_string(
+            v.to_cowstr().replace(&*from.to_cowstr(), &to.to_cowstr()),
+        ))
     }
 
     /// Returns the "length" of the value
     ///
-    /// By default this filter is also registered under the alias `count`.
+    /// Strings return the number of characters (unicode scalar values rather
+    /// than bytes), sequences and maps the number of items.  By default this
+    /// filter is also registered under the alias `count`.
     ///
     /// ```jinja
     /// <p>Search results: {{ results|length }}
@@ -336,150 +629,750 @@ mod builtins {
         })
     }
 
-    /// Dict sorting functionality.
+    /// Counts the words in a string.
     ///
-    /// This filter works like `|items` but sorts the pairs by key first.
+    /// Words are separated by any amount of whitespace.
+    ///
+    /// ```jinja
+    /// <p>This article has {{ article.body|wordcount }} words.
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn dictsort(v: Value) -> Result<Value, Error> {
-        let mut pairs = match v.0 {
-            ValueRepr::Map(ref v, _) => v.iter().collect::<Vec<_>>(),
-            _ => {
-                return Err(Error::new(
-                    ErrorKind::InvalidOperation,
-                    "cannot convert value into pair list",
-                ))
-            }
-        };
-        pairs.sort_by(|a, b| a.0.cmp(b.0));
-        Ok(Value::from(
-            pairs
-                .into_iter()
-                .map(|(k, v)| vec![Value::from(k.clone()), v.clone()])
-                .collect::<Vec<_>>(),
-        ))
+    pub fn wordcount(v: Cow<'_, str>) -> usize {
+        v.split_whitespace().count()
     }
 
-    /// Returns a list of pairs (items) from a mapping.
+    /// Truncates a string to a number of words.
     ///
-    /// This can be used to iterate over keys and values of a mapping
-    /// at once.  Note that this will use the original order of the map
-    /// which is typically arbitrary unless the `preserve_order` feature
-    /// is used in which case the original order of the map is retained.
-    /// It's generally better to use `|dictsort` which sorts the map by
-    /// key before iterating.
+    /// Keeps the first `count` words of the string and joins them with single
+    /// spaces.  If words were cut off the `end` argument (defaults to `...`)
+    /// is appended.  It can also be passed as keyword argument.
     ///
     /// ```jinja
-    /// <dl>
-    /// {% for key, value in my_dict|items %}
-    ///   <dt>{{ key }}
-    ///   <dd>{{ value }}
-    /// {% endfor %}
-    /// </dl>
+    /// <p>{{ article.body|truncatewords(25) }}
+    /// <p>{{ article.body|truncatewords(10, end=" [more]") }}
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn items(v: Value) -> Result<Value, Error> {
-        Ok(Value::from(
-            match v.0 {
-                ValueRepr::Map(ref v, _) => v.iter(),
+    pub fn truncatewords(
+        v: Cow<'_, str>,
+        count: usize,
+        end: Option<Value>,
+    ) -> Result<String, Error> {
+        let end = ok!(optional_arg(end, "end"));
+        let mut words = v.split_whitespace();
+        let mut rv = words.by_ref().take(count).collect::<Vec<_>>().join(" ");
+        if words.next().is_some() {
+            if end.is_undefined() {
+                rv.push_str("...");
+            } else {
+                rv.push_str(&end.to_cowstr());
+            }
+        }
+        Ok(rv)
+    }
+
+    /// Unpacks an optional trailing argument that can also be passed
+    /// as the only keyword argument `name`.
+    pub(crate) fn optional_arg(arg: Option<Value>, name: &str) -> Result<Value, Error> {
+        match arg {
+            Some(ref arg) if arg.is_kwargs() => {
+                let mut rv = Value::UNDEFINED;
+                ok!(for_each_kwarg(arg, |key, value| {
+                    if key != name {
+                        return Ok(false);
+                    }
+                    rv = value;
+                    Ok(true)
+                }));
+                Ok(rv)
+            }
+            Some(arg) => Ok(arg),
+            None => Ok(Value::UNDEFINED),
+        }
+    }
+
+    /// Invokes `f` with the name and value of every keyword argument.
+    ///
+    /// The callback returns `false` for names it does not know which fails
+    /// with an unknown keyword argument error.
+    fn for_each_kwarg<F>(kwargs: &Value, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&str, Value) -> Result<bool, Error>,
+    {
+        for key in ok!(kwargs.try_iter()) {
+            let value = ok!(kwargs.get_item(&key));
+            let known = match key.as_str() {
+                Some(name) => ok!(f(name, value)),
+                None => false,
+            };
+            if !known {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("unknown keyword argument {}", key),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn pad(
+        state: &State,
+        v: &str,
+        width: usize,
+        fillchar: Option<Value>,
+        left_share: impl FnOnce(usize) -> usize,
+    ) -> Result<String, Error> {
+        let fillchar = ok!(optional_arg(fillchar, "fillchar"));
+        let fillchar = if fillchar.is_undefined() {
+            ' '
+        } else {
+            let s = fillchar.to_cowstr();
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
                 _ => {
                     return Err(Error::new(
                         ErrorKind::InvalidOperation,
-                        "cannot convert value into pair list",
+                        "fill character must be exactly one character",
                     ))
                 }
             }
-            .map(|(k, v)| vec![Value::from(k.clone()), v.clone()])
-            .collect::<Vec<_>>(),
-        ))
+        };
+        let margin = width.saturating_sub(v.chars().count());
+        let left = left_share(margin);
+        let size = margin
+            .checked_mul(fillchar.len_utf8())
+            .and_then(|size| size.checked_add(v.len()));
+        let mut rv = String::with_capacity(ok!(check_generated_size(state, size)));
+        rv.extend(std::iter::repeat(fillchar).take(left));
+        rv.push_str(v);
+        rv.extend(std::iter::repeat(fillchar).take(margin - left));
+        Ok(rv)
     }
 
-    /// Reverses a list or string
+    /// Left-justifies a string in a field of the given width.
+    ///
+    /// The string is padded on the right with `fillchar` (defaults to a
+    /// space) until it is `width` characters long.  Longer strings are
+    /// returned unchanged.  The fill character can also be passed as
+    /// keyword argument.
     ///
     /// ```jinja
-    /// {% for user in users|reverse %}
-    ///   <li>{{ user.name }}
-    /// {% endfor %}
+    /// <pre>{{ name|ljust(20) }}|</pre>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn reverse(v: Value) -> Result<Value, Error> {
-        if let Some(s) = v.as_str() {
-            Ok(Value::from(s.chars().rev().collect::<String>()))
-        } else if matches!(v.kind(), ValueKind::Seq) {
-            Ok(Value::from(
-                ok!(v.as_slice()).iter().rev().cloned().collect::<Vec<_>>(),
-            ))
-        } else {
-            Err(Error::new(
-                ErrorKind::InvalidOperation,
-                format!("cannot reverse value of type {}", v.kind()),
-            ))
-        }
+    pub fn ljust(
+        state: &State,
+        v: Cow<'_, str>,
+        width: usize,
+        fillchar: Option<Value>,
+    ) -> Result<String, Error> {
+        pad(state, &v, width, fillchar, |_| 0)
     }
 
-    /// Trims a value
+    /// Right-justifies a string in a field of the given width.
+    ///
+    /// The string is padded on the left with `fillchar` (defaults to a
+    /// space) until it is `width` characters long.  Longer strings are
+    /// returned unchanged.  The fill character can also be passed as
+    /// keyword argument.
+    ///
+    /// ```jinja
+    /// <pre>{{ invoice.number|rjust(8, "0") }}</pre>
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn trim(s: Cow<'_, str>, chars: Option<Cow<'_, str>>) -> String {
-        match chars {
-            Some(chars) => {
-                let chars = chars.chars().collect::<Vec<_>>();
-                s.trim_matches(&chars[..]).to_string()
-            }
-            None => s.trim().to_string(),
-        }
+    pub fn rjust(
+        state: &State,
+        v: Cow<'_, str>,
+        width: usize,
+        fillchar: Option<Value>,
+    ) -> Result<String, Error> {
+        pad(state, &v, width, fillchar, |margin| margin)
     }
 
-    /// Joins a sequence by a character
+    /// Centers a string in a field of the given width.
+    ///
+    /// The string is padded on both sides with `fillchar` (defaults to a
+    /// space) until it is `width` characters long.  Longer strings are
+    /// returned unchanged.  Uneven padding is split the same way as
+    /// Python's `str.center` does it.
+    ///
+    /// ```jinja
+    /// <pre>{{ title|center(40, fillchar="-") }}</pre>
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn join(val: Value, joiner: Option<Cow<'_, str>>) -> Result<String, Error> {
-        if val.is_undefined() || val.is_none() {
-            return Ok(String::new());
-        }
-
-        let joiner = joiner.as_ref().unwrap_or(&Cow::Borrowed(""));
+    pub fn center(
+        state: &State,
+        v: Cow<'_, str>,
+        width: usize,
+        fillchar: Option<Value>,
+    ) -> Result<String, Error> {
+        pad(state, &v, width, fillchar, |margin| {
+            margin / 2 + (margin & width & 1)
+        })
+    }
 
-        if let Some(s) = val.as_str() {
-            let mut rv = String::new();
-            for c in s.chars() {
-                if !rv.is_empty() {
-                    rv.push_str(joiner);
+    /// Indents every line of a string but the first.
+    ///
+    /// The filter accepts the arguments `width`, `first` and `blank` either
+    /// positionally or as keyword arguments.  `width` is the number of spaces
+    /// to indent with (default 4) or the string to indent with.  If `first` is
+    /// `true` the first line is indented as well, if `blank` is `true` blank
+    /// lines are indented too.  The safe marker of the value is retained.
+    ///
+    /// ```jinja
+    /// <pre>
+    ///   {{ code|indent(2) }}
+    /// </pre>
+    /// {% filter indent(4, first=true) %}
+    ///   {{ body }}
+    /// {% endfilter %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn indent(state: &State, v: Value, args: Rest<Value>) -> Result<Value, Error> {
+        let (args, kwargs) = split_kwargs(args);
+        if args.len() > 3 {
+            return Err(Error::from(ErrorKind::TooManyArguments));
+        }
+        let mut width = args.first().cloned();
+        let mut first = args.get(1).map_or(false, |x| x.is_true());
+        let mut blank = args.get(2).map_or(false, |x| x.is_true());
+        if let Some(kwargs) = kwargs {
+            ok!(for_each_kwarg(&kwargs, |key, value| {
+                match key {
+                    "width" => width = Some(value),
+                    "first" => first = value.is_true(),
+                    "blank" => blank = value.is_true(),
+                    _ => return Ok(false),
                 }
-                rv.push(c);
-            }
-            Ok(rv)
-        } else if matches!(val.kind(), ValueKind::Seq) {
-            let mut rv = String::new();
-            for item in ok!(val.as_slice()) {
-                if !rv.is_empty() {
-                    rv.push_str(joiner);
+                Ok(true)
+            }));
+        }
+        let indention = match width {
+            None => "    ".to_string(),
+            Some(width) => match width.as_str() {
+                Some(s) if v.is_safe() => HtmlEscape(s).to_string(),
+                Some(s) => s.to_string(),
+                None => {
+                    let width = ok!(usize::try_from(width));
+                    " ".repeat(ok!(check_generated_size(state, Some(width))))
+                }
+            },
+        };
+        let mut failed = None;
+        let rv = map_keep_safe(&v, |text| {
+            let size = indention
+                .len()
+                .checked_mul(text.split('\n').count())
+                .and_then(|size| size.checked_add(text.len()));
+            let mut rv = match check_generated_size(state, size) {
+                Ok(size) => String::with_capacity(size),
+                Err(err) => {
+                    failed = Some(err);
+                    return String::new();
                 }
-                if let Some(s) = item.as_str() {
-                    rv.push_str(s);
+            };
+            for (idx, line) in text.split('\n').enumerate() {
+                if idx > 0 {
+                    rv.push('\n');
+                }
+                let indent_line = if idx == 0 {
+                    first
                 } else {
-                    write!(rv, "{}", item).ok();
+                    blank || !line.trim_end_matches('\r').is_empty()
+                };
+                if indent_line {
+                    rv.push_str(&indention);
                 }
+                rv.push_str(line);
             }
-            Ok(rv)
-        } else {
-            Err(Error::new(
-                ErrorKind::InvalidOperation,
-                format!("cannot join value of type {}", val.kind()),
-            ))
+            rv
+        });
+        match failed {
+            Some(err) => Err(err),
+            None => Ok(rv),
         }
     }
 
-    /// If the value is undefined it will return the passed default value,
-    /// otherwise the value of the variable:
+    /// Returns a plural suffix if the value is not 1.
+    ///
+    /// The value can be a number or anything with a length such as a list.
+    /// By default the suffix is `s`.  A custom suffix can be provided as the
+    /// first argument.  If it contains a comma, the part before the comma is
+    /// used for the singular and the part after it for the plural.
     ///
     /// ```jinja
-    /// <p>{{ my_variable|default("my_variable was not defined") }}</p>
+    /// {{ count }} item{{ count|pluralize }}
+    /// {{ count }} class{{ count|pluralize("es") }}
+    /// {{ count }} cand{{ count|pluralize("y,ies") }}
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn default(value: Value, other: Option<Value>) -> Value {
-        if value.is_undefined() {
-            other.unwrap_or_else(|| Value::from(""))
-        } else {
-            value
-        }
+    pub fn pluralize(v: Value, suffix: Option<Cow<'_, str>>) -> Result<String, Error> {
+        let is_singular = match v.kind() {
+            ValueKind::Number => v == Value::from(1),
+            _ => ok!(length(v)) == 1,
+        };
+        let suffix = suffix.as_deref().unwrap_or("s");
+        let (singular, plural) = match suffix.split_once(',') {
+            Some((_, plural)) if plural.contains(',') => {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "pluralize suffix must not contain more than one comma",
+                ))
+            }
+            Some((singular, plural)) => (singular, plural),
+            None => ("", suffix),
+        };
+        Ok(if is_singular { singular } else { plural }.to_string())
+    }
+
+    /// Returns the key/value pairs of a map or an object implementing
+    /// [`Object::pairs`].
+    fn pair_list(v: &Value) -> Result<Vec<(Value, Value)>, Error> {
+        match v.0 {
+            ValueRepr::Map(ref v, _) => Ok(v
+                .iter()
+                .map(|(k, v)| (Value::from(k.clone()), v.clone()))
+                .collect()),
+            ValueRepr::Dynamic(ref obj) => match obj.pairs() {
+                Some(pairs) => Ok(pairs.collect()),
+                None => Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "cannot convert object into pair list, it does not provide pairs",
+                )),
+            },
+            _ => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "cannot convert value into pair list",
+            )),
+        }
+    }
+
+    fn pairs_to_value(pairs: Vec<(Value, Value)>) -> Value {
+        Value::from(
+            pairs
+                .into_iter()
+                .map(|(k, v)| Value::from(vec![k, v]))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Dict sorting functionality.
+    ///
+    /// This filter works like `|items` but sorts the pairs by key first.
+    /// Objects that implement [`Object::pairs`](crate::value::Object::pairs)
+    /// are sorted too.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn dictsort(v: Value) -> Result<Value, Error> {
+        let pairs = match v.0 {
+            ValueRepr::Map(ref v, _) => {
+                let mut pairs = v.iter().collect::<Vec<_>>();
+                pairs.sort_by(|a, b| a.0.cmp(b.0));
+                pairs
+                    .into_iter()
+                    .map(|(k, v)| (Value::from(k.clone()), v.clone()))
+                    .collect()
+            }
+            _ => {
+                let mut pairs = ok!(pair_list(&v));
+                pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+                pairs
+            }
+        };
+        Ok(pairs_to_value(pairs))
+    }
+
+    /// Sorts a sequence.
+    ///
+    /// The filter accepts the arguments `reverse`, `case_sensitive` and
+    /// `attribute` either positionally or as keyword arguments.  Strings are
+    /// compared case insensitively unless `case_sensitive` is set to `true`.
+    /// With `attribute` the items are sorted by the value at that dotted
+    /// attribute path.  Multiple paths can be given separated by commas in
+    /// which case items that compare equal on the first path are sorted by
+    /// the next one.  The sort is stable so items that compare equal on all
+    /// paths retain their original order, also when `reverse` is set.
+    /// Undefined and none values are sorted last.  Sorting values that cannot
+    /// be compared with each other (like a number and a string) fails with an
+    /// error.
+    ///
+    /// ```jinja
+    /// {% for user in users|sort(attribute="lastname,firstname") %}
+    ///   <li>{{ user.lastname }}, {{ user.firstname }}
+    /// {% endfor %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn sort(state: &State, value: Value, args: Rest<Value>) -> Result<Value, Error> {
+        let (args, kwargs) = split_kwargs(args);
+        if args.len() > 3 {
+            return Err(Error::from(ErrorKind::TooManyArguments));
+        }
+        let mut reverse = args.first().map_or(false, |x| x.is_true());
+        let mut case_sensitive = args.get(1).map_or(false, |x| x.is_true());
+        let mut attribute = args.get(2).cloned();
+        if let Some(kwargs) = kwargs {
+            ok!(for_each_kwarg(&kwargs, |key, value| {
+                match key {
+                    "reverse" => reverse = value.is_true(),
+                    "case_sensitive" => case_sensitive = value.is_true(),
+                    "attribute" => attribute = Some(value),
+                    _ => return Ok(false),
+                }
+                Ok(true)
+            }));
+        }
+        let attribute = match attribute {
+            Some(ref attribute) if !attribute.is_none() => match attribute.as_str() {
+                Some(attribute) => Some(attribute),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        "attribute name must be a string",
+                    ))
+                }
+            },
+            _ => None,
+        };
+        let paths = attribute
+            .map(|x| {
+                x.split(',')
+                    .map(|x| AttrPath::new(x.trim()))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let make_key = |value: Value| match value.as_str() {
+            Some(s) if !case_sensitive => Value::from(s.to_lowercase()),
+            _ => value,
+        };
+        let mut items = Vec::with_capacity(value.len().unwrap_or(0));
+        for item in ok!(LazySeq::iter(state, &value)) {
+            let item = ok!(item);
+            let keys = if paths.is_empty() {
+                vec![make_key(item.clone())]
+            } else {
+                let mut keys = Vec::with_capacity(paths.len());
+                for path in &paths {
+                    keys.push(make_key(ok!(path.resolve(state, &item))));
+                }
+                keys
+            };
+            items.push((keys, item));
+        }
+        let mut failed = None;
+        items.sort_by(|a, b| {
+            let ordering =
+                a.0.iter()
+                    .zip(b.0.iter())
+                    .map(|(a, b)| {
+                        cmp_sort_keys(a, b).unwrap_or_else(|err| {
+                            failed.get_or_insert(err);
+                            Ordering::Equal
+                        })
+                    })
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(Ordering::Equal);
+            if reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        if let Some(err) = failed {
+            return Err(err);
+        }
+        Ok(Value::from(
+            items.into_iter().map(|(_, item)| item).collect::<Vec<_>>(),
+        ))
+    }
+
+    /// Compares two sort keys, failing for values that have no ordering.
+    ///
+    /// Undefined and none keys are ordered after all other values.
+    fn cmp_sort_keys(a: &Value, b: &Value) -> Result<Ordering, Error> {
+        let missing = |v: &Value| v.is_undefined() || v.is_none();
+        match (missing(a), missing(b)) {
+            (true, true) => Ok(Ordering::Equal),
+            (true, false) => Ok(Ordering::Greater),
+            (false, true) => Ok(Ordering::Less),
+            (false, false) => a.partial_cmp(b).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("cannot compare {} and {}", a.kind(), b.kind()),
+                )
+            }),
+        }
+    }
+
+    /// Returns a list of unique items from the given iterable.
+    ///
+    /// The items are returned in the order of their first occurrence.  Like
+    /// with [`sort`] strings are compared case insensitively unless
+    /// `case_sensitive` is set to `true` and the `attribute` argument
+    /// compares the items by the value at a dotted attribute path.  Both can
+    /// be given positionally or as keyword arguments.
+    ///
+    /// ```jinja
+    /// {{ ["foo", "bar", "FOO", "baz"]|unique|join(", ") }}
+    ///   -> foo, bar, baz
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn unique(state: &State, value: Value, args: Rest<Value>) -> Result<Value, Error> {
+        let (args, kwargs) = split_kwargs(args);
+        if args.len() > 2 {
+            return Err(Error::from(ErrorKind::TooManyArguments));
+        }
+        let mut case_sensitive = args.first().map_or(false, |x| x.is_true());
+        let mut attribute = args.get(1).cloned();
+        if let Some(kwargs) = kwargs {
+            ok!(for_each_kwarg(&kwargs, |key, value| {
+                match key {
+                    "case_sensitive" => case_sensitive = value.is_true(),
+                    "attribute" => attribute = Some(value),
+                    _ => return Ok(false),
+                }
+                Ok(true)
+            }));
+        }
+        let path = match attribute {
+            Some(ref attribute) if !attribute.is_none() => match attribute.as_str() {
+                Some(attribute) => Some(AttrPath::new(attribute)),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        "attribute name must be a string",
+                    ))
+                }
+            },
+            _ => None,
+        };
+
+        // hashable keys are tracked in a set, the others (anything that
+        // contains dynamic objects without a custom hash) have to be compared
+        // one by one.
+        let mut seen = std::collections::HashSet::new();
+        let mut seen_unhashable = Vec::new();
+        let mut rv = Vec::new();
+        for item in ok!(LazySeq::iter(state, &value)) {
+            let item = ok!(item);
+            let key = match path {
+                Some(ref path) => ok!(path.resolve(state, &item)),
+                None => item.clone(),
+            };
+            let key = match key.as_str() {
+                Some(s) if !case_sensitive => Value::from(s.to_lowercase()),
+                _ => key,
+            };
+            if seen_unhashable.contains(&key) {
+                continue;
+            }
+            if is_hashable(&key) {
+                if !seen.insert(key) {
+                    continue;
+                }
+            } else if seen.iter().any(|x| x == &key) {
+                continue;
+            } else {
+                seen_unhashable.push(key);
+            }
+            rv.push(item);
+        }
+        Ok(Value::from(rv))
+    }
+
+    /// Checks if the hash of a value is consistent with its equality.
+    fn is_hashable(value: &Value) -> bool {
+        match value.0 {
+            ValueRepr::Seq(ref items) => items.iter().all(is_hashable),
+            ValueRepr::Map(ref map, _) => map.values().all(is_hashable),
+            ValueRepr::Dynamic(ref obj) => obj.custom_hash().map_or(false, |x| is_hashable(&x)),
+            _ => true,
+        }
+    }
+
+    /// Returns a list of pairs (items) from a mapping.
+    ///
+    /// This can be used to iterate over keys and values of a mapping
+    /// at once.  Note that this will use the original order of the map
+    /// which is typically arbitrary unless the `preserve_order` feature
+    /// is used in which case the original order of the map is retained.
+    /// It's generally better to use `|dictsort` which sorts the map by
+    /// key before iterating.  Objects that implement
+    /// [`Object::pairs`](crate::value::Object::pairs) yield their pairs.
+    ///
+    /// ```jinja
+    /// <dl>
+    /// {% for key, value in my_dict|items %}
+    ///   <dt>{{ key }}
+    ///   <dd>{{ value }}
+    /// {% endfor %}
+    /// </dl>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn items(v: Value) -> Result<Value, Error> {
+        Ok(pairs_to_value(ok!(pair_list(&v))))
+    }
+
+    /// Reverses a list or string
+    ///
+    /// ```jinja
+    /// {% for user in users|reverse %}
+    ///   <li>{{ user.name }}
+    /// {% endfor %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn reverse(v: Value) -> Result<Value, Error> {
+        if let Some(s) = v.as_str() {
+            Ok(Value::from(s.chars().rev().collect::<String>()))
+        } else if matches!(v.kind(), ValueKind::Seq) {
+            Ok(Value::from(
+                ok!(v.as_slice()).iter().rev().cloned().collect::<Vec<_>>(),
+            ))
+        } else {
+            Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("cannot reverse value of type {}", v.kind()),
+            ))
+        }
+    }
+
+    /// Trims a value
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn trim(v: &Value, chars: Option<Cow<'_, str>>) -> Value {
+        map_keep_safe(v, |s| match chars {
+            Some(chars) => {
+                let chars = chars.chars().collect::<Vec<_>>();
+                s.trim_matches(&chars[..]).to_string()
+            }
+            None => s.trim().to_string(),
+        })
+    }
+
+    /// Joins a sequence by a character
+    ///
+    /// If HTML auto escaping is enabled the items are escaped unless they are
+    /// already marked as safe and the result is marked as safe.  The same
+    /// applies to the joiner.  For other auto escape formats the items are
+    /// joined first and the result is escaped when it's printed.
+    ///
+    /// ```jinja
+    /// {{ users|map(attribute="username")|join(", ") }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn join(state: &State, val: Value, joiner: Option<Value>) -> Result<Value, Error> {
+        if val.is_undefined() || val.is_none() {
+            return Ok(Value::from(""));
+        }
+
+        if !matches!(val.kind(), ValueKind::String | ValueKind::Seq) && !LazySeq::is_lazy(&val) {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("cannot join value of type {}", val.kind()),
+            ));
+        }
+
+        let html = matches!(state.auto_escape(), AutoEscape::Html);
+        let mut joiner_str = String::new();
+        if let Some(ref joiner) = joiner {
+            // the joined string is marked as safe, so the joiner has to be
+            // escaped just like the items.
+            if html {
+                ok!(write_escaped(
+                    &mut Output::with_string(&mut joiner_str),
+                    AutoEscape::Html,
+                    joiner,
+                ));
+            } else {
+                ok!(write!(joiner_str, "{}", joiner).map_err(Error::from));
+            }
+        }
+        let mut rv = String::new();
+        let mut out = Output::with_string(&mut rv);
+        for (idx, item) in ok!(LazySeq::iter(state, &val)).enumerate() {
+            let item = ok!(item);
+            if idx > 0 {
+                ok!(out.write_str(&joiner_str).map_err(Error::from));
+            }
+            if html {
+                ok!(write_escaped(&mut out, AutoEscape::Html, &item));
+            } else {
+                ok!(write!(out, "{}", item).map_err(Error::from));
+            }
+        }
+
+        // other formats (like JSON) escape the joined string as a whole
+        // when it's printed.
+        Ok(if html {
+            Value::from_safe_string(rv)
+        } else {
+            Value::from(rv)
+        })
+    }
+
+    /// If the value is undefined it will return the passed default value,
+    /// otherwise the value of the variable:
+    ///
+    /// ```jinja
+    /// <p>{{ my_variable|default("my_variable was not defined") }}</p>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn default(value: Value, other: Option<Value>) -> Value {
+        if value.is_undefined() {
+            other.unwrap_or_else(|| Value::from(""))
+        } else {
+            value
+        }
+    }
+
+    /// If the value is none it will return the passed default value,
+    /// otherwise the value of the variable.
+    ///
+    /// Unlike [`default`] this does not replace undefined values.  This filter
+    /// is registered by
+    /// [`Environment::new_django_compat`](crate::Environment::new_django_compat).
+    ///
+    /// ```jinja
+    /// <p>{{ user.nickname|default_if_none("anonymous") }}</p>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn default_if_none(value: Value, other: Value) -> Value {
+        if value.is_none() {
+            other
+        } else {
+            value
+        }
+    }
+
+    /// Maps a value to one of the comma separated choices.
+    ///
+    /// The choices are the strings for true, false and optionally none
+    /// values, defaulting to `"yes,no,maybe"`.  If only two choices are given
+    /// none values map to the second one.  This filter is registered by
+    /// [`Environment::new_django_compat`](crate::Environment::new_django_compat).
+    ///
+    /// ```jinja
+    /// Subscribed: {{ user.subscribed|yesno("yes,no,unknown") }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn yesno(value: Value, choices: Option<Cow<'_, str>>) -> Result<String, Error> {
+        let choices = choices.unwrap_or(Cow::Borrowed("yes,no,maybe"));
+        let (yes, no, maybe) = match choices.split(',').collect::<Vec<_>>()[..] {
+            [yes, no] => (yes, no, no),
+            [yes, no, maybe] => (yes, no, maybe),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "yesno expects two or three comma separated choices",
+                ))
+            }
+        };
+        Ok(if value.is_none() {
+            maybe
+        } else if value.is_true() {
+            yes
+        } else {
+            no
+        }
+        .to_string())
     }
 
     /// Returns the absolute value of a number.
@@ -504,20 +1397,78 @@ mod builtins {
     /// Round the number to a given precision.
     ///
     /// Round the number to a given precision. The first parameter specifies the
-    /// precision (default is 0).
+    /// precision (default is 0), the second the rounding method.  Both can also
+    /// be passed as keyword arguments `precision` and `method`:
+    ///
+    /// - `common` rounds ties away from zero (default)
+    /// - `half_even` rounds ties to the nearest even digit (banker's rounding)
+    /// - `ceil` always rounds up
+    /// - `floor` always rounds down
+    ///
+    /// Rounding is performed on the shortest decimal representation of the
+    /// number rather than on its binary value.  This means that `2.675` is
+    /// considered a tie and rounds to `2.68` with `common` and `half_even`,
+    /// even though the closest binary float is slightly below `2.675`.
+    /// Rounding to a power of ten that does not fit into a float (for
+    /// instance `2.5|round(-400, 'ceil')`) fails with an error.
     ///
     /// ```jinja
     /// {{ 42.55|round }}
     ///   -> 43.0
+    /// {{ 2.665|round(2, 'half_even') }}
+    ///   -> 2.66
+    /// {{ 42.11|round(method='ceil') }}
+    ///   -> 43.0
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn round(value: Value, precision: Option<i32>) -> Result<Value, Error> {
+    pub fn round(
+        value: Value,
+        precision: Option<Value>,
+        method: Option<Value>,
+    ) -> Result<Value, Error> {
+        let (mut precision, mut method) = (precision, method);
+        if let Some(kwargs) = method.as_ref().or(precision.as_ref()) {
+            if kwargs.is_kwargs() {
+                let kwargs = kwargs.clone();
+                if method.is_none() {
+                    precision = None;
+                } else {
+                    method = None;
+                }
+                ok!(for_each_kwarg(&kwargs, |key, value| {
+                    match key {
+                        "precision" => precision = Some(value),
+                        "method" => method = Some(value),
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                }));
+            }
+        }
+        let precision = match precision {
+            Some(precision) => ok!(i32::try_from(precision)),
+            None => 0,
+        };
+        let method = method.unwrap_or(Value::UNDEFINED);
+        let method = if method.is_undefined() {
+            RoundingMethod::Common
+        } else {
+            match method.as_str() {
+                Some("common") => RoundingMethod::Common,
+                Some("half_even") => RoundingMethod::HalfEven,
+                Some("ceil") => RoundingMethod::Ceil,
+                Some("floor") => RoundingMethod::Floor,
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        format!("unknown rounding method {}", method),
+                    ))
+                }
+            }
+        };
         match value.0 {
             ValueRepr::I64(_) | ValueRepr::I128(_) => Ok(value),
-            ValueRepr::F64(val) => {
-                let x = 10f64.powi(precision.unwrap_or(0));
-                Ok(Value::from((x * val).round() / x))
-            }
+            ValueRepr::F64(val) => Ok(Value::from(ok!(round_decimal(val, precision, method)))),
             _ => Err(Error::new(
                 ErrorKind::InvalidOperation,
                 "cannot round value",
@@ -525,50 +1476,213 @@ mod builtins {
         }
     }
 
-    /// Returns the first item from a list.
-    ///
-    /// If the list is empty `undefined` is returned.
-    ///
-    /// ```jinja
-    /// <dl>
-    ///   <dt>primary email
-    ///   <dd>{{ user.email_addresses|first|default('no user') }}
-    /// </dl>
-    /// ```
-    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn first(value: Value) -> Result<Value, Error> {
-        match value.0 {
-            ValueRepr::String(s, _) => Ok(s.chars().next().map_or(Value::UNDEFINED, Value::from)),
-            ValueRepr::Seq(ref s) => Ok(s.first().cloned().unwrap_or(Value::UNDEFINED)),
-            _ => Err(Error::new(
+    #[derive(Copy, Clone, PartialEq)]
+    enum RoundingMethod {
+        Common,
+        HalfEven,
+        Ceil,
+        Floor,
+    }
+
+    /// Rounds the shortest decimal representation of `val` to `precision`
+    /// digits after the decimal point.
+    fn round_decimal(val: f64, precision: i32, method: RoundingMethod) -> Result<f64, Error> {
+        if !val.is_finite() || val == 0.0 {
+            return Ok(val);
+        }
+        // beyond this range every float is either kept as is or rounded to
+        // zero or a power of ten that no longer fits into a float.
+        let precision = precision.clamp(-400, 400);
+        // the shortest representation that round trips, eg: 2.675e0
+        let repr = format!("{:e}", val.abs());
+        let (mantissa, exp) = repr.split_once('e').unwrap();
+        let digits = mantissa.replace('.', "").into_bytes();
+        let exp: i32 = exp.parse().unwrap();
+
+        // number of leading digits that are kept
+        let keep = exp + precision + 1;
+        if keep >= digits.len() as i32 {
+            return Ok(val);
+        }
+        let (kept, rest) = digits.split_at(keep.max(0) as usize);
+        let mut rv = kept
+            .iter()
+            .fold(0u64, |acc, d| acc * 10 + (d - b'0') as u64);
+        let negative = val < 0.0;
+        let is_tie = keep >= 0 && rest[0] == b'5' && rest[1..].iter().all(|&d| d == b'0');
+        let above_half = keep >= 0 && rest[0] >= b'5' && !is_tie;
+        let round_up = match method {
+            RoundingMethod::Common => above_half || is_tie,
+            RoundingMethod::HalfEven => above_half || (is_tie && rv % 2 == 1),
+            RoundingMethod::Ceil => !negative,
+            RoundingMethod::Floor => negative,
+        };
+        if round_up {
+            rv += 1;
+        }
+        // parsing the decimal representation avoids spurious trailing digits
+        let rv: f64 = format!("{}e{}", rv, -precision).parse().unwrap();
+        if rv.is_infinite() {
+            return Err(Error::new(
                 ErrorKind::InvalidOperation,
-                "cannot get first item from value",
-            )),
+                "rounded value is out of range",
+            ));
         }
+        Ok(if negative && rv != 0.0 { -rv } else { rv })
     }
 
-    /// Returns the last item from a list.
+    /// Formats a number with a configurable decimal point and grouping.
     ///
-    /// If the list is empty `undefined` is returned.
+    /// The filter accepts the arguments `decimals`, `grouping`, `decimal_point`
+    /// and `thousands_sep` either positionally or as keyword arguments.  If
+    /// `decimals` is given the number is rounded to that many digits after the
+    /// decimal point, at most 100 digits are supported.  With `grouping`
+    /// enabled the digits before the decimal point are grouped in thousands,
+    /// separated by `thousands_sep` which defaults to `,`.  The decimal point
+    /// defaults to `.`.
     ///
     /// ```jinja
-    /// <h2>Most Recent Update</h2>
-    /// {% with update = updates|last %}
-    ///   <dl>
-    ///     <dt>Location
-    ///     <dd>{{ update.location }}
-    ///     <dt>Status
-    ///     <dd>{{ update.status }}
-    ///   </dl>
-    /// {% endwith %}
+    /// {{ 1234567|format_number(grouping=true) }}
+    ///   -> 1,234,567
+    /// {{ 1234.5|format_number(2, true, decimal_point=",", thousands_sep=".") }}
+    ///   -> 1.234,50
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn format_number(value: Value, args: Rest<Value>) -> Result<String, Error> {
+        const PARAMS: [&str; 4] = ["decimals", "grouping", "decimal_point", "thousands_sep"];
+        const MAX_DECIMALS: usize = 100;
+
+        let (args, kwargs) = split_kwargs(args);
+        if args.len() > PARAMS.len() {
+            return Err(Error::from(ErrorKind::TooManyArguments));
+        }
+        let mut params = PARAMS.iter().copied().zip(args).collect::<Vec<_>>();
+        if let Some(ref kwargs) = kwargs {
+            ok!(for_each_kwarg(kwargs, |key, value| {
+                match PARAMS.iter().find(|name| **name == key) {
+                    Some(name) => params.push((name, value)),
+                    None => return Ok(false),
+                }
+                Ok(true)
+            }));
+        }
+
+        let mut decimals = None;
+        let mut grouping = false;
+        let mut decimal_point = Cow::Borrowed(".");
+        let mut thousands_sep = Cow::Borrowed(",");
+        for (name, value) in params {
+            match name {
+                "decimals" if value.is_none() || value.is_undefined() => decimals = None,
+                "decimals" => {
+                    let value = ok!(usize::try_from(value));
+                    if value > MAX_DECIMALS {
+                        return Err(Error::new(
+                            ErrorKind::InvalidOperation,
+                            format!("at most {} decimals are supported", MAX_DECIMALS),
+                        ));
+                    }
+                    decimals = Some(value);
+                }
+                "grouping" => grouping = value.is_true(),
+                "decimal_point" => decimal_point = Cow::Owned(value.to_string()),
+                _ => thousands_sep = Cow::Owned(value.to_string()),
+            }
+        }
+
+        let num = match value.0 {
+            ValueRepr::F64(val) if !val.is_finite() => return Ok(value.to_string()),
+            ValueRepr::F64(val) => match decimals {
+                Some(decimals) => format!("{:.*}", decimals, val),
+                None => value.to_string(),
+            },
+            ValueRepr::U64(_) | ValueRepr::I64(_) | ValueRepr::U128(_) | ValueRepr::I128(_) => {
+                match decimals {
+                    Some(decimals) if decimals > 0 => {
+                        format!("{}.{}", value, "0".repeat(decimals))
+                    }
+                    _ => value.to_string(),
+                }
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("cannot format value of type {} as number", value.kind()),
+                ))
+            }
+        };
+
+        let (sign, num) = match num.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", &num as &str),
+        };
+        let (int_part, frac_part) = match num.split_once('.') {
+            Some((int_part, frac_part)) => (int_part, Some(frac_part)),
+            None => (num, None),
+        };
+
+        let mut rv = String::from(sign);
+        for (idx, c) in int_part.chars().enumerate() {
+            if grouping && idx > 0 && (int_part.len() - idx) % 3 == 0 {
+                rv.push_str(&thousands_sep);
+            }
+            rv.push(c);
+        }
+        if let Some(frac_part) = frac_part {
+            rv.push_str(&decimal_point);
+            rv.push_str(frac_part);
+        }
+        Ok(rv)
+    }
+
+    /// Returns the first item from a list.
+    ///
+    /// If the list is empty or the value is undefined, `undefined` is returned.
+    ///
+    /// ```jinja
+    /// <dl>
+    ///   <dt>primary email
+    ///   <dd>{{ user.email_addresses|first|default('no user') }}
+    /// </dl>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn first(value: Value) -> Result<Value, Error> {
+        match value.0 {
+            ValueRepr::Undefined => Ok(Value::UNDEFINED),
+            ValueRepr::String(s, _) => Ok(s.chars().next().map_or(Value::UNDEFINED, Value::from)),
+            ValueRepr::Seq(ref s) => Ok(s.first().cloned().unwrap_or(Value::UNDEFINED)),
+            ValueRepr::Dynamic(ref obj) => Ok(obj.iter().next().unwrap_or(Value::UNDEFINED)),
+            _ => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "cannot get first item from value",
+            )),
+        }
+    }
+
+    /// Returns the last item from a list.
+    ///
+    /// If the list is empty or the value is undefined, `undefined` is returned.
+    ///
+    /// ```jinja
+    /// <h2>Most Recent Update</h2>
+    /// {% with update = updates|last %}
+    ///   <dl>
+    ///     <dt>Location
+    ///     <dd>{{ update.location }}
+    ///     <dt>Status
+    ///     <dd>{{ update.status }}
+    ///   </dl>
+    /// {% endwith %}
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn last(value: Value) -> Result<Value, Error> {
         match value.0 {
+            ValueRepr::Undefined => Ok(Value::UNDEFINED),
             ValueRepr::String(s, _) => {
                 Ok(s.chars().rev().next().map_or(Value::UNDEFINED, Value::from))
             }
             ValueRepr::Seq(ref s) => Ok(s.last().cloned().unwrap_or(Value::UNDEFINED)),
+            ValueRepr::Dynamic(ref obj) => Ok(obj.iter().last().unwrap_or(Value::UNDEFINED)),
             _ => Err(Error::new(
                 ErrorKind::InvalidOperation,
                 "cannot get last item from value",
@@ -576,138 +1690,745 @@ mod builtins {
         }
     }
 
-    /// Converts the input value into a list.
-    ///
-    /// If the value is already a list, then it's returned unchanged.
-    /// Applied to a map this returns the list of keys, applied to a
-    /// string this returns the characters.  If the value is undefined
-    /// an empty list is returned.
-    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn list(value: Value) -> Result<Value, Error> {
-        match &value.0 {
-            ValueRepr::Undefined => Ok(Value::from(Vec::<Value>::new())),
-            ValueRepr::String(ref s, _) => {
-                Ok(Value::from(s.chars().map(Value::from).collect::<Vec<_>>()))
+    /// Converts the input value into a list.
+    ///
+    /// If the value is already a list, then it's returned unchanged.
+    /// Applied to a map this returns the list of keys, applied to a
+    /// string this returns the characters.  Objects are materialized into
+    /// a list of the items they [iterate](crate::value::Object::iter) over.
+    /// If the value is undefined an empty list is returned.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn list(value: Value) -> Result<Value, Error> {
+        match &value.0 {
+            ValueRepr::Undefined => Ok(Value::from(Vec::<Value>::new())),
+            ValueRepr::String(ref s, _) => {
+                Ok(Value::from(s.chars().map(Value::from).collect::<Vec<_>>()))
+            }
+            ValueRepr::Seq(_) => Ok(value.clone()),
+            ValueRepr::Map(ref m, _) => Ok(Value::from(
+                m.iter()
+                    .map(|x| Value::from(x.0.clone()))
+                    .collect::<Vec<_>>(),
+            )),
+            ValueRepr::Dynamic(_) => Ok(Value::from(ok!(value.try_iter()).collect::<Vec<_>>())),
+            _ => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "cannot convert value to list",
+            )),
+        }
+    }
+
+    /// Converts the input value into a string.
+    ///
+    /// Strings are returned unchanged so a string that is marked as safe
+    /// stays safe.  All other values are converted into their display
+    /// form which is then escaped as usual when auto escaping is enabled.
+    ///
+    /// ```jinja
+    /// {{ user.id|string|length }}
+    /// {{ [1, 2, 3]|map("string")|join("-") }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn string(value: Value) -> Value {
+        if value.as_str().is_some() {
+            value
+        } else {
+            Value::from(value.to_string())
+        }
+    }
+
+    /// Converts the value into a boolean value.
+    ///
+    /// This behaves the same as the if statement does with regards to
+    /// handling of boolean values.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn bool(value: Value) -> bool {
+        value.is_true()
+    }
+
+    /// Slice an iterable and return a list of lists containing
+    /// those items.
+    ///
+    /// Useful if you want to create a div containing three ul tags that
+    /// represent columns:
+    ///
+    /// ```jinja
+    /// <div class="columnwrapper">
+    /// {% for column in items|slice(3) %}
+    ///   <ul class="column-{{ loop.index }}">
+    ///   {% for item in column %}
+    ///     <li>{{ item }}</li>
+    ///   {% endfor %}
+    ///   </ul>
+    /// {% endfor %}
+    /// </div>
+    /// ```
+    ///
+    /// If you pass it a second argument it’s used to fill missing values on the
+    /// last iteration.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn slice(value: Value, count: usize, fill_with: Option<Value>) -> Result<Value, Error> {
+        if count == 0 {
+            return Err(Error::new(ErrorKind::InvalidOperation, "count cannot be 0"));
+        }
+        let items = ok!(value.try_iter_owned()).collect::<Vec<_>>();
+        let len = items.len();
+        let items_per_slice = len / count;
+        let slices_with_extra = len % count;
+        let mut offset = 0;
+        let mut rv = Vec::new();
+
+        for slice in 0..count {
+            let start = offset + slice * items_per_slice;
+            if slice < slices_with_extra {
+                offset += 1;
+            }
+            let end = offset + (slice + 1) * items_per_slice;
+            let tmp = &items[start..end];
+
+            if let Some(ref filler) = fill_with {
+                if slice >= slices_with_extra {
+                    let mut tmp = tmp.to_vec();
+                    tmp.push(filler.clone());
+                    rv.push(Value::from(tmp));
+                    continue;
+                }
+            }
+
+            rv.push(Value::from(tmp.to_vec()));
+        }
+
+        Ok(Value::from(rv))
+    }
+
+    /// Batch items.
+    ///
+    /// This filter works pretty much like `slice` just the other way round. It
+    /// returns a list of lists with the given number of items. If you provide a
+    /// second parameter this is used to fill up missing items.
+    ///
+    /// ```jinja
+    /// <table>
+    ///   {% for row in items|batch(3, '&nbsp;') %}
+    ///   <tr>
+    ///   {% for column in row %}
+    ///     <td>{{ column }}</td>
+    ///   {% endfor %}
+    ///   </tr>
+    ///   {% endfor %}
+    /// </table>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn batch(value: Value, count: usize, fill_with: Option<Value>) -> Result<Value, Error> {
+        let mut rv = Vec::new();
+        let mut tmp = Vec::with_capacity(count);
+
+        for item in ok!(value.try_iter_owned()) {
+            if tmp.len() == count {
+                rv.push(Value::from(mem::replace(
+                    &mut tmp,
+                    Vec::with_capacity(count),
+                )));
+            }
+            tmp.push(item);
+        }
+
+        if !tmp.is_empty() {
+            if let Some(filler) = fill_with {
+                for _ in 0..count - tmp.len() {
+                    tmp.push(filler.clone());
+                }
+            }
+            rv.push(Value::from(tmp));
+        }
+
+        Ok(Value::from(rv))
+    }
+
+    /// Looks up an attribute of an object.
+    ///
+    /// Unlike the `.` operator and subscripts this only consults the attribute
+    /// of a dynamic object and never falls back to item access.  As such
+    /// `{{ map|attr("key") }}` is always undefined for plain maps even if the
+    /// map has a key of that name.  This is useful if the name of the attribute
+    /// is only known at runtime but it should not be possible to reach into
+    /// maps or sequences.
+    ///
+    /// ```jinja
+    /// {{ loop|attr("index") }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn attr(value: Value, name: Cow<'_, str>) -> Result<Value, Error> {
+        match value.0 {
+            ValueRepr::Undefined => Err(Error::from(ErrorKind::UndefinedError)),
+            ValueRepr::Dynamic(ref obj) => Ok(obj.get_attr(&name).unwrap_or(Value::UNDEFINED)),
+            _ => Ok(Value::UNDEFINED),
+        }
+    }
+
+    /// Looks up a dotted path in a value.
+    ///
+    /// The path is split on dots and every segment is looked up in turn.
+    /// Integer segments index into sequences.  If any segment cannot be found
+    /// the result is undefined rather than an error.  See
+    /// [`Value::get_path`] for the exact rules.
+    ///
+    /// ```jinja
+    /// {{ data|get_path("users.0.address.city")|default("unknown") }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn get_path(value: &Value, path: Cow<'_, str>) -> Value {
+        value.get_path(&path)
+    }
+
+    /// Returns the items of a sequence in random order.
+    ///
+    /// The optional `seed` argument makes the order deterministic.  If no seed
+    /// is given the seed configured with
+    /// [`Environment::set_random_seed`](crate::Environment::set_random_seed)
+    /// is used and otherwise the order is random.
+    ///
+    /// ```jinja
+    /// {% for tip in tips|shuffle %}{{ tip }}{% endfor %}
+    /// {{ [1, 2, 3]|shuffle(seed=42) }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn shuffle(state: &State, value: Value, seed: Option<Value>) -> Result<Value, Error> {
+        let mut rng = ok!(Rng::for_filter(state, seed));
+        let mut items = ok!(value.try_iter()).collect::<Vec<_>>();
+        for idx in (1..items.len()).rev() {
+            items.swap(idx, rng.below(idx + 1));
+        }
+        Ok(Value::from(items))
+    }
+
+    /// Returns a random item from a sequence.
+    ///
+    /// If the sequence is empty `undefined` is returned.  Like with
+    /// [`shuffle`](shuffle) an optional `seed` argument can be provided.
+    ///
+    /// ```jinja
+    /// Tip of the day: {{ tips|random }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn random(state: &State, value: Value, seed: Option<Value>) -> Result<Value, Error> {
+        let mut rng = ok!(Rng::for_filter(state, seed));
+        let items = ok!(value.try_iter()).collect::<Vec<_>>();
+        if items.is_empty() {
+            return Ok(Value::UNDEFINED);
+        }
+        Ok(items[rng.below(items.len())].clone())
+    }
+
+    /// A small random number generator (splitmix64) for `shuffle` and `random`.
+    ///
+    /// This is not suitable for cryptographic purposes.
+    struct Rng(u64);
+
+    impl Rng {
+        fn for_filter(state: &State, seed: Option<Value>) -> Result<Rng, Error> {
+            let seed = ok!(optional_arg(seed, "seed"));
+            Ok(if seed.is_undefined() || seed.is_none() {
+                match state.env().random_seed() {
+                    Some(seed) => Rng(seed),
+                    None => Rng::from_entropy(),
+                }
+            } else {
+                Rng(ok!(u64::try_from(seed)))
+            })
+        }
+
+        fn from_entropy() -> Rng {
+            use std::cell::Cell;
+            use std::collections::hash_map::RandomState;
+            use std::hash::{BuildHasher, Hasher};
+
+            thread_local! {
+                static STATE: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish());
+            }
+            STATE.with(|state| {
+                let mut rng = Rng(state.get());
+                state.set(rng.next_u64());
+                rng
+            })
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            ((self.next_u64() as u128 * n as u128) >> 64) as usize
+        }
+    }
+
+    /// Applies a filter or looks up an attribute on each item of a sequence.
+    ///
+    /// When invoked with the name of a filter, that filter is applied to each
+    /// item with the remaining arguments passed along:
+    ///
+    /// ```jinja
+    /// {{ ["a", "b"]|map("upper")|join(", ") }}
+    /// ```
+    ///
+    /// Alternatively the `attribute` keyword argument can be used to look up
+    /// an attribute of each item.  The attribute can be a dotted path and an
+    /// optional `default` keyword argument is used for items where the
+    /// attribute is undefined:
+    ///
+    /// ```jinja
+    /// {{ users|map(attribute="profile.name", default="anonymous")|join(", ") }}
+    /// ```
+    ///
+    /// Attributes that are not plain fields of an object are resolved by
+    /// calling a method of that name without arguments.  See
+    /// [`selectattr`](selectattr) for details.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn map(state: &State, value: Value, args: Rest<Value>) -> Result<Value, Error> {
+        let (args, kwargs) = split_kwargs(args);
+
+        if let Some(kwargs) = kwargs
+            .as_ref()
+            .filter(|x| matches!(x.get_attr("attribute"), Ok(x) if !x.is_undefined()))
+        {
+            if !args.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "map does not accept positional arguments with attribute",
+                ));
+            }
+            let mut attribute = None;
+            let mut default = None;
+            ok!(for_each_kwarg(kwargs, |key, value| {
+                match key {
+                    "attribute" => attribute = Some(value),
+                    "default" => default = Some(value),
+                    _ => return Ok(false),
+                }
+                Ok(true)
+            }));
+            let attribute = match attribute.as_ref().and_then(|x| x.as_str()) {
+                Some(attribute) => attribute,
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::MissingArgument,
+                        "map requires a filter name or attribute",
+                    ))
+                }
+            };
+            let attribute = AttrPath::new(attribute);
+            return Ok(LazySeq::then(value, move |state, item| {
+                let attr = ok!(attribute.resolve(state, &item));
+                Ok(Some(match default {
+                    Some(ref default) if attr.is_undefined() => default.clone(),
+                    _ => attr,
+                }))
+            }));
+        }
+
+        let (name, args) = match args.split_first() {
+            Some((name, args)) => (name, args),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::MissingArgument,
+                    "map requires a filter name or attribute",
+                ))
+            }
+        };
+        let name = match name.as_str() {
+            Some(name) => name,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "filter name must be a string",
+                ))
+            }
+        };
+        let filter = match state.env().get_filter(name) {
+            Some(filter) => filter.clone(),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::UnknownFilter,
+                    format!("filter {} is unknown", name),
+                ))
+            }
+        };
+        let args = args.iter().cloned().chain(kwargs).collect::<Vec<_>>();
+        Ok(LazySeq::then(value, move |state, item| {
+            let rv = if args.is_empty() {
+                filter.apply_to(state, std::slice::from_ref(&item))
+            } else {
+                let mut filter_args = Vec::with_capacity(args.len() + 1);
+                filter_args.push(item);
+                filter_args.extend(args.iter().cloned());
+                filter.apply_to(state, &filter_args)
+            };
+            rv.map(Some)
+        }))
+    }
+
+    /// Selects the items of a sequence by an attribute.
+    ///
+    /// Without a test the items are selected where the attribute is true.
+    /// Otherwise the named test is performed on the attribute with the
+    /// remaining arguments passed along:
+    ///
+    /// ```jinja
+    /// {{ users|selectattr("is_active")|map(attribute="name")|join(", ") }}
+    /// {{ users|selectattr("age", "odd")|map(attribute="name")|join(", ") }}
+    /// ```
+    ///
+    /// If an attribute is not a plain field of an object it is resolved by
+    /// calling a method of that name without arguments.  Fields always take
+    /// precedence so that methods are never invoked if the object has an
+    /// attribute of the same name.  Objects that do not have such a method
+    /// resolve to undefined.  Maps and sequences are never invoked.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn selectattr(state: &State, value: Value, args: Rest<Value>) -> Result<Value, Error> {
+        let (args, kwargs) = split_kwargs(args);
+        if kwargs.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "selectattr does not accept keyword arguments",
+            ));
+        }
+        let attribute = match args.first().map(|x| x.as_str()) {
+            Some(Some(attribute)) => attribute,
+            Some(None) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "attribute name must be a string",
+                ))
+            }
+            None => {
+                return Err(Error::new(
+                    ErrorKind::MissingArgument,
+                    "selectattr requires an attribute",
+                ))
+            }
+        };
+        let test = match args.get(1) {
+            None => None,
+            Some(name) => {
+                let name = match name.as_str() {
+                    Some(name) => name,
+                    None => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidOperation,
+                            "test name must be a string",
+                        ))
+                    }
+                };
+                match state.env().get_test(name) {
+                    Some(test) => Some(test.clone()),
+                    None => {
+                        return Err(Error::new(
+                            ErrorKind::UnknownTest,
+                            format!("test {} is unknown", name),
+                        ))
+                    }
+                }
+            }
+        };
+
+        let attribute = AttrPath::new(attribute);
+        let test_args = args.get(2..).unwrap_or_default().to_vec();
+        Ok(LazySeq::then(value, move |state, item| {
+            let attr = ok!(attribute.resolve(state, &item));
+            let selected = match test {
+                Some(ref test) if test_args.is_empty() => {
+                    ok!(test.perform(state, std::slice::from_ref(&attr)))
+                }
+                Some(ref test) => {
+                    let mut args = Vec::with_capacity(test_args.len() + 1);
+                    args.push(attr);
+                    args.extend(test_args.iter().cloned());
+                    ok!(test.perform(state, &args))
+                }
+                None => attr.is_true(),
+            };
+            Ok(if selected { Some(item) } else { None })
+        }))
+    }
+
+    /// Groups a sequence of objects by an attribute.
+    ///
+    /// The items are sorted by the attribute and then grouped.  Each group
+    /// exposes the shared value as `grouper` and the items of the group as
+    /// `list`.  Groups can also be unpacked into a `(grouper, list)` pair.
+    /// The attribute can be given as a dotted path and an optional `default`
+    /// keyword argument is used for items where the attribute is undefined.
+    /// Items without the attribute are grouped last.  Like with [`sort`]
+    /// grouping fails if the attribute values cannot be compared with each
+    /// other.
+    ///
+    /// ```jinja
+    /// <ul>{% for group in users|groupby("city") %}
+    ///   <li>{{ group.grouper }}: {{ group.list|map(attribute="name")|join(", ") }}
+    /// {% endfor %}</ul>
+    ///
+    /// <ul>{% for city, items in users|groupby("city") %}
+    ///   <li>{{ city }}: {{ items|length }}
+    /// {% endfor %}</ul>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn groupby(state: &State, value: Value, args: Rest<Value>) -> Result<Value, Error> {
+        let (args, kwargs) = split_kwargs(args);
+        let attribute = match args.as_slice() {
+            [attribute] => match attribute.as_str() {
+                Some(attribute) => attribute,
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        "attribute name must be a string",
+                    ))
+                }
+            },
+            [] => {
+                return Err(Error::new(
+                    ErrorKind::MissingArgument,
+                    "groupby requires an attribute",
+                ))
+            }
+            _ => return Err(Error::from(ErrorKind::TooManyArguments)),
+        };
+        let mut default = None;
+        if let Some(kwargs) = kwargs {
+            ok!(for_each_kwarg(&kwargs, |key, value| {
+                match key {
+                    "default" => default = Some(value),
+                    _ => return Ok(false),
+                }
+                Ok(true)
+            }));
+        }
+
+        let attribute = AttrPath::new(attribute);
+        let mut items = Vec::with_capacity(value.len().unwrap_or(0));
+        for item in ok!(LazySeq::iter(state, &value)) {
+            let item = ok!(item);
+            let key = ok!(attribute.resolve(state, &item));
+            let key = match default {
+                Some(ref default) if key.is_undefined() => default.clone(),
+                _ => key,
+            };
+            items.push((key, item));
+        }
+        let mut failed = None;
+        items.sort_by(|a, b| {
+            cmp_sort_keys(&a.0, &b.0).unwrap_or_else(|err| {
+                failed.get_or_insert(err);
+                Ordering::Equal
+            })
+        });
+        if let Some(err) = failed {
+            return Err(err);
+        }
+
+        let mut rv = Vec::new();
+        let mut current: Option<(Value, Vec<Value>)> = None;
+        for (key, item) in items {
+            match current {
+                Some((ref grouper, ref mut list)) if *grouper == key => list.push(item),
+                _ => {
+                    if let Some((grouper, list)) = current.take() {
+                        rv.push(Value::from_object(GroupTuple::new(grouper, list)));
+                    }
+                    current = Some((key, vec![item]));
+                }
+            }
+        }
+        if let Some((grouper, list)) = current {
+            rv.push(Value::from_object(GroupTuple::new(grouper, list)));
+        }
+        Ok(Value::from(rv))
+    }
+
+    /// A group produced by the `groupby` filter.
+    #[derive(Debug)]
+    struct GroupTuple {
+        grouper: Value,
+        list: Value,
+    }
+
+    impl GroupTuple {
+        fn new(grouper: Value, list: Vec<Value>) -> GroupTuple {
+            GroupTuple {
+                grouper,
+                list: Value::from(list),
+            }
+        }
+    }
+
+    impl fmt::Display for GroupTuple {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "[{:?}, {}]", self.grouper, self.list)
+        }
+    }
+
+    impl Object for GroupTuple {
+        fn get_attr(&self, name: &str) -> Option<Value> {
+            match name {
+                "grouper" => Some(self.grouper.clone()),
+                "list" => Some(self.list.clone()),
+                _ => None,
             }
-            ValueRepr::Seq(_) => Ok(value.clone()),
-            ValueRepr::Map(ref m, _) => Ok(Value::from(
-                m.iter()
-                    .map(|x| Value::from(x.0.clone()))
-                    .collect::<Vec<_>>(),
-            )),
-            _ => Err(Error::new(
-                ErrorKind::InvalidOperation,
-                "cannot convert value to list",
-            )),
+        }
+
+        fn attributes(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+            Box::new(["grouper", "list"].into_iter())
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = Value> + '_> {
+            Box::new([self.grouper.clone(), self.list.clone()].into_iter())
         }
     }
 
-    /// Converts the value into a boolean value.
-    ///
-    /// This behaves the same as the if statement does with regards to
-    /// handling of boolean values.
-    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn bool(value: Value) -> bool {
-        value.is_true()
+    /// Splits off trailing keyword arguments.
+    fn split_kwargs(mut args: Rest<Value>) -> (Vec<Value>, Option<Value>) {
+        let kwargs = match args.last() {
+            Some(last) if last.is_kwargs() => args.pop(),
+            _ => None,
+        };
+        (args.0, kwargs)
     }
 
-    /// Slice an iterable and return a list of lists containing
-    /// those items.
-    ///
-    /// Useful if you want to create a div containing three ul tags that
-    /// represent columns:
-    ///
-    /// ```jinja
-    /// <div class="columnwrapper">
-    /// {% for column in items|slice(3) %}
-    ///   <ul class="column-{{ loop.index }}">
-    ///   {% for item in column %}
-    ///     <li>{{ item }}</li>
-    ///   {% endfor %}
-    ///   </ul>
-    /// {% endfor %}
-    /// </div>
-    /// ```
+    type LazyStep = dyn Fn(&State, Value) -> Result<Option<Value>, Error> + Sync + Send;
+
+    /// A sequence whose items are filtered and mapped on iteration.
     ///
-    /// If you pass it a second argument it’s used to fill missing values on the
-    /// last iteration.
-    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn slice(value: Value, count: usize, fill_with: Option<Value>) -> Result<Value, Error> {
-        if count == 0 {
-            return Err(Error::new(ErrorKind::InvalidOperation, "count cannot be 0"));
+    /// [`map`] and [`selectattr`] return this so that a chain of sequence
+    /// filters runs every item through all steps at once instead of building
+    /// a list for every filter of the chain.  Lazy sequences never escape the
+    /// filters: the result of a filter is collected into a list unless it's
+    /// passed on to another filter and filters that do not iterate over lazy
+    /// sequences themselves get a list as well.
+    pub(crate) struct LazySeq {
+        source: Value,
+        steps: Vec<Arc<LazyStep>>,
+    }
+
+    impl LazySeq {
+        /// Adds a step to the items of a value.
+        ///
+        /// The step returns the new item or `None` to skip the item.
+        fn then<F>(value: Value, step: F) -> Value
+        where
+            F: Fn(&State, Value) -> Result<Option<Value>, Error> + Sync + Send + 'static,
+        {
+            let (source, mut steps) = match value.downcast_object_ref::<LazySeq>() {
+                Some(seq) => (seq.source.clone(), seq.steps.clone()),
+                None => (value, Vec::new()),
+            };
+            steps.push(Arc::new(step));
+            Value::from_object(LazySeq { source, steps })
         }
-        let items = ok!(value.try_iter_owned()).collect::<Vec<_>>();
-        let len = items.len();
-        let items_per_slice = len / count;
-        let slices_with_extra = len % count;
-        let mut offset = 0;
-        let mut rv = Vec::new();
 
-        for slice in 0..count {
-            let start = offset + slice * items_per_slice;
-            if slice < slices_with_extra {
-                offset += 1;
-            }
-            let end = offset + (slice + 1) * items_per_slice;
-            let tmp = &items[start..end];
+        /// Iterates over the items of a value, running the steps of lazy sequences.
+        fn iter<'a>(
+            state: &'a State,
+            value: &'a Value,
+        ) -> Result<Box<dyn Iterator<Item = Result<Value, Error>> + 'a>, Error> {
+            let seq = match value.downcast_object_ref::<LazySeq>() {
+                Some(seq) => seq,
+                None => return Ok(Box::new(ok!(value.try_iter()).map(Ok))),
+            };
+            Ok(Box::new(ok!(seq.source.try_iter()).filter_map(
+                move |mut item| {
+                    for step in &seq.steps {
+                        match step(state, item) {
+                            Ok(Some(next)) => item = next,
+                            Ok(None) => return None,
+                            Err(err) => return Some(Err(err)),
+                        }
+                    }
+                    Some(Ok(item))
+                },
+            )))
+        }
 
-            if let Some(ref filler) = fill_with {
-                if slice >= slices_with_extra {
-                    let mut tmp = tmp.to_vec();
-                    tmp.push(filler.clone());
-                    rv.push(Value::from(tmp));
-                    continue;
-                }
+        /// Checks if a value is a lazy sequence.
+        pub(crate) fn is_lazy(value: &Value) -> bool {
+            value.downcast_object_ref::<LazySeq>().is_some()
+        }
+
+        /// Collects a lazy sequence into a list.  Other values are returned
+        /// unchanged.
+        pub(crate) fn collect(state: &State, value: Value) -> Result<Value, Error> {
+            if !LazySeq::is_lazy(&value) {
+                return Ok(value);
             }
+            let items = ok!(ok!(LazySeq::iter(state, &value)).collect::<Result<Vec<_>, _>>());
+            Ok(Value::from(items))
+        }
+    }
 
-            rv.push(Value::from(tmp.to_vec()));
+    impl fmt::Debug for LazySeq {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("LazySeq")
+                .field("source", &self.source)
+                .field("steps", &self.steps.len())
+                .finish()
         }
+    }
 
-        Ok(Value::from(rv))
+    impl fmt::Display for LazySeq {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "<lazy sequence>")
+        }
     }
 
-    /// Batch items.
-    ///
-    /// This filter works pretty much like `slice` just the other way round. It
-    /// returns a list of lists with the given number of items. If you provide a
-    /// second parameter this is used to fill up missing items.
+    impl Object for LazySeq {}
+
+    /// A dotted attribute path for `map`, `selectattr`, `groupby` and `sort`.
     ///
-    /// ```jinja
-    /// <table>
-    ///   {% for row in items|batch(3, '&nbsp;') %}
-    ///   <tr>
-    ///   {% for column in row %}
-    ///     <td>{{ column }}</td>
-    ///   {% endfor %}
-    ///   </tr>
-    ///   {% endfor %}
-    /// </table>
-    /// ```
-    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn batch(value: Value, count: usize, fill_with: Option<Value>) -> Result<Value, Error> {
-        let mut rv = Vec::new();
-        let mut tmp = Vec::with_capacity(count);
+    /// The path is split once up front so that resolving it for every item of
+    /// a sequence does not have to parse it again.
+    struct AttrPath {
+        segments: Vec<(String, Option<Value>)>,
+    }
 
-        for item in ok!(value.try_iter_owned()) {
-            if tmp.len() == count {
-                rv.push(Value::from(mem::replace(
-                    &mut tmp,
-                    Vec::with_capacity(count),
-                )));
+    impl AttrPath {
+        fn new(path: &str) -> AttrPath {
+            AttrPath {
+                segments: path
+                    .split('.')
+                    .map(|segment| {
+                        (
+                            segment.to_string(),
+                            segment.parse::<i64>().ok().map(Value::from),
+                        )
+                    })
+                    .collect(),
             }
-            tmp.push(item);
         }
 
-        if !tmp.is_empty() {
-            if let Some(filler) = fill_with {
-                for _ in 0..count - tmp.len() {
-                    tmp.push(filler.clone());
+        fn resolve(&self, state: &State, value: &Value) -> Result<Value, Error> {
+            let mut rv = value.clone();
+            for (segment, idx) in &self.segments {
+                if rv.is_undefined() {
+                    break;
                 }
+                let attr = match idx {
+                    Some(idx) => ok!(rv.get_item(idx)),
+                    None => ok!(rv.get_attr(segment)),
+                };
+                rv = if attr.is_undefined() && matches!(rv.0, ValueRepr::Dynamic(_)) {
+                    match rv.call_method(state, segment, &[]) {
+                        Ok(rv) => rv,
+                        Err(err) if err.kind() == ErrorKind::UnknownMethod => Value::UNDEFINED,
+                        Err(err) => return Err(err),
+                    }
+                } else {
+                    attr
+                };
             }
-            rv.push(Value::from(tmp));
+            Ok(rv)
         }
-
-        Ok(Value::from(rv))
     }
 
     /// Dumps a value to JSON.
@@ -725,18 +2446,74 @@ mod builtins {
     /// </script>
     /// <a href="#" data-info='{{ json_object|tojson }}'>...</a>
     /// ```
+    ///
+    /// Alternatively the `mode` keyword argument can be set to `"attr"` in
+    /// which case the JSON is HTML escaped for use in single or double quoted
+    /// HTML attributes.  The default mode is `"script"`.  Pretty printing can
+    /// also be enabled with the `pretty` keyword argument:
+    ///
+    /// ```jinja
+    /// <div data-config="{{ config|tojson(mode="attr") }}"></div>
+    /// ```
+    ///
+    /// JSON cannot represent `NaN` and infinite floats.  By default they are
+    /// emitted as `null`.  If the `allow_nan` keyword argument is set to `false`
+    /// the filter fails instead.
     #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "json"))))]
     #[cfg(feature = "json")]
-    pub fn tojson(value: Value, pretty: Option<bool>) -> Result<Value, Error> {
-        if pretty.unwrap_or(false) {
-            serde_json::to_string_pretty(&value)
+    pub fn tojson(
+        value: Value,
+        args: Option<Value>,
+        kwargs: Option<Value>,
+    ) -> Result<Value, Error> {
+        let mut pretty = false;
+        let mut attr_mode = false;
+        let mut allow_nan = true;
+        for arg in args.iter().chain(kwargs.iter()) {
+            if !arg.is_kwargs() {
+                pretty = arg.is_true();
+                continue;
+            }
+            ok!(for_each_kwarg(arg, |key, value| {
+                match key {
+                    "pretty" => pretty = value.is_true(),
+                    "allow_nan" => allow_nan = value.is_true(),
+                    "mode" => {
+                        attr_mode = match value.as_str() {
+                            Some("script") => false,
+                            Some("attr") => true,
+                            _ => {
+                                return Err(Error::new(
+                                    ErrorKind::InvalidOperation,
+                                    format!("invalid tojson mode {}", value),
+                                ))
+                            }
+                        }
+                    }
+                    _ => return Ok(false),
+                }
+                Ok(true)
+            }));
+        }
+
+        if !allow_nan && has_non_finite_float(&value) {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "cannot serialize NaN or infinite float to JSON",
+            ));
+        }
+
+        if pretty {
+            crate::value::to_json_pretty(&value)
         } else {
-            serde_json::to_string(&value)
+            crate::value::to_json(&value)
         }
-        .map_err(|err| {
-            Error::new(ErrorKind::InvalidOperation, "cannot serialize to JSON").with_source(err)
-        })
         .map(|s| {
+            if attr_mode {
+                // HTML escaping is undone by the browser before the attribute
+                // value is read, so this yields the original JSON.
+                return Value::from_safe_string(HtmlEscape(&s).to_string());
+            }
             // When this filter is used the return value is safe for both HTML and JSON
             let mut rv = String::with_capacity(s.len());
             for c in s.chars() {
@@ -752,6 +2529,306 @@ mod builtins {
         })
     }
 
+    /// Parses a JSON string into a value.
+    ///
+    /// This filter is only available if the `json` feature is enabled.  It is
+    /// the counterpart to [`tojson`]: objects become maps, arrays become lists
+    /// and `null` becomes `none`.  If the string is not valid JSON the filter
+    /// fails with an error that points to the line and column of the problem.
+    ///
+    /// ```jinja
+    /// {% set data = raw_json|fromjson %}
+    /// {{ data.user.name }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "json"))))]
+    #[cfg(feature = "json")]
+    pub fn fromjson(value: Cow<'_, str>) -> Result<Value, Error> {
+        serde_json::from_str::<serde_json::Value>(&value)
+            .map(|parsed| Value::from_serializable(&parsed))
+            .map_err(|err| {
+                Error::new(
+                    ErrorKind::CannotDeserialize,
+                    format!("cannot parse JSON: {}", err),
+                )
+                .with_source(err)
+            })
+    }
+
+    /// Checks if a value contains floats that JSON cannot represent.
+    #[cfg(feature = "json")]
+    fn has_non_finite_float(value: &Value) -> bool {
+        match value.0 {
+            ValueRepr::F64(x) => !x.is_finite(),
+            ValueRepr::Seq(ref items) => items.iter().any(has_non_finite_float),
+            ValueRepr::Map(ref items, _) => items.values().any(has_non_finite_float),
+            // objects are checked the same way they are serialized
+            ValueRepr::Dynamic(ref obj) => {
+                if let Some(mut pairs) = obj.pairs() {
+                    pairs.any(|(_, value)| has_non_finite_float(&value))
+                } else if obj.len().is_some() && obj.attributes().next().is_none() {
+                    obj.iter().any(|item| has_non_finite_float(&item))
+                } else {
+                    obj.attributes()
+                        .filter_map(|attr| obj.get_attr(attr))
+                        .any(|value| has_non_finite_float(&value))
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Dumps a value to YAML.
+    ///
+    /// This filter is only available if the `yaml` feature is enabled.  Maps
+    /// are emitted in the order they are iterated, so with the `preserve_order`
+    /// feature the original insertion order is kept.  Strings that YAML would
+    /// otherwise read as a different type (such as `"yes"` or `"1.0"`) are
+    /// quoted.  The trailing newline is removed.
+    ///
+    /// The optional `indent` parameter indents all lines but the first by the
+    /// given number of spaces which makes it possible to embed the output into
+    /// an already indented YAML document:
+    ///
+    /// ```jinja
+    /// services:
+    ///   web:
+    ///     {{ web_config|toyaml(indent=4) }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "yaml"))))]
+    #[cfg(feature = "yaml")]
+    pub fn toyaml(state: &State, value: Value, indent: Option<Value>) -> Result<String, Error> {
+        let indent = ok!(optional_arg(indent, "indent"));
+        let indent = if indent.is_undefined() {
+            0
+        } else {
+            ok!(usize::try_from(indent))
+        };
+        let lines = ok!(yaml::to_lines(&value));
+        let size = lines.iter().try_fold(lines.len(), |size, line| {
+            size.checked_add(line.len())?.checked_add(indent)
+        });
+        let mut rv = String::with_capacity(ok!(check_generated_size(state, size)));
+        for (idx, line) in lines.iter().enumerate() {
+            if idx > 0 {
+                rv.push('\n');
+                if !line.is_empty() {
+                    rv.extend(std::iter::repeat(' ').take(indent));
+                }
+            }
+            rv.push_str(line);
+        }
+        Ok(rv)
+    }
+
+    #[cfg(feature = "yaml")]
+    mod yaml {
+        use std::fmt::Write;
+
+        use crate::error::{Error, ErrorKind};
+        use crate::value::{Value, ValueRepr};
+
+        /// Strings that YAML 1.1 parsers read as booleans.  serde_yaml emits
+        /// YAML 1.2 which leaves them unquoted, so they are quoted here.
+        fn is_ambiguous(s: &str) -> bool {
+            matches!(
+                s,
+                "y" | "Y"
+                    | "yes"
+                    | "Yes"
+                    | "YES"
+                    | "n"
+                    | "N"
+                    | "no"
+                    | "No"
+                    | "NO"
+                    | "on"
+                    | "On"
+                    | "ON"
+                    | "off"
+                    | "Off"
+                    | "OFF"
+            )
+        }
+
+        enum Block {
+            Scalar,
+            Map,
+            Seq,
+        }
+
+        fn serialize<T: serde::Serialize + ?Sized>(value: &T) -> Result<String, Error> {
+            let mut rv = ok!(serde_yaml::to_string(value).map_err(|err| {
+                Error::new(ErrorKind::InvalidOperation, "cannot serialize to YAML").with_source(err)
+            }));
+            if rv.ends_with('\n') {
+                rv.pop();
+            }
+            Ok(rv)
+        }
+
+        /// Emits a string as double quoted scalar.
+        fn quote(s: &str) -> String {
+            let mut rv = String::with_capacity(s.len() + 2);
+            rv.push('"');
+            for c in s.chars() {
+                match c {
+                    '"' => rv.push_str("\\\""),
+                    '\\' => rv.push_str("\\\\"),
+                    '\n' => rv.push_str("\\n"),
+                    '\r' => rv.push_str("\\r"),
+                    '\t' => rv.push_str("\\t"),
+                    c if c.is_control() => write!(rv, "\\x{:02x}", c as u32).unwrap(),
+                    c => rv.push(c),
+                }
+            }
+            rv.push('"');
+            rv
+        }
+
+        fn scalar(value: &Value) -> Result<String, Error> {
+            match value.as_str() {
+                Some(s) if is_ambiguous(s) => Ok(quote(s)),
+                Some(s) => serialize(s),
+                None => serialize(value),
+            }
+        }
+
+        fn key(value: &Value) -> Result<String, Error> {
+            let rv = ok!(scalar(value));
+            if rv.contains('\n') {
+                Ok(quote(&value.to_string()))
+            } else {
+                Ok(rv)
+            }
+        }
+
+        fn push_entry(lines: &mut Vec<String>, key: String, value: &Value) -> Result<(), Error> {
+            let (child, block) = ok!(emit(value));
+            let mut child = child.into_iter();
+            match block {
+                Block::Scalar => {
+                    lines.push(format!("{}: {}", key, child.next().unwrap_or_default()));
+                    lines.extend(child);
+                }
+                // sequences in maps are not indented
+                Block::Seq => {
+                    lines.push(format!("{}:", key));
+                    lines.extend(child);
+                }
+                Block::Map => {
+                    lines.push(format!("{}:", key));
+                    lines.extend(child.map(|line| indented(&line)));
+                }
+            }
+            Ok(())
+        }
+
+        fn push_item(lines: &mut Vec<String>, value: &Value) -> Result<(), Error> {
+            let (child, _) = ok!(emit(value));
+            for (idx, line) in child.into_iter().enumerate() {
+                lines.push(if idx == 0 {
+                    format!("- {}", line)
+                } else {
+                    indented(&line)
+                });
+            }
+            Ok(())
+        }
+
+        fn indented(line: &str) -> String {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("  {}", line)
+            }
+        }
+
+        fn emit(value: &Value) -> Result<(Vec<String>, Block), Error> {
+            let mut lines = Vec::new();
+            let block = match value.0 {
+                ValueRepr::Seq(ref items) if !items.is_empty() => {
+                    for item in items.iter() {
+                        ok!(push_item(&mut lines, item));
+                    }
+                    Block::Seq
+                }
+                ValueRepr::Map(ref entries, _) if !entries.is_empty() => {
+                    for (k, v) in entries.iter() {
+                        ok!(push_entry(&mut lines, ok!(key(&Value::from(k.clone()))), v));
+                    }
+                    Block::Map
+                }
+                ValueRepr::Dynamic(ref obj) => {
+                    let block = if let Some(pairs) = obj.pairs() {
+                        for (k, v) in pairs {
+                            ok!(push_entry(&mut lines, ok!(key(&k)), &v));
+                        }
+                        Block::Map
+                    } else if obj.len().is_some() && obj.attributes().next().is_none() {
+                        for item in obj.iter() {
+                            ok!(push_item(&mut lines, &item));
+                        }
+                        Block::Seq
+                    } else {
+                        for k in obj.attributes() {
+                            let v = obj.get_attr(k).unwrap_or(Value::UNDEFINED);
+                            ok!(push_entry(&mut lines, ok!(key(&Value::from(k))), &v));
+                        }
+                        Block::Map
+                    };
+                    // empty objects are emitted as flow collections
+                    if lines.is_empty() {
+                        return Ok((vec![ok!(serialize(value))], Block::Scalar));
+                    }
+                    block
+                }
+                _ => {
+                    let s = ok!(scalar(value));
+                    lines.extend(s.split('\n').map(|x| x.to_string()));
+                    Block::Scalar
+                }
+            };
+            Ok((lines, block))
+        }
+
+        /// Emits a value as YAML block document split into lines.
+        pub fn to_lines(value: &Value) -> Result<Vec<String>, Error> {
+            emit(value).map(|(lines, _)| lines)
+        }
+    }
+
+    /// Replaces matches of a regular expression.
+    ///
+    /// This filter is only available if the `regex` feature is enabled.  The
+    /// pattern uses the syntax of the [`regex`](https://docs.rs/regex) crate.
+    /// The replacement can refer to capture groups with `$1` or `${name}`.
+    /// By default all matches are replaced, the optional `count` parameter
+    /// limits the number of replacements.
+    ///
+    /// ```jinja
+    /// {{ "Order 42 of 100"|regex_replace("\\d+", "#") }}
+    ///   -> Order # of #
+    /// {{ "John Smith"|regex_replace("(\\w+) (\\w+)", "$2, $1") }}
+    ///   -> Smith, John
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "regex"))))]
+    #[cfg(feature = "regex")]
+    pub fn regex_replace(
+        value: Cow<'_, str>,
+        pattern: Cow<'_, str>,
+        repl: Cow<'_, str>,
+        count: Option<Value>,
+    ) -> Result<String, Error> {
+        let count = ok!(optional_arg(count, "count"));
+        let count = if count.is_undefined() {
+            0
+        } else {
+            ok!(usize::try_from(count))
+        };
+        let re = ok!(crate::utils::get_regex(&pattern));
+        Ok(re.replacen(&value, count, &repl as &str).into_owned())
+    }
+
     /// URL encodes a value.
     ///
     /// If given a map it encodes the parameters into a query set, otherwise it
@@ -796,6 +2873,84 @@ mod builtins {
         }
     }
 
+    /// Formats a date and time.
+    ///
+    /// The value can be the return value of `now()` or a unix timestamp.  The
+    /// format string uses `strftime` style specifiers: `%Y`, `%y`, `%m`, `%d`,
+    /// `%e`, `%j`, `%H`, `%I`, `%M`, `%S`, `%f`, `%p`, `%a`, `%A`, `%u`, `%b`,
+    /// `%B`, `%F`, `%T`, `%s`, `%z`, `%Z` and `%%`.  The time is shown in UTC
+    /// unless a different time zone is given with the `tz` argument, which can
+    /// also be passed as keyword argument.  Only `UTC` and fixed offsets such
+    /// as `+02:00` are supported as time zones.
+    ///
+    /// ```jinja
+    /// <p>Generated at {{ now()|datetimeformat("%H:%M") }}
+    /// <p>Posted on {{ post.created|datetimeformat("%d %B %Y", tz="+01:00") }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "time"))))]
+    #[cfg(feature = "time")]
+    pub fn datetimeformat(
+        value: Value,
+        format: Cow<'_, str>,
+        tz: Option<Value>,
+    ) -> Result<String, Error> {
+        let (datetime, offset) = ok!(to_datetime("datetimeformat", &value, tz));
+        datetime.format(&format, offset)
+    }
+
+    /// Formats a date and time with a Django style format string.
+    ///
+    /// This accepts the same values and `tz` argument as [`datetimeformat`]
+    /// but uses the format characters of Django's `date` filter such as `j`,
+    /// `N`, `Y`, `H` and `i`.  The default format is `"N j, Y"`.  A backslash
+    /// escapes a format character.  This filter is registered by
+    /// [`Environment::new_django_compat`](crate::Environment::new_django_compat).
+    ///
+    /// ```jinja
+    /// <p>Posted on {{ post.created|date("D j M Y") }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "time"))))]
+    #[cfg(feature = "time")]
+    pub fn date(
+        value: Value,
+        format: Option<Cow<'_, str>>,
+        tz: Option<Value>,
+    ) -> Result<String, Error> {
+        let (datetime, offset) = ok!(to_datetime("date", &value, tz));
+        Ok(datetime.format_django(format.as_deref().unwrap_or("N j, Y"), offset))
+    }
+
+    /// Converts the value of a date filter and its time zone argument.
+    #[cfg(feature = "time")]
+    fn to_datetime(
+        filter: &str,
+        value: &Value,
+        tz: Option<Value>,
+    ) -> Result<(crate::datetime::DateTime, i32), Error> {
+        use crate::datetime::{parse_tz, DateTime};
+        use crate::value::ops::as_f64;
+
+        let datetime = match value.downcast_object_ref::<DateTime>() {
+            Some(datetime) => *datetime,
+            None => match as_f64(value).filter(|_| value.kind() == ValueKind::Number) {
+                Some(ts) => ok!(DateTime::from_timestamp(ts)),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        format!("{} expects a datetime or a timestamp", filter),
+                    ))
+                }
+            },
+        };
+        let tz = ok!(optional_arg(tz, "tz"));
+        let offset = if tz.is_undefined() || tz.is_none() {
+            0
+        } else {
+            ok!(parse_tz(&tz.to_cowstr()))
+        };
+        Ok((datetime, offset))
+    }
+
     #[test]
     fn test_basics() {
         fn test(a: u32, b: u32) -> Result<u32, Error> {
@@ -215,6 +215,10 @@ impl fmt::Display for BoxedFunction {
 }
 
 impl Object for BoxedFunction {
+    fn is_callable(&self) -> bool {
+        true
+    }
+
     fn call(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
         self.invoke(state, args)
     }
@@ -225,9 +229,11 @@ mod builtins {
     use super::*;
 
     use std::collections::BTreeMap;
+    use std::sync::atomic::{self, AtomicBool, AtomicUsize};
 
     use crate::error::ErrorKind;
-    use crate::value::ValueKind;
+    use crate::filters::optional_arg;
+    use crate::value::{Rest, ValueKind};
 
     /// Returns a range.
     ///
@@ -278,19 +284,210 @@ mod builtins {
         }
     }
 
+    /// Creates an object that cycles through the given values.
+    ///
+    /// Unlike `loop.cycle` the cycler is not bound to a single loop which makes
+    /// it possible to keep cycling across multiple loops.  `current` is the
+    /// current value, `next()` returns the current value and advances to the
+    /// next one and `reset()` starts over from the first value.
+    ///
+    /// ```jinja
+    /// {% set row_class = cycler("odd", "even") %}
+    /// {% for user in users %}
+    ///   <li class="{{ row_class.next() }}">{{ user.name }}</li>
+    /// {% endfor %}
+    /// {% for group in groups %}
+    ///   <li class="{{ row_class.next() }}">{{ group.name }}</li>
+    /// {% endfor %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn cycler(items: Rest<Value>) -> Result<Value, Error> {
+        if items.is_empty() {
+            return Err(Error::new(
+                ErrorKind::MissingArgument,
+                "cycler requires at least one value",
+            ));
+        }
+        Ok(Value::from_object(Cycler {
+            items: items.0,
+            pos: AtomicUsize::new(0),
+        }))
+    }
+
+    #[derive(Debug)]
+    struct Cycler {
+        items: Vec<Value>,
+        pos: AtomicUsize,
+    }
+
+    impl Cycler {
+        fn current(&self) -> Value {
+            self.items[self.pos.load(atomic::Ordering::Relaxed)].clone()
+        }
+    }
+
+    impl fmt::Display for Cycler {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "<cycler {}>", self.current())
+        }
+    }
+
+    impl Object for Cycler {
+        fn get_attr(&self, name: &str) -> Option<Value> {
+            match name {
+                "current" => Some(self.current()),
+                _ => None,
+            }
+        }
+
+        fn attributes(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+            Box::new(Some("current").into_iter())
+        }
+
+        fn call_method(&self, _state: &State, name: &str, args: &[Value]) -> Result<Value, Error> {
+            match name {
+                "next" | "reset" if !args.is_empty() => {
+                    Err(Error::from(ErrorKind::TooManyArguments))
+                }
+                "next" => {
+                    let len = self.items.len();
+                    let pos = self
+                        .pos
+                        .fetch_update(atomic::Ordering::Relaxed, atomic::Ordering::Relaxed, |x| {
+                            Some((x + 1) % len)
+                        })
+                        .unwrap();
+                    Ok(self.items[pos].clone())
+                }
+                "reset" => {
+                    self.pos.store(0, atomic::Ordering::Relaxed);
+                    Ok(Value::UNDEFINED)
+                }
+                _ => Err(Error::new(
+                    ErrorKind::UnknownMethod,
+                    format!("cycler has no method named {}", name),
+                )),
+            }
+        }
+    }
+
+    /// Creates a callable that joins sections with a separator.
+    ///
+    /// Calling the joiner returns an empty string the first time and the
+    /// separator (`", "` by default) every time after.  This makes it easy
+    /// to separate items that are conditionally rendered.
+    ///
+    /// ```jinja
+    /// {% set pipe = joiner(" | ") %}
+    /// {% if categories %}{{ pipe() }}Categories: {{ categories|join(", ") }}{% endif %}
+    /// {% if author %}{{ pipe() }}Author: {{ author }}{% endif %}
+    /// {% if can_edit %}{{ pipe() }}<a href="?action=edit">Edit</a>{% endif %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn joiner(sep: Option<Value>) -> Result<Value, Error> {
+        let sep = ok!(optional_arg(sep, "sep"));
+        let sep = if sep.is_undefined() {
+            Value::from(", ")
+        } else {
+            sep
+        };
+        Ok(Value::from_object(Joiner {
+            sep,
+            used: AtomicBool::new(false),
+        }))
+    }
+
+    #[derive(Debug)]
+    struct Joiner {
+        sep: Value,
+        used: AtomicBool,
+    }
+
+    impl fmt::Display for Joiner {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "<joiner {:?}>", self.sep)
+        }
+    }
+
+    impl Object for Joiner {
+        fn call(&self, _state: &State, args: &[Value]) -> Result<Value, Error> {
+            if !args.is_empty() {
+                return Err(Error::from(ErrorKind::TooManyArguments));
+            }
+            if self.used.swap(true, atomic::Ordering::Relaxed) {
+                Ok(self.sep.clone())
+            } else {
+                Ok(Value::from(""))
+            }
+        }
+    }
+
+    /// Returns the current time.
+    ///
+    /// The returned value exposes `year`, `month`, `day`, `hour`, `minute`,
+    /// `second`, `microsecond`, `weekday` (`0` is Monday) and `timestamp`
+    /// attributes and can be formatted with the
+    /// [`datetimeformat`](crate::filters::datetimeformat) filter.  MiniJinja
+    /// has no knowledge of the local time zone, so the attributes are in UTC
+    /// the same as for `utcnow()`.  The clock can be replaced with
+    /// [`Environment::set_clock`](crate::Environment::set_clock).
+    ///
+    /// ```jinja
+    /// <footer>&copy; {{ now().year }}</footer>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "time"))))]
+    #[cfg(feature = "time")]
+    pub fn now(state: &State) -> Value {
+        Value::from_object(crate::datetime::DateTime::from_system_time(
+            state.env().now(),
+        ))
+    }
+
+    /// Returns the current time in UTC.
+    ///
+    /// This is the same as `now()`.
+    ///
+    /// ```jinja
+    /// <time>{{ utcnow()|datetimeformat("%Y-%m-%dT%H:%M:%SZ") }}</time>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "time"))))]
+    #[cfg(feature = "time")]
+    pub fn utcnow(state: &State) -> Value {
+        now(state)
+    }
+
     /// Outputs the current context stringified.
     ///
     /// This is a useful function to quickly figure out the state of affairs
     /// in a template.  It emits a stringified debug dump of the current
-    /// engine state including the layers of the context, the current block
-    /// and auto escaping setting.
+    /// engine state including the variables in scope (as returned by
+    /// [`State::dump_locals`]), the current block, the auto escaping setting
+    /// and the names of the available filters and tests.  It never fails,
+    /// not even with [`SemiStrict`](crate::UndefinedBehavior::SemiStrict)
+    /// undefined behavior.
     ///
     /// ```jinja
     /// <pre>{{ debug() }}</pre>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn debug(state: &State) -> String {
-        format!("{:#?}", state)
+        struct Dump<'a, 'vm, 'env>(&'a State<'vm, 'env>);
+
+        impl<'a, 'vm, 'env> fmt::Debug for Dump<'a, 'vm, 'env> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let state = self.0;
+                let mut ds = f.debug_struct("State");
+                ds.field("name", &state.name());
+                ds.field("current_block", &state.current_block());
+                ds.field("auto_escape", &state.auto_escape());
+                ds.field("locals", &state.dump_locals());
+                ds.field("filters", &state.env().filters().collect::<Vec<_>>());
+                ds.field("tests", &state.env().tests().collect::<Vec<_>>());
+                ds.finish()
+            }
+        }
+
+        format!("{:#?}", Dump(state))
     }
 }
 
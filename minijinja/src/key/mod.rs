@@ -95,7 +95,7 @@ impl<'a> Key<'a> {
                 // in Jinja division is always a division resuling
                 // in floating point values (4 / 2 == 2.0).
                 let intval = x as i64;
-                if intval as f64 == x {
+                if intval as f64 == x && x < i64::MAX as f64 {
                     Ok(Key::I64(intval))
                 } else {
                     Err(ErrorKind::NonKey.into())
@@ -191,6 +191,11 @@ pub mod key_interning {
 
     use crate::utils::OnDrop;
 
+    /// The maximum number of keys that are interned per thread.  Once the
+    /// cache is full further keys are allocated individually until the
+    /// cache is cleared at the end of the outermost evaluation.
+    const MAX_INTERNED_KEYS: usize = 4096;
+
     thread_local! {
         static STRING_KEY_CACHE: RefCell<HashSet<CachedKey<'static>>> = Default::default();
         static STRING_KEY_CACHE_DEPTH: AtomicUsize = AtomicUsize::new(0);
@@ -252,6 +257,7 @@ pub mod key_interning {
             let mut set = cache.borrow_mut();
             match set.get(&CachedKey::Ref(s)) {
                 Some(CachedKey::Stored(s)) => s.clone(),
+                None if set.len() >= MAX_INTERNED_KEYS => Arc::new(String::from(s)),
                 None => {
                     let rv = Arc::new(String::from(s));
                     set.insert(CachedKey::Stored(rv.clone()));
@@ -284,6 +290,23 @@ pub mod key_interning {
             }
         }
     }
+
+    #[test]
+    fn test_key_interning_bounded() {
+        with(|| {
+            for idx in 0..MAX_INTERNED_KEYS + 10 {
+                try_intern(&idx.to_string());
+            }
+            let a = try_intern("overflow");
+            let b = try_intern("overflow");
+            assert!(!Arc::ptr_eq(&a, &b));
+            let a = try_intern("0");
+            let b = try_intern("0");
+            assert!(Arc::ptr_eq(&a, &b));
+            STRING_KEY_CACHE.with(|cache| assert_eq!(cache.borrow().len(), MAX_INTERNED_KEYS));
+        });
+        STRING_KEY_CACHE.with(|cache| assert!(cache.borrow().is_empty()));
+    }
 }
 
 #[test]
@@ -156,9 +156,14 @@
 //! - `source`: enables the `Source` type which helps with dynamic loading of templates.
 //! - `speedups`: enables all speedups, in particular it turns on the `v_htmlescape` dependency
 //!   for faster HTML escapling.  This also turns on `key_interning` automatically.
-//! - `json`: When enabled the `tojson` filter is added as builtin filter as well as
-//!   the ability to auto escape via `AutoEscape::Json`.
+//! - `json`: When enabled the `tojson` and `fromjson` filters are added as builtin
+//!   filters as well as the ability to auto escape via `AutoEscape::Json`.
+//! - `yaml`: When enabled the `toyaml` filter is added as builtin filter.
 //! - `urlencode`: When enabled the `urlencode` filter is added as builtin filter.
+//! - `time`: When enabled the `now()` and `utcnow()` functions as well as the
+//!   `datetimeformat` filter are added as builtins.
+//! - `regex`: When enabled the `regex_replace` filter as well as the `matching`
+//!   and `search` tests are added as builtins.
 //! - `preserve_order`: When enable the internal value implementation uses an indexmap
 //!   which preserves the original order of maps and structs.
 //!
@@ -195,13 +200,16 @@ mod source;
 #[cfg(feature = "debug")]
 mod debug;
 
+#[cfg(all(feature = "builtins", feature = "time"))]
+mod datetime;
+
 pub use self::defaults::{default_auto_escape_callback, escape_formatter};
 pub use self::environment::Environment;
 pub use self::error::{Error, ErrorKind};
 pub use self::expression::Expression;
 pub use self::output::Output;
 pub use self::template::Template;
-pub use self::utils::{AutoEscape, HtmlEscape};
+pub use self::utils::{AutoEscape, HtmlEscape, UndefinedBehavior};
 
 #[cfg(feature = "source")]
 pub use self::source::Source;
@@ -227,9 +235,17 @@ pub mod machinery {
     pub use crate::template::CompiledTemplate;
     pub use crate::vm::Vm;
 
-    use crate::Output;
+    use crate::{Output, Template};
 
     pub fn make_string_output(s: &mut String) -> Output<'_> {
         Output::with_string(s)
     }
+
+    /// Returns the compiled form of a template.
+    ///
+    /// This gives read only access to the instructions of a template which
+    /// is useful for tooling that wants to analyze templates.
+    pub fn get_compiled_template<'env>(tmpl: &Template<'env>) -> &'env CompiledTemplate<'env> {
+        tmpl.compiled()
+    }
 }
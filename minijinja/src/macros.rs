@@ -73,7 +73,16 @@ pub mod __context {
 /// let ctx = context! { name };
 /// ```
 ///
-/// The return value is a [`Value`](crate::value::Value).
+/// The return value is a [`Value`](crate::value::Value) which can be passed
+/// directly to [`render`](crate::Template::render) as context:
+///
+/// ```rust
+/// # use minijinja::{context, Environment};
+/// let mut env = Environment::new();
+/// env.add_template("hello", "Hello {{ name }}!").unwrap();
+/// let tmpl = env.get_template("hello").unwrap();
+/// assert_eq!(tmpl.render(context!(name => "Peter")).unwrap(), "Hello Peter!");
+/// ```
 ///
 /// Note that [`context!`](crate::context!) can also be used recursively if you need to
 /// create nested objects:
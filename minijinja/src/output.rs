@@ -1,5 +1,6 @@
 use std::{fmt, io};
 
+use crate::error::{Error, ErrorKind};
 use crate::utils::AutoEscape;
 use crate::value::Value;
 
@@ -19,6 +20,28 @@ pub enum CaptureMode {
 pub struct Output<'a> {
     w: &'a mut (dyn fmt::Write + 'a),
     capture_stack: Vec<Option<String>>,
+    // only set if blank lines are collapsed
+    blank_lines: Option<BlankLines>,
+    max_size: Option<usize>,
+    size: usize,
+    size_exceeded: bool,
+}
+
+/// Tracks the state for collapsing blank lines.
+struct BlankLines {
+    pending_ws: String,
+    at_line_start: bool,
+    after_blank: bool,
+}
+
+impl Default for BlankLines {
+    fn default() -> BlankLines {
+        BlankLines {
+            pending_ws: String::new(),
+            at_line_start: true,
+            after_blank: false,
+        }
+    }
 }
 
 impl<'a> Output<'a> {
@@ -27,6 +50,10 @@ impl<'a> Output<'a> {
         Self {
             w: buf,
             capture_stack: Vec::new(),
+            blank_lines: None,
+            max_size: None,
+            size: 0,
+            size_exceeded: false,
         }
     }
 
@@ -34,6 +61,10 @@ impl<'a> Output<'a> {
         Self {
             w,
             capture_stack: Vec::new(),
+            blank_lines: None,
+            max_size: None,
+            size: 0,
+            size_exceeded: false,
         }
     }
 
@@ -42,6 +73,58 @@ impl<'a> Output<'a> {
         Self {
             w: NullWriter::get_mut(),
             capture_stack: Vec::new(),
+            blank_lines: None,
+            max_size: None,
+            size: 0,
+            size_exceeded: false,
+        }
+    }
+
+    /// Enables collapsing of blank lines for template data.
+    ///
+    /// Once enabled, runs of lines that only contain whitespace are collapsed
+    /// into a single empty line when written with
+    /// [`write_template_data`](Self::write_template_data).  This only applies
+    /// to the final output, captured output is written unchanged.
+    pub(crate) fn collapse_blank_lines(&mut self) {
+        if self.blank_lines.is_none() {
+            self.blank_lines = Some(BlankLines::default());
+        }
+    }
+
+    /// Writes out whitespace that is still held back for collapsing.
+    ///
+    /// This needs to be called once the rendering finished so that trailing
+    /// whitespace at the end of the output is not lost.
+    pub(crate) fn finish(&mut self) -> Result<(), Error> {
+        let pending = match self.blank_lines {
+            Some(ref mut state) => std::mem::take(&mut state.pending_ws),
+            None => return Ok(()),
+        };
+        if pending.is_empty() || !self.capture_stack.is_empty() {
+            return Ok(());
+        }
+        self.write_target(&pending)
+            .map_err(|err| self.limit_error().unwrap_or_else(|| Error::from(err)))
+    }
+
+    /// Limits the number of bytes that can be written.
+    ///
+    /// The limit applies to the final output and to every capture level
+    /// individually.  Writes beyond the limit fail and the failure can be
+    /// turned into a proper error with [`limit_error`](Self::limit_error).
+    pub(crate) fn set_max_size(&mut self, max_size: Option<usize>) {
+        self.max_size = max_size;
+    }
+
+    /// Returns an error if a write failed because the size limit was hit.
+    pub(crate) fn limit_error(&self) -> Option<Error> {
+        match self.max_size {
+            Some(max_size) if self.size_exceeded => Some(Error::new(
+                ErrorKind::LimitExceeded,
+                format!("output exceeds maximum size of {} bytes", max_size),
+            )),
+            _ => None,
         }
     }
 
@@ -75,33 +158,127 @@ impl<'a> Output<'a> {
         }
     }
 
+    /// Writes to the current target enforcing the size limit.
+    #[inline(always)]
+    fn write_target(&mut self, s: &str) -> fmt::Result {
+        if let Some(max_size) = self.max_size {
+            let size = match self.capture_stack.last() {
+                Some(Some(stream)) => stream.len(),
+                Some(None) => 0,
+                None => self.size,
+            };
+            if size + s.len() > max_size {
+                self.size_exceeded = true;
+                return Err(fmt::Error);
+            }
+            if self.capture_stack.is_empty() {
+                self.size += s.len();
+            }
+        }
+        self.target().write_str(s)
+    }
+
     /// Writes some data to the underlying buffer contained within this output.
     #[inline]
     pub fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.target().write_str(s)
+        if self.blank_lines.is_some() && self.capture_stack.is_empty() {
+            ok!(self.flush_pending_whitespace(s));
+        }
+        self.write_target(s)
     }
 
     /// Writes some formatted information into this instance.
     #[inline]
     pub fn write_fmt(&mut self, a: fmt::Arguments<'_>) -> fmt::Result {
+        if self.blank_lines.is_some() || self.max_size.is_some() {
+            return fmt::write(self, a);
+        }
         self.target().write_fmt(a)
     }
+
+    /// Writes template data.
+    ///
+    /// Unlike [`write_str`](Self::write_str) this collapses blank lines if
+    /// that was enabled with [`collapse_blank_lines`](Self::collapse_blank_lines)
+    /// and nothing is being captured.
+    pub(crate) fn write_template_data(&mut self, mut s: &str) -> fmt::Result {
+        if self.blank_lines.is_none() || !self.capture_stack.is_empty() {
+            return self.write_target(s);
+        }
+
+        while !s.is_empty() {
+            let state = self.blank_lines.as_mut().unwrap();
+            if state.at_line_start {
+                let ws_len = s
+                    .find(|c| !matches!(c, ' ' | '\t' | '\r'))
+                    .unwrap_or(s.len());
+                state.pending_ws.push_str(&s[..ws_len]);
+                s = &s[ws_len..];
+                if let Some(rest) = s.strip_prefix('\n') {
+                    s = rest;
+                    let newline = if state.pending_ws.ends_with('\r') {
+                        "\r\n"
+                    } else {
+                        "\n"
+                    };
+                    state.pending_ws.clear();
+                    if !state.after_blank {
+                        state.after_blank = true;
+                        ok!(self.write_target(newline));
+                    }
+                    continue;
+                }
+                if s.is_empty() {
+                    break;
+                }
+                let pending = std::mem::take(&mut state.pending_ws);
+                state.at_line_start = false;
+                state.after_blank = false;
+                ok!(self.write_target(&pending));
+            }
+
+            let end = s.find('\n').map_or(s.len(), |idx| idx + 1);
+            let (line, rest) = s.split_at(end);
+            ok!(self.write_target(line));
+            if line.ends_with('\n') {
+                self.blank_lines.as_mut().unwrap().at_line_start = true;
+            }
+            s = rest;
+        }
+
+        Ok(())
+    }
+
+    /// Writes out pending whitespace before data that is written verbatim.
+    fn flush_pending_whitespace(&mut self, s: &str) -> fmt::Result {
+        if s.is_empty() {
+            return Ok(());
+        }
+        let state = self.blank_lines.as_mut().unwrap();
+        let pending = std::mem::take(&mut state.pending_ws);
+        state.at_line_start = s.ends_with('\n');
+        state.after_blank = false;
+        self.write_target(&pending)
+    }
 }
 
 impl fmt::Write for Output<'_> {
     #[inline]
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        fmt::Write::write_str(self.target(), s)
+        Output::write_str(self, s)
     }
 
     #[inline]
     fn write_char(&mut self, c: char) -> fmt::Result {
+        if self.blank_lines.is_some() || self.max_size.is_some() {
+            return Output::write_str(self, c.encode_utf8(&mut [0; 4]));
+        }
         fmt::Write::write_char(self.target(), c)
     }
 
     #[inline]
     fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> fmt::Result {
-        fmt::Write::write_fmt(self.target(), args)
+        Output::write_fmt(self, args)
     }
 }
 
@@ -1,14 +1,17 @@
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fmt;
 use std::fs;
+use std::hash::{Hash, Hasher};
 use std::io;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use memo_map::MemoMap;
 use self_cell::self_cell;
 
+use crate::compiler::parser::ParserConfig;
 use crate::error::{Error, ErrorKind};
 use crate::template::CompiledTemplate;
 
@@ -35,6 +38,9 @@ type LoadFunc = dyn for<'a> Fn(&'a str) -> Result<String, Error> + Send + Sync;
 #[cfg_attr(docsrs, doc(cfg(feature = "source")))]
 pub struct Source {
     backing: SourceBacking,
+    config: ParserConfig,
+    // templates that failed to compile after a config change by name
+    broken: HashMap<String, String>,
 }
 
 #[derive(Clone)]
@@ -42,12 +48,57 @@ enum SourceBacking {
     Dynamic {
         templates: MemoMap<String, Arc<LoadedTemplate>>,
         loader: Arc<LoadFunc>,
+        // if reloading is enabled, the loaded versions of the templates
+        versions: Option<Box<Versions>>,
     },
     Static {
         templates: HashMap<String, Arc<LoadedTemplate>>,
     },
 }
 
+/// The versions of templates loaded with reloading enabled.
+struct Versions {
+    // loaded templates by name and content hash
+    compiled: MemoMap<(String, u64), Arc<LoadedTemplate>>,
+    // the content hash of the most recently loaded version by name
+    latest: Mutex<HashMap<String, u64>>,
+}
+
+impl Clone for Versions {
+    fn clone(&self) -> Versions {
+        Versions {
+            compiled: self.compiled.clone(),
+            latest: Mutex::new(self.latest.lock().unwrap().clone()),
+        }
+    }
+}
+
+impl Versions {
+    fn new() -> Versions {
+        Versions {
+            compiled: MemoMap::new(),
+            latest: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Drops all but the most recently loaded version of every template.
+    ///
+    /// Older versions can only be dropped once the source is borrowed
+    /// mutably as templates handed out before might still refer to them.
+    fn prune(&mut self) {
+        let latest = self.latest.get_mut().unwrap();
+        let stale = self
+            .compiled
+            .keys()
+            .filter(|(name, hash)| latest.get(name) != Some(hash))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in stale {
+            self.compiled.remove(&key);
+        }
+    }
+}
+
 impl Default for Source {
     fn default() -> Source {
         Source::new()
@@ -57,10 +108,19 @@ impl Default for Source {
 impl fmt::Debug for Source {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self.backing {
-            SourceBacking::Dynamic { templates, .. } => f
-                .debug_list()
-                .entries(templates.iter().map(|x| x.0))
-                .finish(),
+            SourceBacking::Dynamic {
+                templates,
+                versions,
+                ..
+            } => {
+                let mut names = templates.iter().map(|x| x.0).collect::<Vec<_>>();
+                if let Some(versions) = versions {
+                    names.extend(versions.compiled.keys().map(|x| &x.0));
+                    names.sort();
+                    names.dedup();
+                }
+                f.debug_list().entries(names).finish()
+            }
             SourceBacking::Static { templates } => f
                 .debug_list()
                 .entries(templates.iter().map(|x| x.0))
@@ -101,6 +161,8 @@ impl Source {
             backing: SourceBacking::Static {
                 templates: HashMap::new(),
             },
+            config: ParserConfig::default(),
+            broken: HashMap::new(),
         }
     }
 
@@ -109,7 +171,10 @@ impl Source {
     /// When a source was created with the loader, the source gains the ability
     /// to dynamically load templates.  The loader is invoked with the name of
     /// the template.  If this template exists `Ok(Some(template_source))` has
-    /// to be returned, otherwise `Ok(None)`.
+    /// to be returned, otherwise `Ok(None)` which is reported as an error of
+    /// kind [`TemplateNotFound`](ErrorKind::TemplateNotFound).  If the template
+    /// exists but cannot be loaded an error of kind
+    /// [`CannotLoad`](ErrorKind::CannotLoad) should be returned.
     ///
     /// # Example
     ///
@@ -138,7 +203,10 @@ impl Source {
                     Some(rv) => Ok(rv),
                     None => Err(Error::new_not_found(name)),
                 }),
+                versions: None,
             },
+            config: ParserConfig::default(),
+            broken: HashMap::new(),
         }
     }
 
@@ -168,14 +236,54 @@ impl Source {
             match fs::read_to_string(path) {
                 Ok(result) => Ok(Some(result)),
                 Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
-                Err(err) => Err(
-                    Error::new(ErrorKind::InvalidOperation, "could not read template")
-                        .with_source(err),
-                ),
+                Err(err) => {
+                    Err(Error::new(ErrorKind::CannotLoad, "could not read template")
+                        .with_source(err))
+                }
             }
         })
     }
 
+    /// Reloads templates from the loader when their source changes.
+    ///
+    /// By default a source with a loader invokes the loader only once per
+    /// template and keeps the compiled template forever.  When this is enabled
+    /// the loader is invoked every time a template is requested from the
+    /// environment and the template is only recompiled if the returned source
+    /// differs from what was compiled before.  Changes are detected by hashing
+    /// the contents rather than by modification time so that quick successive
+    /// edits are never missed.  This is intended for development servers.
+    ///
+    /// Only the most recently loaded version of a template is kept.  As
+    /// templates handed out before might still refer to the versions they
+    /// were loaded from, superseded versions are only dropped the next time
+    /// the source is modified (for instance with
+    /// [`add_template`](Self::add_template)) or when it's dropped together
+    /// with the environment.  Templates added with
+    /// [`add_template`](Self::add_template) are not reloaded and neither are
+    /// templates that were loaded before reloading was enabled.  This has no
+    /// effect on sources without a loader.
+    ///
+    /// ```rust
+    /// # use minijinja::{Source, Environment};
+    /// fn create_env() -> Environment<'static> {
+    ///     let mut env = Environment::new();
+    ///     let mut source = Source::from_path("path/to/templates");
+    ///     source.set_reload_on_change(true);
+    ///     env.set_source(source);
+    ///     env
+    /// }
+    /// ```
+    pub fn set_reload_on_change(&mut self, yes: bool) {
+        if let SourceBacking::Dynamic { versions, .. } = &mut self.backing {
+            *versions = if yes {
+                Some(Box::new(Versions::new()))
+            } else {
+                None
+            };
+        }
+    }
+
     /// Adds a new template into the source.
     ///
     /// This is similar to the method of the same name on the environment but
@@ -186,16 +294,15 @@ impl Source {
         name: N,
         source: S,
     ) -> Result<(), Error> {
-        let source = source.into();
         let name = name.into();
-        let owner = (name.clone(), source);
-        let tmpl = ok!(LoadedTemplate::try_new(
-            owner,
-            |(name, source)| -> Result<_, Error> {
-                CompiledTemplate::from_name_and_source(name.as_str(), source)
-            }
-        ));
+        let tmpl = ok!(load_template(name.clone(), source.into(), self.config));
+        self.broken.remove(&name);
+        self.insert_template(name, tmpl);
+        self.prune_versions();
+        Ok(())
+    }
 
+    fn insert_template(&mut self, name: String, tmpl: LoadedTemplate) {
         match self.backing {
             SourceBacking::Dynamic {
                 ref mut templates, ..
@@ -206,35 +313,127 @@ impl Source {
                 templates.insert(name, Arc::new(tmpl));
             }
         }
-        Ok(())
+    }
+
+    fn prune_versions(&mut self) {
+        if let SourceBacking::Dynamic {
+            versions: Some(ref mut versions),
+            ..
+        } = self.backing
+        {
+            versions.prune();
+        }
     }
 
     /// Removes an already loaded template from the source.
     pub fn remove_template(&mut self, name: &str) {
+        self.broken.remove(name);
         match &mut self.backing {
-            SourceBacking::Dynamic { templates, .. } => templates.remove(name),
+            SourceBacking::Dynamic {
+                templates,
+                versions,
+                ..
+            } => {
+                if let Some(versions) = versions {
+                    versions.latest.get_mut().unwrap().remove(name);
+                    versions.prune();
+                }
+                templates.remove(name)
+            }
             SourceBacking::Static { templates } => templates.remove(name),
         };
     }
 
+    /// Changes the parser config.
+    ///
+    /// If the whitespace config changes, loaded templates are recompiled.  A
+    /// template that fails to compile with the new config reports the error
+    /// when it's requested next.
+    pub(crate) fn set_parser_config(&mut self, config: ParserConfig) {
+        let recompile = self.config.whitespace != config.whitespace;
+        self.config = config;
+        if !recompile {
+            return;
+        }
+        let mut loaded = Vec::new();
+        match self.backing {
+            SourceBacking::Dynamic {
+                ref mut templates,
+                ref mut versions,
+                ..
+            } => {
+                // reloaded templates are compiled again on next use
+                if let Some(versions) = versions {
+                    **versions = Versions::new();
+                }
+                loaded.extend(
+                    templates
+                        .iter()
+                        .map(|(_, tmpl)| tmpl.borrow_owner().clone()),
+                );
+                *templates = MemoMap::new();
+            }
+            SourceBacking::Static { ref mut templates } => {
+                loaded.extend(
+                    templates
+                        .drain()
+                        .map(|(_, tmpl)| tmpl.borrow_owner().clone()),
+                );
+            }
+        }
+        loaded.extend(self.broken.drain());
+        for (name, source) in loaded {
+            match load_template(name.clone(), source.clone(), config) {
+                Ok(tmpl) => self.insert_template(name, tmpl),
+                Err(_) => {
+                    self.broken.insert(name, source);
+                }
+            }
+        }
+    }
+
     /// Gets a compiled template from the source.
     pub(crate) fn get_compiled_template(&self, name: &str) -> Result<&CompiledTemplate<'_>, Error> {
+        if let Some(source) = self.broken.get(name) {
+            // compile again to report why the template fails to compile with
+            // the current config.
+            ok!(load_template(name.to_owned(), source.clone(), self.config));
+        }
         match &self.backing {
-            SourceBacking::Dynamic { templates, loader } => Ok(ok!(templates.get_or_try_insert(
-                name,
-                || -> Result<_, Error> {
+            SourceBacking::Dynamic {
+                templates,
+                loader,
+                versions: Some(versions),
+            } => {
+                if let Some(tmpl) = templates.get(name) {
+                    return Ok(tmpl.borrow_dependent());
+                }
+                let source = ok!(loader(name));
+                let hash = content_hash(&source);
+                let tmpl = ok!(versions.compiled.get_or_try_insert_owned(
+                    (name.to_owned(), hash),
+                    || -> Result<_, Error> {
+                        let tmpl = ok!(load_template(name.to_owned(), source, self.config));
+                        Ok(Arc::new(tmpl))
+                    }
+                ));
+                versions
+                    .latest
+                    .lock()
+                    .unwrap()
+                    .insert(name.to_owned(), hash);
+                Ok(tmpl.borrow_dependent())
+            }
+            SourceBacking::Dynamic {
+                templates, loader, ..
+            } => Ok(
+                ok!(templates.get_or_try_insert(name, || -> Result<_, Error> {
                     let source = ok!(loader(name));
-                    let owner = (name.to_owned(), source);
-                    let tmpl = ok!(LoadedTemplate::try_new(
-                        owner,
-                        |(name, source)| -> Result<_, Error> {
-                            CompiledTemplate::from_name_and_source(name.as_str(), source)
-                        }
-                    ));
+                    let tmpl = ok!(load_template(name.to_owned(), source, self.config));
                     Ok(Arc::new(tmpl))
-                }
-            ))
-            .borrow_dependent()),
+                }))
+                .borrow_dependent(),
+            ),
             SourceBacking::Static { templates } => templates
                 .get(name)
                 .map(|value| value.borrow_dependent())
@@ -243,6 +442,22 @@ impl Source {
     }
 }
 
+fn load_template(
+    name: String,
+    source: String,
+    config: ParserConfig,
+) -> Result<LoadedTemplate, Error> {
+    LoadedTemplate::try_new((name, source), |(name, source)| -> Result<_, Error> {
+        CompiledTemplate::from_name_and_source_with_config(name.as_str(), source, config)
+    })
+}
+
+fn content_hash(source: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
 fn safe_join(base: &Path, template: &str) -> Option<PathBuf> {
     let mut rv = base.to_path_buf();
     for segment in template.split('/') {
@@ -4,6 +4,7 @@
 //!
 //! - [Synopsis](#synopsis)
 //! - [Trailing Newlines](#trailing-newlines)
+//! - [Whitespace Control](#whitespace-control)
 //! - [Expressions](#expressions)
 //!   - [Literals](#literals)
 //!   - [Math](#math)
@@ -61,7 +62,18 @@
 //! MiniJinja, like Jinja2, will remove one trailing newline from the end of the file automatically
 //! on parsing.  This lets templates produce a consistent output no matter if the editor adds a
 //! trailing newline or not.  If one wants a trailing newline an extra newline can be added or the
-//! code rendering it adds it manually.
+//! code rendering it adds it manually.  Alternatively the trailing newline can be preserved
+//! by enabling [`Environment::set_keep_trailing_newline`](crate::Environment::set_keep_trailing_newline).
+//!
+//! # Whitespace Control
+//!
+//! A minus sign (`-`) at the start or end of a block, a variable or a comment removes all
+//! whitespace before or after it: `{%- if foo -%}`.  Additionally two options can be enabled
+//! on the environment: [`set_trim_blocks`](crate::Environment::set_trim_blocks) removes the
+//! first newline after a block and [`set_lstrip_blocks`](crate::Environment::set_lstrip_blocks)
+//! strips spaces and tabs from the start of a line to a block.  With these enabled a plus sign
+//! disables the behavior for a single tag: `{%+ if foo %}` keeps the leading whitespace and
+//! `{% endif +%}` keeps the newline.
 //!
 //! # Expressions
 //!
@@ -80,11 +92,14 @@
 //! - `42`: Integers are whole numbers without a decimal part.
 //! - `42.0`: Floating point numbers can be written using a `.` as a decimal mark.
 //! - `['list', 'of', 'objects']`: Everything between two brackets is a list. Lists are useful
-//!   for compatibility with Jinja2 `('list', 'of', 'objects')` is also allowed.
-//!   for storing sequential data to be iterated over.
+//!   for storing sequential data to be iterated over.  For compatibility with Jinja2 tuples
+//!   like `('list', 'of', 'objects')` are also allowed and are represented as lists.  A
+//!   tuple with a single item needs a trailing comma: `('item',)`.
 //! - `{'map': 'of', 'key': 'and', 'value': 'pairs'}`: A map is a structure that combines keys
-//!   and values. Keys must be unique and always have exactly one value. Maps are rarely
-//!   created in templates.
+//!   and values. Keys must be unique and always have exactly one value; if a key is given
+//!   more than once the last value wins. Another map can be merged in with `**`, for instance
+//!   `{**defaults, 'key': 'value'}`. Maps are rarely created in templates.
+//! - `[]` and `()` create an empty list, `{}` creates an empty map.
 //! - `true` / `false` / `none`: boolean values and the special `none` value which maps to the
 //!   unit type in Rust.
 //!
@@ -113,6 +128,9 @@
 //! - ``<``:``true`` if the left hand side is lower than the right hand side.
 //! - ``<=``: ``true`` if the left hand side is lower or equal to the right hand side.
 //!
+//! Sequences and maps compare equal if their items are equal.  Sequences are
+//! ordered lexicographically like in Python, maps have no ordering.
+//!
 //! ## Logic
 //!
 //! For ``if`` statements it can be useful to combine multiple expressions:
@@ -136,8 +154,16 @@
 //! - ``()``: Call a callable: ``{{ super() }}``.  Inside of the parentheses you
 //!   can use positional arguments.  Additionally keyword arguments are supported
 //!   which are treated like a dict syntax.  Eg: `foo(a=1, b=2)` is the same as
-//!   `foo({"a": 1, "b": 2})`.
-//! - ``.`` / ``[]``: Get an attribute of an object.
+//!   `foo({"a": 1, "b": 2})`.  Sequences and maps can be unpacked into positional and
+//!   keyword arguments with `*` and `**`: `foo(*args, **kwargs)`.
+//! - ``.`` / ``[]``: Get an attribute of an object.  Both forms resolve string keys the
+//!   same way: maps look up the key of that name and objects are asked for the attribute.
+//!   As there are no methods on maps, a key always wins even if it is named like a method
+//!   (``{{ {"items": 1}.items }}`` is ``1``).  Only the subscript syntax can look up
+//!   non-string keys such as indexes into sequences (``{{ seq[0] }}``).  Negative
+//!   indexes count from the end (``{{ seq[-1] }}``).  Strings can be indexed the same
+//!   way and return the character at that position as string.  Indexes out of range
+//!   are undefined.
 //! - ``[start:stop]`` / ``[start:stop:step]``: slices a list or string.  All three expressions
 //!   are optional (`start`, `stop`, `step`).  For instance ``"Hello World"[:5]`` will return
 //!   just `"Hello"`.  Likewise ``"Hello"[1:-1]`` will return `"ell"`.  The step component can
@@ -204,6 +230,14 @@
 //! - `loop.changed(...args)`: Returns true if the passed values have changed since the last time it was called with the same arguments.
 //! - `loop.cycle(...args)`: Returns a value from the passed sequence in a cycle.
 //!
+//! Because `loop.last`, `loop.length` and `loop.revindex` need to know the
+//! number of items up front, the loop materializes the iterated value before
+//! the first iteration.  Sequences, maps and strings are iterated without
+//! copying, but the items of dynamic objects (such as custom iterators
+//! implemented via [`Object::iter`](crate::value::Object::iter)) are collected
+//! into a list first.  This means memory use grows with the number of items
+//! and infinite iterators cannot be looped over.
+//!
 //! Within a for-loop, it’s possible to cycle among a list of strings/variables each time through
 //! the loop by using the special `loop.cycle` helper:
 //!
@@ -469,6 +503,12 @@
 //! {% set navigation = [('index.html', 'Index'), ('about.html', 'About')] %}
 //! ```
 //!
+//! Sequences such as tuples can be unpacked into multiple variables at once:
+//!
+//! ```jinja
+//! {% set url, title = navigation|first %}
+//! ```
+//!
 //! Please keep in mind that it is not possible to set variables inside a block
 //! and have them show up outside of it.  This also applies to loops.  The only
 //! exception to that rule are if statements which do not introduce a scope.
@@ -487,6 +527,13 @@
 //!
 //! The `navigation` variable then contains the navigation HTML source.
 //!
+//! The captured template data is subject to the same [whitespace control](#whitespace-control)
+//! as regular output.  Without any whitespace control, the newline after `{% set navigation %}`
+//! as well as the indentation in front of `{% endset %}` become part of the value.  Using
+//! `{%- endset %}` and `{% set navigation -%}` strips them, and with `trim_blocks` and
+//! `lstrip_blocks` enabled the newline after the opening tag and the whitespace before an
+//! indented `{% endset %}` are removed automatically.
+//!
 //! This can also be combined with applying a filter:
 //!
 //! ```jinja
@@ -504,6 +551,17 @@
 //! {% endfilter %}
 //! ```
 //!
+//! Filter blocks can be nested in which case the inner filter is applied
+//! first.  The body of a filter block has its own scope, so variables set
+//! within it are not visible outside and the arguments of the filter are
+//! evaluated in the enclosing scope:
+//!
+//! ```jinja
+//! {% filter indent(4) %}{% filter upper %}
+//!   This text becomes uppercase and indented
+//! {% endfilter %}{% endfilter %}
+//! ```
+//!
 //! ## `{% macro %}`
 //!
 //! **Feature:** `macros` (included by default)
@@ -550,6 +608,15 @@
 //! ```
 //!
 //! After an `endautoescape` the behavior is reverted to what it was before.
+//! Blocks can be nested in which case each `endautoescape` restores the
+//! behavior of the enclosing block:
+//!
+//! ```jinja
+//! {% autoescape false %}
+//!   <script>{{ raw_script }}</script>
+//!   {% autoescape true %}{{ user_input }}{% endautoescape %}
+//! {% endautoescape %}
+//! ```
 //!
 //! The exact auto escaping behavior is determined by the value of
 //! [`AutoEscape`](crate::AutoEscape) set to the template.
@@ -1,11 +1,16 @@
 use std::collections::BTreeMap;
-use std::{fmt, io};
+#[cfg(feature = "multi-template")]
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::{self, Write};
+use std::io;
 
 use serde::Serialize;
 
 use crate::compiler::codegen::CodeGenerator;
+#[cfg(feature = "multi-template")]
+use crate::compiler::instructions::Instruction;
 use crate::compiler::instructions::Instructions;
-use crate::compiler::parser::parse;
+use crate::compiler::parser::{parse_template, ParserConfig};
 use crate::environment::Environment;
 use crate::error::{attach_basic_debug_info, Error, ErrorKind};
 use crate::output::{Output, WriteWrapper};
@@ -85,8 +90,13 @@ impl<'env> Template<'env> {
     }
 
     fn _render(&self, root: Value) -> Result<String, Error> {
+        self.render_nested(root, 0)
+    }
+
+    /// Renders the template on behalf of an outer evaluation at the given depth.
+    pub(crate) fn render_nested(&self, root: Value, depth: usize) -> Result<String, Error> {
         let mut rv = String::new();
-        self._eval(root, &mut Output::with_string(&mut rv))
+        self._eval(root, &mut Output::with_string(&mut rv), depth)
             .map(|_| rv)
     }
 
@@ -109,6 +119,7 @@ impl<'env> Template<'env> {
         self._eval(
             Value::from_serializable(&ctx),
             &mut Output::with_write(&mut wrapper),
+            0,
         )
         .map_err(|err| {
             wrapper
@@ -122,27 +133,107 @@ impl<'env> Template<'env> {
         })
     }
 
-    fn _eval(&self, root: Value, out: &mut Output) -> Result<(), Error> {
+    /// Returns a listing of the compiled instructions of the template.
+    ///
+    /// Every instruction is written on its own line together with its offset
+    /// and the line in the template source it was compiled from.  The
+    /// instructions of blocks follow the root instructions, each introduced
+    /// by a `block <name>:` line.  This is useful for tooling that wants to
+    /// inspect templates, for instance to find uses of a deprecated filter.
+    /// The instructions themselves are an implementation detail and can
+    /// change between releases.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// # let mut env = Environment::new();
+    /// env.add_template("hello", "{{ name|upper }}").unwrap();
+    /// let tmpl = env.get_template("hello").unwrap();
+    /// assert!(tmpl.debug_instructions().contains(r#"ApplyFilter("upper""#));
+    /// ```
+    pub fn debug_instructions(&self) -> String {
+        let mut rv = String::new();
+        self.compiled.instructions.write_listing(&mut rv).unwrap();
+        for (name, block) in &self.compiled.blocks {
+            writeln!(rv, "block {}:", name).unwrap();
+            block.write_listing(&mut rv).unwrap();
+        }
+        rv
+    }
+
+    /// Returns the names of all blocks the template defines.
+    ///
+    /// This includes the blocks inherited from parent templates as long as they
+    /// are referenced with a constant name in `{% extends %}`.  The names are
+    /// returned in sorted order.  Loading a parent template can fail in which case
+    /// the error is returned.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// # let mut env = Environment::new();
+    /// env.add_template("layout.html", "{% block title %}{% endblock %}").unwrap();
+    /// env.add_template("index.html", r#"{% extends "layout.html" %}{% block body %}{% endblock %}"#).unwrap();
+    /// let tmpl = env.get_template("index.html").unwrap();
+    /// let blocks = tmpl.blocks().unwrap().collect::<Vec<_>>();
+    /// assert_eq!(blocks, ["body", "title"]);
+    /// ```
+    #[cfg(feature = "multi-template")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "multi-template")))]
+    pub fn blocks(&self) -> Result<impl Iterator<Item = &'env str>, Error> {
+        let mut rv = BTreeSet::new();
+        let mut seen = HashSet::new();
+        let mut tmpl = *self;
+        loop {
+            rv.extend(tmpl.compiled.blocks.keys().copied());
+            let name = tmpl.compiled.instructions.name();
+            if !seen.insert(name) {
+                break;
+            }
+            let parent =
+                tmpl.compiled.instructions.instructions.windows(2).find_map(
+                    |window| match window {
+                        [Instruction::LoadConst(value), Instruction::LoadBlocks] => value.as_str(),
+                        _ => None,
+                    },
+                );
+            match parent {
+                Some(parent) => {
+                    let parent = self.env.join_template_path(parent, name);
+                    tmpl = ok!(self.env.get_template(&parent));
+                }
+                None => break,
+            }
+        }
+        Ok(rv.into_iter())
+    }
+
+    fn _eval(&self, root: Value, out: &mut Output, depth: usize) -> Result<(), Error> {
         Vm::new(self.env)
-            .eval(
+            .eval_nested(
                 &self.compiled.instructions,
                 root,
                 &self.compiled.blocks,
                 out,
                 self.initial_auto_escape,
+                depth,
             )
             .map(|_| ())
     }
 
+    /// Returns the compiled template.
+    #[cfg(feature = "unstable_machinery")]
+    pub(crate) fn compiled(&self) -> &'env CompiledTemplate<'env> {
+        self.compiled
+    }
+
     /// Returns the root instructions.
     #[cfg(feature = "multi-template")]
     pub(crate) fn instructions(&self) -> &'env Instructions<'env> {
         &self.compiled.instructions
     }
 
-    /// Returns the blocks.
+    /// Returns the compiled blocks.
     #[cfg(feature = "multi-template")]
-    pub(crate) fn blocks(&self) -> &'env BTreeMap<&'env str, Instructions<'env>> {
+    pub(crate) fn compiled_blocks(&self) -> &'env BTreeMap<&'env str, Instructions<'env>> {
         &self.compiled.blocks
     }
 
@@ -175,22 +266,38 @@ impl<'env> fmt::Debug for CompiledTemplate<'env> {
 
 impl<'source> CompiledTemplate<'source> {
     /// Creates a compiled template from name and source.
+    #[cfg(feature = "unstable_machinery")]
     pub fn from_name_and_source(
         name: &'source str,
         source: &'source str,
     ) -> Result<CompiledTemplate<'source>, Error> {
-        attach_basic_debug_info(Self::_from_name_and_source_impl(name, source), source)
+        Self::from_name_and_source_with_config(name, source, ParserConfig::default())
+    }
+
+    /// Creates a compiled template from name and source with the given
+    /// parser configuration.
+    pub fn from_name_and_source_with_config(
+        name: &'source str,
+        source: &'source str,
+        config: ParserConfig,
+    ) -> Result<CompiledTemplate<'source>, Error> {
+        attach_basic_debug_info(
+            Self::_from_name_and_source_impl(name, source, config),
+            source,
+        )
     }
 
     fn _from_name_and_source_impl(
         name: &'source str,
         source: &'source str,
+        config: ParserConfig,
     ) -> Result<CompiledTemplate<'source>, Error> {
         // the parser/compiler combination can create constants in which case
         // we can probably benefit from the value optimization a bit.
         value::with_value_optimization(|| {
-            let ast = ok!(parse(source, name));
+            let (ast, trailing_newline) = ok!(parse_template(source, name, config));
             let mut gen = CodeGenerator::new(name, source);
+            gen.set_trailing_newline(trailing_newline);
             ok!(gen.compile_stmt(&ast));
             let (instructions, blocks) = gen.finish();
             Ok(CompiledTemplate {
@@ -46,6 +46,28 @@
 //! MiniJinja will perform the necessary conversions automatically.  For more
 //! information see the [`Test`] trait.
 //!
+//! # Accessing State
+//!
+//! Like filters, tests can access the execution [`State`] by declaring it as
+//! first parameter.  This makes it possible to look up variables of the
+//! render context.  For instance the following test checks if a value is one
+//! of the features enabled in the context:
+//!
+//! ```
+//! # use minijinja::Environment;
+//! # let mut env = Environment::new();
+//! use minijinja::State;
+//!
+//! fn is_enabled(state: &State, value: String) -> bool {
+//!     state
+//!         .lookup("enabled_features")
+//!         .and_then(|features| features.get_attr(&value).ok())
+//!         .map_or(false, |enabled| enabled.is_true())
+//! }
+//!
+//! env.add_test("enabled", is_enabled);
+//! ```
+//!
 //! # Built-in Tests
 //!
 //! When the `builtins` feature is enabled a range of built-in tests are
@@ -207,6 +229,13 @@ pub fn is_defined(v: Value) -> bool {
     !v.is_undefined()
 }
 
+/// Checks if a value is `none`.
+///
+/// This is not true for undefined values.
+pub fn is_none(v: Value) -> bool {
+    v.is_none()
+}
+
 #[cfg(feature = "builtins")]
 mod builtins {
     use super::*;
@@ -252,6 +281,14 @@ mod builtins {
         matches!(v.kind(), ValueKind::Map)
     }
 
+    /// Checks if this value can be called.
+    ///
+    /// This is true for functions, macros and callable objects.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_callable(v: Value) -> bool {
+        v.is_callable()
+    }
+
     /// Checks if the value is starting with a string.
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn is_startingwith(v: Cow<'_, str>, other: Cow<'_, str>) -> bool {
@@ -264,6 +301,36 @@ mod builtins {
         v.ends_with(&other as &str)
     }
 
+    /// Checks if the value matches a regular expression at its start.
+    ///
+    /// This test is only available if the `regex` feature is enabled.  The
+    /// match is anchored at the start of the value but not at the end, use
+    /// `$` in the pattern to match the entire value.
+    ///
+    /// ```jinja
+    /// {% if value is matching("^\\d+$") %}a number{% endif %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "regex"))))]
+    #[cfg(feature = "regex")]
+    pub fn is_matching(v: Cow<'_, str>, pattern: Cow<'_, str>) -> Result<bool, Error> {
+        let re = ok!(crate::utils::get_regex(&pattern));
+        Ok(re.find(&v).map_or(false, |m| m.start() == 0))
+    }
+
+    /// Checks if a regular expression matches anywhere in the value.
+    ///
+    /// This test is only available if the `regex` feature is enabled.
+    ///
+    /// ```jinja
+    /// {% if value is search("error|warning") %}check the log{% endif %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "regex"))))]
+    #[cfg(feature = "regex")]
+    pub fn is_search(v: Cow<'_, str>, pattern: Cow<'_, str>) -> Result<bool, Error> {
+        let re = ok!(crate::utils::get_regex(&pattern));
+        Ok(re.is_match(&v))
+    }
+
     #[test]
     fn test_basics() {
         fn test(_: &State, a: u32, b: u32) -> bool {
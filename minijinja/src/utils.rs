@@ -53,6 +53,24 @@ pub fn write_escaped(
     auto_escape: AutoEscape,
     value: &Value,
 ) -> Result<(), Error> {
+    // objects can provide a separate value for the template output.  This
+    // is only done once, if that value is an object again it's written as is.
+    let rendered;
+    let value = match value.0 {
+        ValueRepr::Dynamic(ref obj) => match obj.render() {
+            Some(rv) => {
+                rendered = rv;
+                &rendered
+            }
+            None if obj.is_callable() => {
+                rendered = Value::from("<function>");
+                &rendered
+            }
+            None => value,
+        },
+        _ => value,
+    };
+
     // common case of safe strings or strings without auto escaping
     if let ValueRepr::String(ref s, ty) = value.0 {
         if matches!(ty, StringType::Safe) || matches!(auto_escape, AutoEscape::None) {
@@ -65,9 +83,7 @@ pub fn write_escaped(
         AutoEscape::Html => write_with_html_escaping(out, value).map_err(Error::from),
         #[cfg(feature = "json")]
         AutoEscape::Json => {
-            let value = ok!(serde_json::to_string(&value).map_err(|err| {
-                Error::new(ErrorKind::BadSerialization, "unable to format to JSON").with_source(err)
-            }));
+            let value = ok!(crate::value::to_json(value));
             write!(out, "{}", value).map_err(Error::from)
         }
         AutoEscape::Custom(name) => invalid_autoescape(name),
@@ -104,6 +120,46 @@ pub enum AutoEscape {
     Custom(&'static str),
 }
 
+/// Controls how undefined values are handled.
+///
+/// For more information see
+/// [`set_undefined_behavior`](crate::Environment::set_undefined_behavior).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UndefinedBehavior {
+    /// The default, somewhat lenient undefined behavior.
+    ///
+    /// * printing: allowed (renders an empty string)
+    /// * iteration: allowed (yields no items)
+    /// * attribute access of undefined values: fails
+    Lenient,
+    /// Chainable undefined behavior for missing links in a lookup chain.
+    ///
+    /// Variables that are missing entirely are an error when they are used,
+    /// also after they were assigned to another variable.  Once a variable
+    /// exists however, missing attributes produce an undefined value on which
+    /// further attributes can be looked up.  Such undefined values print as an
+    /// empty string.
+    ///
+    /// * printing a missing variable: fails
+    /// * attribute access on a missing variable: fails
+    /// * passing a missing variable to functions or operators: fails
+    /// * filtering a missing variable: fails if the filter needs a concrete
+    ///   type like a string or number.  Filters that accept any value (like
+    ///   `default`) see it as undefined and string filters like `upper` pass it
+    ///   on, so `{{ missing|upper|default('') }}` works.
+    /// * printing an undefined attribute: allowed (renders an empty string)
+    /// * attribute access of undefined attributes: allowed (yields undefined)
+    /// * iteration, conditions and tests: allowed
+    SemiStrict,
+}
+
+impl Default for UndefinedBehavior {
+    fn default() -> UndefinedBehavior {
+        UndefinedBehavior::Lenient
+    }
+}
+
 /// Helper to HTML escape a string.
 pub struct HtmlEscape<'a>(pub &'a str);
 
@@ -226,6 +282,40 @@ impl Unescaper {
     }
 }
 
+/// Compiles a regular expression.
+///
+/// Compiled expressions are kept in a small per thread cache so that
+/// filters and tests invoked in a loop do not recompile the same pattern.
+#[cfg(all(feature = "builtins", feature = "regex"))]
+pub fn get_regex(pattern: &str) -> Result<regex::Regex, Error> {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    const MAX_CACHED: usize = 32;
+
+    thread_local! {
+        static CACHE: RefCell<HashMap<String, regex::Regex>> = RefCell::new(HashMap::new());
+    }
+
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if let Some(re) = cache.get(pattern) {
+            return Ok(re.clone());
+        }
+        let re = ok!(regex::Regex::new(pattern).map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("invalid regular expression {:?}: {}", pattern, err),
+            )
+        }));
+        if cache.len() >= MAX_CACHED {
+            cache.clear();
+        }
+        cache.insert(pattern.to_string(), re.clone());
+        Ok(re)
+    })
+}
+
 /// Un-escape a string, following JSON rules.
 pub fn unescape(s: &str) -> Result<String, Error> {
     Unescaper {
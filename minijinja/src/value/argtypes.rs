@@ -1,12 +1,13 @@
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
+use std::hash::BuildHasher;
 use std::ops::{Deref, DerefMut};
 
 use crate::error::{Error, ErrorKind};
 use crate::key::{Key, StaticKey};
 use crate::value::{Arc, MapType, Object, Packed, StringType, Value, ValueKind, ValueRepr};
-use crate::vm::State;
+use crate::vm::{ensure_defined, State};
 
 /// A utility trait that represents the return value of functions and filters.
 ///
@@ -268,6 +269,23 @@ impl<K: Into<StaticKey>, V: Into<Value>> From<BTreeMap<K, V>> for Value {
     }
 }
 
+impl<K: Into<StaticKey>, V: Into<Value>, S: BuildHasher> From<HashMap<K, V, S>> for Value {
+    fn from(val: HashMap<K, V, S>) -> Self {
+        val.into_iter().map(|(k, v)| (k.into(), v.into())).collect()
+    }
+}
+
+/// `None` converts into [`none`](Value::NONE), the same value that
+/// serializing an `Option` produces.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(val: Option<T>) -> Self {
+        match val {
+            Some(val) => val.into(),
+            None => Value::NONE,
+        }
+    }
+}
+
 impl<T: Into<Value>> From<Vec<T>> for Value {
     fn from(val: Vec<T>) -> Self {
         val.into_iter().map(|v| v.into()).collect()
@@ -347,7 +365,10 @@ macro_rules! primitive_try_from {
             type Output = Self;
             fn from_value(value: Option<&Value>) -> Result<Self, Error> {
                 match value {
-                    Some(value) => TryFrom::try_from(value.clone()),
+                    Some(value) => {
+                        ok!(ensure_defined(value));
+                        TryFrom::try_from(value.clone())
+                    }
                     None => Err(Error::from(ErrorKind::MissingArgument))
                 }
             }
@@ -400,9 +421,12 @@ impl<'a> ArgType<'a> for &str {
     #[inline(always)]
     fn from_value(value: Option<&'a Value>) -> Result<Self::Output, Error> {
         match value {
-            Some(value) => value
-                .as_str()
-                .ok_or_else(|| Error::new(ErrorKind::InvalidOperation, "value is not a string")),
+            Some(value) => {
+                ok!(ensure_defined(value));
+                value
+                    .as_str()
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidOperation, "value is not a string"))
+            }
             None => Err(Error::from(ErrorKind::MissingArgument)),
         }
     }
@@ -414,9 +438,12 @@ impl<'a> ArgType<'a> for &[u8] {
     #[inline(always)]
     fn from_value(value: Option<&'a Value>) -> Result<Self::Output, Error> {
         match value {
-            Some(value) => value
-                .as_bytes()
-                .ok_or_else(|| Error::new(ErrorKind::InvalidOperation, "value is not in bytes")),
+            Some(value) => {
+                ok!(ensure_defined(value));
+                value
+                    .as_bytes()
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidOperation, "value is not in bytes"))
+            }
             None => Err(Error::from(ErrorKind::MissingArgument)),
         }
     }
@@ -428,7 +455,10 @@ impl<'a, T: ArgType<'a>> ArgType<'a> for Option<T> {
     fn from_value(value: Option<&'a Value>) -> Result<Self::Output, Error> {
         match value {
             Some(value) => {
-                if value.is_undefined() || value.is_none() {
+                // a missing variable is not the same as an omitted argument
+                if matches!(value.0, ValueRepr::Missing(_)) {
+                    T::from_value(Some(value)).map(Some)
+                } else if value.is_undefined() || value.is_none() {
                     Ok(None)
                 } else {
                     T::from_value(Some(value)).map(Some)
@@ -445,7 +475,10 @@ impl<'a> ArgType<'a> for Cow<'_, str> {
     #[inline(always)]
     fn from_value(value: Option<&'a Value>) -> Result<Cow<'a, str>, Error> {
         match value {
-            Some(value) => Ok(value.to_cowstr()),
+            Some(value) => {
+                ok!(ensure_defined(value));
+                Ok(value.to_cowstr())
+            }
             None => Err(Error::from(ErrorKind::MissingArgument)),
         }
     }
@@ -469,7 +502,10 @@ impl<'a> ArgType<'a> for &[Value] {
     #[inline(always)]
     fn from_value(value: Option<&'a Value>) -> Result<&'a [Value], Error> {
         match value {
-            Some(value) => Ok(ok!(value.as_slice())),
+            Some(value) => {
+                ok!(ensure_defined(value));
+                Ok(ok!(value.as_slice()))
+            }
             None => Err(Error::from(ErrorKind::MissingArgument)),
         }
     }
@@ -554,7 +590,10 @@ impl<'a> ArgType<'a> for String {
 
     fn from_value(value: Option<&'a Value>) -> Result<Self, Error> {
         match value {
-            Some(value) => Ok(value.to_string()),
+            Some(value) => {
+                ok!(ensure_defined(value));
+                Ok(value.to_string())
+            }
             None => Err(Error::from(ErrorKind::MissingArgument)),
         }
     }
@@ -572,6 +611,12 @@ impl From<usize> for Value {
     }
 }
 
+impl From<isize> for Value {
+    fn from(val: isize) -> Self {
+        Value::from(val as i64)
+    }
+}
+
 impl<'a, T: ArgType<'a, Output = T>> ArgType<'a> for Vec<T> {
     type Output = Self;
 
@@ -579,6 +624,7 @@ impl<'a, T: ArgType<'a, Output = T>> ArgType<'a> for Vec<T> {
         match value {
             None => Ok(Vec::new()),
             Some(values) => {
+                ok!(ensure_defined(values));
                 let values = ok!(values.as_slice());
                 let mut rv = Vec::new();
                 for value in values {
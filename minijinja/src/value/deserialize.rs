@@ -1,6 +1,11 @@
-use serde::de::{self, MapAccess, SeqAccess, Visitor};
-use serde::Deserialize;
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{
+    self, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, Unexpected,
+    VariantAccess, Visitor,
+};
+use serde::{forward_to_deserialize_any, Deserialize, Deserializer};
 
+use crate::error::Error;
 use crate::value::{MapType, Value, ValueMap, ValueRepr};
 
 impl<'de> Deserialize<'de> for Value {
@@ -101,3 +106,172 @@ impl<'de> Visitor<'de> for ValueVisitor {
         Ok(Value(ValueRepr::Map(rv.into(), MapType::Normal)))
     }
 }
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Value;
+
+    fn into_deserializer(self) -> Value {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            ValueRepr::Undefined | ValueRepr::Missing(_) | ValueRepr::None => visitor.visit_unit(),
+            ValueRepr::Bool(v) => visitor.visit_bool(v),
+            ValueRepr::U64(v) => visitor.visit_u64(v),
+            ValueRepr::I64(v) => visitor.visit_i64(v),
+            ValueRepr::F64(v) => visitor.visit_f64(v),
+            ValueRepr::Char(v) => visitor.visit_char(v),
+            ValueRepr::U128(v) => visitor.visit_u128(v.0),
+            ValueRepr::I128(v) => visitor.visit_i128(v.0),
+            ValueRepr::String(ref v, _) => visitor.visit_str(v),
+            ValueRepr::Bytes(ref v) => visitor.visit_bytes(v),
+            ValueRepr::Seq(ref v) => visitor.visit_seq(SeqDeserializer::new(v.iter().cloned())),
+            ValueRepr::Map(ref v, _) => visitor.visit_map(MapDeserializer::new(
+                v.iter().map(|(k, v)| (Value::from(k.clone()), v.clone())),
+            )),
+            ValueRepr::Dynamic(ref obj) => visitor
+                .visit_map(MapDeserializer::new(obj.attributes().map(|k| {
+                    (Value::from(k), obj.get_attr(k).unwrap_or(Value::UNDEFINED))
+                }))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            ValueRepr::Undefined | ValueRepr::Missing(_) | ValueRepr::None => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let (variant, value) = match self.0 {
+            ValueRepr::Map(ref map, _) => {
+                let mut iter = map.iter();
+                match (iter.next(), iter.next()) {
+                    (Some((k, v)), None) => (Value::from(k.clone()), Some(v.clone())),
+                    _ => {
+                        return Err(de::Error::invalid_value(
+                            Unexpected::Map,
+                            &"map with a single key",
+                        ))
+                    }
+                }
+            }
+            ValueRepr::String(..) => (self, None),
+            _ => return Err(de::Error::invalid_type(self.unexpected(), &"string or map")),
+        };
+        visitor.visit_enum(EnumDeserializer { variant, value })
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl Value {
+    fn unexpected(&self) -> Unexpected<'_> {
+        match self.0 {
+            ValueRepr::Undefined | ValueRepr::Missing(_) | ValueRepr::None => Unexpected::Unit,
+            ValueRepr::Bool(v) => Unexpected::Bool(v),
+            ValueRepr::U64(v) => Unexpected::Unsigned(v),
+            ValueRepr::I64(v) => Unexpected::Signed(v),
+            ValueRepr::F64(v) => Unexpected::Float(v),
+            ValueRepr::Char(v) => Unexpected::Char(v),
+            ValueRepr::U128(_) | ValueRepr::I128(_) => Unexpected::Other("128-bit integer"),
+            ValueRepr::String(ref v, _) => Unexpected::Str(v),
+            ValueRepr::Bytes(ref v) => Unexpected::Bytes(v),
+            ValueRepr::Seq(_) => Unexpected::Seq,
+            ValueRepr::Map(..) | ValueRepr::Dynamic(_) => Unexpected::Map,
+        }
+    }
+}
+
+struct EnumDeserializer {
+    variant: Value,
+    value: Option<Value>,
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, VariantDeserializer), Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self.variant)
+            .map(|v| (v, VariantDeserializer { value: self.value }))
+    }
+}
+
+struct VariantDeserializer {
+    value: Option<Value>,
+}
+
+impl<'de> VariantAccess<'de> for VariantDeserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self.value {
+            Some(value) => Deserialize::deserialize(value),
+            None => Ok(()),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.value {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::invalid_type(
+                Unexpected::UnitVariant,
+                &"newtype variant",
+            )),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            Some(value) => value.deserialize_seq(visitor),
+            None => Err(de::Error::invalid_type(
+                Unexpected::UnitVariant,
+                &"tuple variant",
+            )),
+        }
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.value {
+            Some(value) => value.deserialize_map(visitor),
+            None => Err(de::Error::invalid_type(
+                Unexpected::UnitVariant,
+                &"struct variant",
+            )),
+        }
+    }
+}
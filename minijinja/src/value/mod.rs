@@ -47,6 +47,29 @@
 //! let v = u64::try_from(Value::from(42)).unwrap();
 //! ```
 //!
+//! # Map Keys
+//!
+//! Maps are not restricted to string keys.  Strings, integers, booleans and
+//! characters can all be used as keys, and floats that represent whole numbers
+//! are normalized to integers on lookup (`m[1.0]` finds the key `1`).  Maps
+//! iterate in a deterministic order: insertion order with the `preserve_order`
+//! feature and sorted by key otherwise.
+//!
+//! # None and Undefined
+//!
+//! `none` and undefined are distinct values.  `none` is an actual value (it
+//! is what `None` and the unit type convert into) whereas undefined is what
+//! a lookup of a missing variable or attribute produces.  Both are falsy and
+//! both serialize to `null`, but they are only ever equal to themselves:
+//! `none == none` and `undefined == undefined` hold while `none == undefined`
+//! does not.  Use the `none`, `defined` and `undefined` tests to tell them
+//! apart in templates:
+//!
+//! ```jinja
+//! {% if user.email is none %}no email given{% endif %}
+//! {% if user.email is undefined %}unknown user{% endif %}
+//! ```
+//!
 //! # Value Function Arguments
 //!
 //! [Filters](crate::filters) and [tests](crate::tests) can take values as arguments
@@ -106,6 +129,7 @@ use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::sync::atomic::{self, AtomicBool, AtomicUsize};
 use std::sync::Arc;
@@ -120,7 +144,7 @@ use crate::value::serialize::ValueSerializer;
 use crate::vm::State;
 
 pub use crate::value::argtypes::{from_args, ArgType, FunctionArgs, FunctionResult, Rest};
-pub use crate::value::object::Object;
+pub use crate::value::object::{BinaryOp, Object};
 
 mod argtypes;
 #[cfg(feature = "deserialization")]
@@ -168,6 +192,88 @@ pub fn serializing_for_value() -> bool {
     INTERNAL_SERIALIZATION.with(|flag| flag.load(atomic::Ordering::Relaxed))
 }
 
+/// Serializes a value to a JSON string.
+///
+/// This is what the `tojson` filter and JSON auto escaping use internally.
+/// Safe strings serialize as regular strings, undefined values and none
+/// serialize as `null`.  Dynamic objects serialize as a map of their
+/// [`pairs`](Object::pairs) if they provide them, as a list of their items if
+/// they only provide a [`len`](Object::len) and no attributes, and otherwise
+/// as a map of their [`attributes`](Object::attributes).
+///
+/// ```
+/// # use minijinja::context;
+/// use minijinja::value::{to_json, Value};
+/// let value = context!(name => Value::from_safe_string("<b>Peter</b>".into()));
+/// assert_eq!(to_json(&value).unwrap(), r#"{"name":"<b>Peter</b>"}"#);
+/// ```
+#[cfg(feature = "json")]
+#[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+pub fn to_json(value: &Value) -> Result<String, Error> {
+    serde_json::to_string(value).map_err(json_error)
+}
+
+/// Serializes a value to a pretty printed JSON string.
+///
+/// This works like [`to_json`] but indents the output.
+#[cfg(feature = "json")]
+#[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+pub fn to_json_pretty(value: &Value) -> Result<String, Error> {
+    serde_json::to_string_pretty(value).map_err(json_error)
+}
+
+#[cfg(feature = "json")]
+fn json_error(err: serde_json::Error) -> Error {
+    Error::new(ErrorKind::BadSerialization, "unable to format to JSON").with_source(err)
+}
+
+/// Merges multiple maps into a single map value.
+///
+/// This is useful if a template should be rendered with a base context
+/// (for instance global defaults) that is overridden by values provided
+/// per render.  Keys in later maps shadow the same keys of earlier maps,
+/// all other keys are inherited.  Dynamic objects contribute their
+/// [`pairs`](Object::pairs) or, if they do not provide pairs, their known
+/// [`attributes`](Object::attributes).  Other values are ignored.
+///
+/// ```
+/// # use minijinja::{context, Environment};
+/// use minijinja::value::merge_maps;
+/// let base = context!(site => "example.com", title => "Index");
+/// let overrides = context!(title => "Downloads");
+/// let env = Environment::new();
+/// let rv = env.render_str("{{ site }}: {{ title }}", merge_maps([base, overrides])).unwrap();
+/// assert_eq!(rv, "example.com: Downloads");
+/// ```
+pub fn merge_maps<I, V>(iter: I) -> Value
+where
+    I: IntoIterator<Item = V>,
+    V: Into<Value>,
+{
+    let mut rv = ValueMap::default();
+    for value in iter.into_iter() {
+        let value = value.into();
+        match value.0 {
+            ValueRepr::Map(ref map, _) => {
+                rv.extend(map.iter().map(|(k, v)| (k.clone(), v.clone())));
+            }
+            ValueRepr::Dynamic(ref obj) => {
+                if let Some(pairs) = obj.pairs() {
+                    rv.extend(pairs.filter_map(|(k, v)| Some((k.try_into_key().ok()?, v))));
+                    continue;
+                }
+                for attr in obj.attributes() {
+                    if let Some(val) = obj.get_attr(attr) {
+                        rv.insert(Key::make_string_key(attr), val);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    ValueRepr::Map(Arc::new(rv), MapType::Normal).into()
+}
+
 /// Enables a temporary code section within which some value
 /// optimizations are enabled.  Currently this is exclusively
 /// used to automatically intern keys when the `key_interning`
@@ -271,6 +377,9 @@ impl<T: Copy> Clone for Packed<T> {
 #[derive(Clone)]
 pub(crate) enum ValueRepr {
     Undefined,
+    // a missing variable under semi-strict undefined behavior.  It behaves
+    // like an undefined value but remembers the name of the variable.
+    Missing(Arc<str>),
     Bool(bool),
     U64(u64),
     I64(i64),
@@ -289,7 +398,7 @@ pub(crate) enum ValueRepr {
 impl fmt::Debug for ValueRepr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ValueRepr::Undefined => write!(f, "Undefined"),
+            ValueRepr::Undefined | ValueRepr::Missing(_) => write!(f, "Undefined"),
             ValueRepr::Bool(val) => fmt::Debug::fmt(val, f),
             ValueRepr::U64(val) => fmt::Debug::fmt(val, f),
             ValueRepr::I64(val) => fmt::Debug::fmt(val, f),
@@ -314,11 +423,29 @@ pub struct Value(pub(crate) ValueRepr);
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (&self.0, &other.0) {
+            (
+                ValueRepr::Undefined | ValueRepr::Missing(_),
+                ValueRepr::Undefined | ValueRepr::Missing(_),
+            ) => true,
             (ValueRepr::None, ValueRepr::None) => true,
             (ValueRepr::String(a, _), ValueRepr::String(b, _)) => a == b,
             (ValueRepr::Bytes(a), ValueRepr::Bytes(b)) => a == b,
+            (ValueRepr::Seq(a), ValueRepr::Seq(b)) => a == b,
+            (ValueRepr::Map(a, _), ValueRepr::Map(b, _)) => a == b,
+            (ValueRepr::Dynamic(a), _) => match a.custom_eq(other) {
+                Some(rv) => rv,
+                None => match other.0 {
+                    ValueRepr::Dynamic(ref b) => {
+                        Arc::as_ptr(a) as *const u8 == Arc::as_ptr(b) as *const u8
+                    }
+                    _ => false,
+                },
+            },
+            (_, ValueRepr::Dynamic(b)) => b.custom_eq(self).unwrap_or(false),
             _ => match ops::coerce(self, other) {
-                Some(ops::CoerceResult::F64(a, b)) => a == b,
+                Some(ops::CoerceResult::F64(..)) => {
+                    ops::cmp_mixed_numbers(self, other) == Some(Ordering::Equal)
+                }
                 Some(ops::CoerceResult::I128(a, b)) => a == b,
                 Some(ops::CoerceResult::String(a, b)) => a == b,
                 None => false,
@@ -329,14 +456,80 @@ impl PartialEq for Value {
 
 impl Eq for Value {}
 
+/// Hashes values consistently with `==`.
+///
+/// Numbers that compare equal hash the same independent of their type, so
+/// `1`, `1.0` and `true` share a hash.  Maps hash independently of the order
+/// of their entries.  Dynamic objects hash as the value returned by
+/// [`Object::custom_hash`].  Objects that do not provide one all share a
+/// single hash, which agrees with `==` only as long as they are not equal to
+/// values other than objects.
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.0 {
+            ValueRepr::Undefined | ValueRepr::Missing(_) => state.write_u8(0),
+            ValueRepr::None => state.write_u8(1),
+            ValueRepr::Bool(val) => (2u8, val as i128).hash(state),
+            ValueRepr::U64(val) => (2u8, val as i128).hash(state),
+            ValueRepr::I64(val) => (2u8, val as i128).hash(state),
+            ValueRepr::U128(val) => (2u8, val.0 as i128).hash(state),
+            ValueRepr::I128(val) => (2u8, val.0).hash(state),
+            ValueRepr::F64(val) => {
+                if val.fract() == 0.0 && val >= i128::MIN as f64 && val < i128::MAX as f64 {
+                    (2u8, val as i128).hash(state)
+                } else {
+                    (2u8, val.to_bits()).hash(state)
+                }
+            }
+            ValueRepr::Char(c) => {
+                state.write_u8(3);
+                c.hash(state);
+            }
+            ValueRepr::String(ref s, _) => {
+                state.write_u8(4);
+                s.hash(state);
+            }
+            ValueRepr::Bytes(ref b) => {
+                state.write_u8(5);
+                b.hash(state);
+            }
+            ValueRepr::Seq(ref items) => {
+                state.write_u8(6);
+                items.hash(state);
+            }
+            ValueRepr::Map(ref map, _) => {
+                state.write_u8(7);
+                state.write_usize(map.len());
+                let mut combined = 0u64;
+                for (key, value) in map.iter() {
+                    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                    key.hash(&mut hasher);
+                    value.hash(&mut hasher);
+                    combined = combined.wrapping_add(hasher.finish());
+                }
+                state.write_u64(combined);
+            }
+            ValueRepr::Dynamic(ref obj) => match obj.custom_hash() {
+                Some(value) => value.hash(state),
+                None => state.write_u8(8),
+            },
+        }
+    }
+}
+
 impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match (&self.0, &other.0) {
+            (
+                ValueRepr::Undefined | ValueRepr::Missing(_),
+                ValueRepr::Undefined | ValueRepr::Missing(_),
+            ) => Some(Ordering::Equal),
             (ValueRepr::None, ValueRepr::None) => Some(Ordering::Equal),
             (ValueRepr::String(a, _), ValueRepr::String(b, _)) => a.partial_cmp(b),
             (ValueRepr::Bytes(a), ValueRepr::Bytes(b)) => a.partial_cmp(b),
+            (ValueRepr::Seq(a), ValueRepr::Seq(b)) => a.partial_cmp(b),
             _ => match ops::coerce(self, other) {
-                Some(ops::CoerceResult::F64(a, b)) => a.partial_cmp(&b),
+                Some(ops::CoerceResult::F64(..)) => ops::cmp_mixed_numbers(self, other),
                 Some(ops::CoerceResult::I128(a, b)) => a.partial_cmp(&b),
                 Some(ops::CoerceResult::String(a, b)) => a.partial_cmp(&b),
                 None => None,
@@ -354,13 +547,13 @@ impl fmt::Debug for Value {
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self.0 {
-            ValueRepr::Undefined => Ok(()),
+            ValueRepr::Undefined | ValueRepr::Missing(_) => Ok(()),
             ValueRepr::Bool(val) => write!(f, "{}", val),
             ValueRepr::U64(val) => write!(f, "{}", val),
             ValueRepr::I64(val) => write!(f, "{}", val),
             ValueRepr::F64(val) => {
                 if val.is_nan() {
-                    write!(f, "NaN")
+                    write!(f, "nan")
                 } else if val.is_infinite() {
                     write!(f, "{}inf", if val.is_sign_negative() { "-" } else { "" })
                 } else {
@@ -402,9 +595,10 @@ impl fmt::Display for Value {
     }
 }
 
+/// The default value is [`undefined`](Value::UNDEFINED).
 impl Default for Value {
     fn default() -> Value {
-        ValueRepr::None.into()
+        Value::UNDEFINED
     }
 }
 
@@ -413,6 +607,11 @@ impl Value {
     /// The undefined value
     pub const UNDEFINED: Value = Value(ValueRepr::Undefined);
 
+    /// The none value
+    ///
+    /// This is the value of `none` in templates and what `()` converts into.
+    pub const NONE: Value = Value(ValueRepr::None);
+
     /// Creates a value from something that can be serialized.
     ///
     /// This is the method that MiniJinja will generally use whenever a serializable
@@ -486,15 +685,78 @@ impl Value {
     /// use std::sync::Arc;
     /// let val = Value::from(Arc::new(Thing { id: 42 }));
     /// ```
+    ///
+    /// The value then shares ownership of the object with the retained `Arc`,
+    /// it is not copied.  Values are immutable from the engine's point of view
+    /// but an object can use interior mutability (a `Mutex`, an atomic, …) so
+    /// that changes made through the typed handle are visible to every value
+    /// and every later render that refers to it:
+    ///
+    /// ```rust
+    /// # use minijinja::{context, Environment, value::{Value, Object}};
+    /// use std::fmt;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// #[derive(Debug, Default)]
+    /// struct Status(Mutex<String>);
+    ///
+    /// impl fmt::Display for Status {
+    ///     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    ///         write!(f, "{}", self.0.lock().unwrap())
+    ///     }
+    /// }
+    ///
+    /// impl Object for Status {}
+    ///
+    /// let handle = Arc::new(Status::default());
+    /// let ctx = context!(status => Value::from(handle.clone()));
+    /// let env = Environment::new();
+    /// *handle.0.lock().unwrap() = "running".into();
+    /// assert_eq!(env.render_str("{{ status }}", &ctx).unwrap(), "running");
+    /// *handle.0.lock().unwrap() = "done".into();
+    /// assert_eq!(env.render_str("{{ status }}", &ctx).unwrap(), "done");
+    /// ```
     pub fn from_object<T: Object>(value: T) -> Value {
         Value::from(Arc::new(value) as Arc<dyn Object>)
     }
 
+    /// Creates a value from an already type erased object.
+    ///
+    /// This is useful if you are holding on to `Arc<dyn Object>` handles
+    /// yourself.  The arc is stored as-is and not wrapped again.
+    ///
+    /// ```rust
+    /// # use minijinja::value::{Value, Object};
+    /// # #[derive(Debug)]
+    /// # struct Thing { id: usize };
+    /// # impl std::fmt::Display for Thing {
+    /// #     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    /// #         todo!();
+    /// #     }
+    /// # }
+    /// # impl Object for Thing {}
+    /// use std::sync::Arc;
+    /// let obj: Arc<dyn Object> = Arc::new(Thing { id: 42 });
+    /// let val = Value::from_dyn_object(obj);
+    /// ```
+    pub fn from_dyn_object(value: Arc<dyn Object>) -> Value {
+        Value(ValueRepr::Dynamic(value))
+    }
+
     /// Creates a callable value from a function.
     ///
+    /// The function accepts the same arguments as functions registered with
+    /// [`add_function`](crate::Environment::add_function).  This is useful to
+    /// pass helpers to a single render call without registering them on the
+    /// environment:
+    ///
     /// ```
+    /// # use minijinja::{context, Environment};
     /// # use minijinja::value::Value;
     /// let pow = Value::from_function(|a: u32| a * a);
+    /// let env = Environment::new();
+    /// let rv = env.render_str("{{ pow(4) }}", context!(pow)).unwrap();
+    /// assert_eq!(rv, "16");
     /// ```
     pub fn from_function<F, Rv, Args>(f: F) -> Value
     where
@@ -513,7 +775,7 @@ impl Value {
     /// perform operations on it.
     pub fn kind(&self) -> ValueKind {
         match self.0 {
-            ValueRepr::Undefined => ValueKind::Undefined,
+            ValueRepr::Undefined | ValueRepr::Missing(_) => ValueKind::Undefined,
             ValueRepr::Bool(_) => ValueKind::Bool,
             ValueRepr::U64(_) | ValueRepr::I64(_) | ValueRepr::F64(_) => ValueKind::Number,
             ValueRepr::Char(_) => ValueKind::Char,
@@ -533,6 +795,9 @@ impl Value {
     }
 
     /// Is this value true?
+    ///
+    /// Dynamic objects decide with [`Object::is_true`].  If they do not, they
+    /// are true unless they report a [`len`](Object::len) of zero.
     pub fn is_true(&self) -> bool {
         match self.0 {
             ValueRepr::Bool(val) => val,
@@ -544,10 +809,10 @@ impl Value {
             ValueRepr::Char(x) => x != '\x00',
             ValueRepr::String(ref x, _) => !x.is_empty(),
             ValueRepr::Bytes(ref x) => !x.is_empty(),
-            ValueRepr::None | ValueRepr::Undefined => false,
+            ValueRepr::None | ValueRepr::Undefined | ValueRepr::Missing(_) => false,
             ValueRepr::Seq(ref x) => !x.is_empty(),
             ValueRepr::Map(ref x, _) => !x.is_empty(),
-            ValueRepr::Dynamic(_) => true,
+            ValueRepr::Dynamic(ref obj) => obj.is_true().unwrap_or_else(|| obj.len() != Some(0)),
         }
     }
 
@@ -558,7 +823,7 @@ impl Value {
 
     /// Returns `true` if this value is undefined.
     pub fn is_undefined(&self) -> bool {
-        matches!(&self.0, ValueRepr::Undefined)
+        matches!(&self.0, ValueRepr::Undefined | ValueRepr::Missing(_))
     }
 
     /// Returns `true` if this value is none.
@@ -566,6 +831,24 @@ impl Value {
         matches!(&self.0, ValueRepr::None)
     }
 
+    /// Returns `true` if this value is a dynamic [`Object`].
+    ///
+    /// Plain maps and sequences are not objects.
+    pub fn is_object(&self) -> bool {
+        matches!(&self.0, ValueRepr::Dynamic(_))
+    }
+
+    /// Returns `true` if this value can be called.
+    ///
+    /// This is the case for functions, macros and objects that report to be
+    /// [callable](Object::is_callable).
+    pub fn is_callable(&self) -> bool {
+        match self.0 {
+            ValueRepr::Dynamic(ref obj) => obj.is_callable(),
+            _ => false,
+        }
+    }
+
     /// If the value is a string, return it.
     pub fn as_str(&self) -> Option<&str> {
         match &self.0 {
@@ -593,7 +876,7 @@ impl Value {
     /// ```
     pub fn as_slice(&self) -> Result<&[Value], Error> {
         match self.0 {
-            ValueRepr::Undefined | ValueRepr::None => Ok(&[][..]),
+            ValueRepr::Undefined | ValueRepr::Missing(_) | ValueRepr::None => Ok(&[][..]),
             ValueRepr::Seq(ref v) => Ok(&v[..]),
             _ => Err(Error::new(
                 ErrorKind::InvalidOperation,
@@ -616,7 +899,7 @@ impl Value {
             ValueRepr::String(ref s, _) => Some(s.chars().count()),
             ValueRepr::Map(ref items, _) => Some(items.len()),
             ValueRepr::Seq(ref items) => Some(items.len()),
-            ValueRepr::Dynamic(ref dy) => Some(dy.attributes().count()),
+            ValueRepr::Dynamic(ref dy) => Some(dy.len().unwrap_or_else(|| dy.attributes().count())),
             _ => None,
         }
     }
@@ -624,8 +907,8 @@ impl Value {
     /// Looks up an attribute by attribute name.
     ///
     /// This this returns [`UNDEFINED`](Self::UNDEFINED) when an invalid key is
-    /// resolved.  An error is returned when if the value does not contain an object
-    /// that has attributes.
+    /// resolved.  An error is returned if the value itself is undefined which
+    /// mirrors what happens for attribute lookups in templates.
     ///
     /// ```
     /// # use minijinja::value::Value;
@@ -638,18 +921,11 @@ impl Value {
     /// # Ok(()) }
     /// ```
     pub fn get_attr(&self, key: &str) -> Result<Value, Error> {
-        let value = match self.0 {
-            ValueRepr::Map(ref items, _) => {
-                let lookup_key = Key::Str(key);
-                items.get(&lookup_key).cloned()
-            }
-            ValueRepr::Dynamic(ref dy) => dy.get_attr(key),
-            ValueRepr::Undefined => {
-                return Err(Error::from(ErrorKind::UndefinedError));
-            }
-            _ => None,
-        };
-        Ok(value.unwrap_or(Value::UNDEFINED))
+        if self.is_undefined() {
+            Err(Error::from(ErrorKind::UndefinedError))
+        } else {
+            Ok(self.get_attr_opt(key).unwrap_or(Value::UNDEFINED))
+        }
     }
 
     /// Looks up an index of the value.
@@ -666,6 +942,38 @@ impl Value {
         self.get_item(&Value(ValueRepr::U64(idx as _)))
     }
 
+    /// Looks up a dotted path in the value.
+    ///
+    /// Every segment of the path is looked up in turn.  Segments that are
+    /// integers index into sequences (negative indexes count from the end)
+    /// and fall back to a string key lookup for maps and objects.  If any
+    /// segment is missing [`UNDEFINED`](Self::UNDEFINED) is returned.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// let ctx = minijinja::context! {
+    ///     users => vec![minijinja::context!(name => "Peter")],
+    /// };
+    /// assert_eq!(ctx.get_path("users.0.name").to_string(), "Peter");
+    /// assert!(ctx.get_path("users.1.name").is_undefined());
+    /// ```
+    pub fn get_path(&self, path: &str) -> Value {
+        let mut rv = self.clone();
+        for segment in path.split('.') {
+            let item = match segment.parse::<i64>() {
+                Ok(idx) => rv
+                    .get_item_opt(&Value::from(idx))
+                    .or_else(|| rv.get_attr_opt(segment)),
+                Err(_) => rv.get_attr_opt(segment),
+            };
+            rv = match item {
+                Some(item) => item,
+                None => return Value::UNDEFINED,
+            };
+        }
+        rv
+    }
+
     /// Looks up an item (or attribute) by key.
     ///
     /// This is similar to [`get_attr`](Self::get_attr) but instead of using
@@ -682,7 +990,7 @@ impl Value {
     /// assert_eq!(value.to_string(), "Foo");
     /// ```
     pub fn get_item(&self, key: &Value) -> Result<Value, Error> {
-        if let ValueRepr::Undefined = self.0 {
+        if self.is_undefined() {
             Err(Error::from(ErrorKind::UndefinedError))
         } else {
             Ok(self.get_item_opt(key).unwrap_or(Value::UNDEFINED))
@@ -696,6 +1004,9 @@ impl Value {
     ///
     /// * [`ValueKind::Map`]: the iterator yields the keys of the map.
     /// * [`ValueKind::Seq`]: the iterator yields the items in the sequence.
+    /// * [`ValueKind::String`]: the iterator yields the characters of the string.
+    /// * [`ValueKind::Map`] for objects: the iterator yields what
+    ///   [`Object::iter`] returns.
     /// * [`ValueKind::None`] / [`ValueKind::Undefined`]: the iterator is empty.
     ///
     /// ```
@@ -720,6 +1031,24 @@ impl Value {
         })
     }
 
+    /// Returns the boxed object if the value is an [`Object`].
+    ///
+    /// Unlike [`downcast_object_ref`](Self::downcast_object_ref) this does not
+    /// require knowing the concrete type which allows calling the methods of
+    /// the [`Object`] trait generically.
+    ///
+    /// ```rust
+    /// # use minijinja::value::Value;
+    /// let value = Value::from(vec![1, 2, 3]);
+    /// assert!(value.as_object().is_none());
+    /// ```
+    pub fn as_object(&self) -> Option<&dyn Object> {
+        match self.0 {
+            ValueRepr::Dynamic(ref obj) => Some(&**obj as &dyn Object),
+            _ => None,
+        }
+    }
+
     /// Returns some reference to the boxed object if it is of type `T`, or None if it isn’t.
     ///
     /// This is basically the "reverse" of [`from_object`](Self::from_object).
@@ -759,7 +1088,25 @@ impl Value {
         None
     }
 
+    /// Resolves an attribute.
+    ///
+    /// Maps resolve attributes by looking up a string key of the same name,
+    /// dynamic objects go through [`Object::get_attr`].  This is the single
+    /// place string based lookups are resolved so that `foo.bar` and
+    /// `foo["bar"]` always agree.
+    fn get_attr_opt(&self, key: &str) -> Option<Value> {
+        match self.0 {
+            ValueRepr::Map(ref items, _) => items.get(&Key::Str(key)).cloned(),
+            ValueRepr::Dynamic(ref dy) => dy.get_attr(key),
+            _ => None,
+        }
+    }
+
     fn get_item_opt(&self, key: &Value) -> Option<Value> {
+        if let Some(key) = key.as_str() {
+            return self.get_attr_opt(key);
+        }
+
         let key = some!(Key::from_borrowed_value(key).ok());
 
         match self.0 {
@@ -775,18 +1122,38 @@ impl Value {
                     return items.get(idx).cloned();
                 }
             }
-            ValueRepr::Dynamic(ref dy) => match key {
-                Key::String(ref key) => return dy.get_attr(key),
-                Key::Str(key) => return dy.get_attr(key),
-                _ => {}
-            },
+            ValueRepr::String(ref s, _) => {
+                if let Key::I64(idx) = key {
+                    let idx = some!(isize::try_from(idx).ok());
+                    let idx = if idx < 0 {
+                        some!(s.chars().count().checked_sub(-idx as usize))
+                    } else {
+                        idx as usize
+                    };
+                    return s.chars().nth(idx).map(|c| Value::from(c.to_string()));
+                }
+            }
             _ => {}
         }
         None
     }
 
     /// Calls the value directly.
-    pub(crate) fn call(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
+    ///
+    /// This is how values such as macros or functions are invoked.  As calling
+    /// requires access to the engine state, this is typically used from within
+    /// filters, tests or functions which receive the [`State`] as argument.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, State, Error};
+    /// # use minijinja::value::Value;
+    /// fn call_with_42(state: &State, value: Value) -> Result<Value, Error> {
+    ///     value.call(state, &[Value::from(42)])
+    /// }
+    /// # let mut env = Environment::new();
+    /// env.add_filter("call_with_42", call_with_42);
+    /// ```
+    pub fn call(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
         if let ValueRepr::Dynamic(ref dy) = self.0 {
             dy.call(state, args)
         } else {
@@ -807,14 +1174,28 @@ impl Value {
     }
 
     /// Calls a method on the value.
-    pub(crate) fn call_method(
-        &self,
-        state: &State,
-        name: &str,
-        args: &[Value],
-    ) -> Result<Value, Error> {
+    ///
+    /// For dynamic objects this dispatches to [`Object::call_method`], for
+    /// maps the key of the given name is looked up and called.  Objects that
+    /// implement [`Object::pairs`] additionally provide an `items()` method.
+    pub fn call_method(&self, state: &State, name: &str, args: &[Value]) -> Result<Value, Error> {
         match self.0 {
-            ValueRepr::Dynamic(ref dy) => return dy.call_method(state, name, args),
+            ValueRepr::Dynamic(ref dy) => {
+                if name == "items" && args.is_empty() {
+                    if let Some(pairs) = self.object_pairs() {
+                        return Ok(pairs);
+                    }
+                }
+                match dy.call_method(state, name, args) {
+                    Err(err) if err.kind() == ErrorKind::UnknownMethod => {
+                        return state
+                            .env()
+                            .resolve_unknown_method(state, self, name, args)
+                            .unwrap_or(Err(err));
+                    }
+                    rv => return rv,
+                }
+            }
             ValueRepr::Map(ref map, _) => {
                 if let Some(value) = map.get(&Key::Str(name)) {
                     return value.call(state, args);
@@ -822,12 +1203,29 @@ impl Value {
             }
             _ => {}
         }
+        if let Some(rv) = state.env().resolve_unknown_method(state, self, name, args) {
+            return rv;
+        }
         Err(Error::new(
-            ErrorKind::InvalidOperation,
+            ErrorKind::UnknownMethod,
             format!("object has no method named {}", name),
         ))
     }
 
+    /// Returns the pairs of an object as list of two item lists.
+    pub(crate) fn object_pairs(&self) -> Option<Value> {
+        match self.0 {
+            ValueRepr::Dynamic(ref dy) => dy.pairs().map(|pairs| {
+                Value::from(
+                    pairs
+                        .map(|(k, v)| Value::from(vec![k, v]))
+                        .collect::<Vec<_>>(),
+                )
+            }),
+            _ => None,
+        }
+    }
+
     pub(crate) fn try_into_key(self) -> Result<StaticKey, Error> {
         match self.0 {
             ValueRepr::Bool(val) => Ok(Key::Bool(val)),
@@ -864,7 +1262,9 @@ impl Value {
     /// Iterates over the value without holding a reference.
     pub(crate) fn try_iter_owned(&self) -> Result<OwnedValueIterator, Error> {
         let (iter_state, len) = match self.0 {
-            ValueRepr::None | ValueRepr::Undefined => (ValueIteratorState::Empty, 0),
+            ValueRepr::None | ValueRepr::Undefined | ValueRepr::Missing(_) => {
+                (ValueIteratorState::Empty, 0)
+            }
             ValueRepr::Seq(ref seq) => (ValueIteratorState::Seq(0, Arc::clone(seq)), seq.len()),
             #[cfg(feature = "preserve_order")]
             ValueRepr::Map(ref items, _) => {
@@ -878,10 +1278,14 @@ impl Value {
                 ),
                 items.len(),
             ),
+            ValueRepr::String(ref s, _) => (
+                ValueIteratorState::Chars(0, Arc::clone(s)),
+                s.chars().count(),
+            ),
             ValueRepr::Dynamic(ref obj) => {
-                let attrs = obj.attributes().map(Value::from).collect::<Vec<_>>();
-                let attr_count = attrs.len();
-                (ValueIteratorState::Seq(0, Arc::new(attrs)), attr_count)
+                let items = obj.iter().collect::<Vec<_>>();
+                let item_count = items.len();
+                (ValueIteratorState::Seq(0, Arc::new(items)), item_count)
             }
             _ => {
                 return Err(Error::new(
@@ -916,7 +1320,7 @@ impl Serialize for Value {
             ValueRepr::F64(f) => serializer.serialize_f64(f),
             ValueRepr::Char(c) => serializer.serialize_char(c),
             ValueRepr::None => serializer.serialize_unit(),
-            ValueRepr::Undefined => serializer.serialize_unit(),
+            ValueRepr::Undefined | ValueRepr::Missing(_) => serializer.serialize_unit(),
             ValueRepr::U128(u) => serializer.serialize_u128(u.0),
             ValueRepr::I128(i) => serializer.serialize_i128(i.0),
             ValueRepr::String(ref s, _) => serializer.serialize_str(s),
@@ -931,7 +1335,21 @@ impl Serialize for Value {
                 map.end()
             }
             ValueRepr::Dynamic(ref n) => {
-                use serde::ser::SerializeMap;
+                use serde::ser::{SerializeMap, SerializeSeq};
+                if let Some(pairs) = n.pairs() {
+                    let mut s = ok!(serializer.serialize_map(None));
+                    for (k, v) in pairs {
+                        ok!(s.serialize_entry(&k, &v));
+                    }
+                    return s.end();
+                }
+                if n.len().is_some() && n.attributes().next().is_none() {
+                    let mut s = ok!(serializer.serialize_seq(n.len()));
+                    for item in n.iter() {
+                        ok!(s.serialize_element(&item));
+                    }
+                    return s.end();
+                }
                 let mut s = ok!(serializer.serialize_map(None));
                 for k in n.attributes() {
                     let v = n.get_attr(k).unwrap_or(Value::UNDEFINED);
@@ -956,8 +1374,15 @@ impl<'a> Iterator for Iter<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         self.inner.next()
     }
+
+    #[inline(always)]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
 }
 
+impl<'a> ExactSizeIterator for Iter<'a> {}
+
 pub(crate) struct OwnedValueIterator {
     iter_state: ValueIteratorState,
     len: usize,
@@ -988,6 +1413,7 @@ impl fmt::Debug for OwnedValueIterator {
 
 enum ValueIteratorState {
     Empty,
+    Chars(usize, Arc<String>),
     Seq(usize, Arc<Vec<Value>>),
     #[cfg(not(feature = "preserve_order"))]
     Map(Option<StaticKey>, Arc<ValueMap>),
@@ -999,6 +1425,10 @@ impl ValueIteratorState {
     fn advance_state(&mut self) -> Option<Value> {
         match self {
             ValueIteratorState::Empty => None,
+            ValueIteratorState::Chars(offset, s) => s[*offset..].chars().next().map(|c| {
+                *offset += c.len_utf8();
+                Value::from(c)
+            }),
             ValueIteratorState::Seq(idx, items) => items
                 .get(*idx)
                 .map(|x| {
@@ -5,6 +5,27 @@ use crate::error::{Error, ErrorKind};
 use crate::value::Value;
 use crate::vm::State;
 
+/// A binary operator that can be implemented by objects.
+///
+/// See [`Object::custom_op`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BinaryOp {
+    /// The `+` operator.
+    Add,
+    /// The `-` operator.
+    Sub,
+    /// The `*` operator.
+    Mul,
+    /// The `/` operator.
+    Div,
+    /// The `//` operator.
+    IntDiv,
+    /// The `%` operator.
+    Rem,
+    /// The `**` operator.
+    Pow,
+}
+
 /// A utility trait that represents a dynamic object.
 ///
 /// The engine uses the [`Value`] type to represent values that the engine
@@ -22,6 +43,7 @@ use crate::vm::State;
 /// Objects need to implement [`Display`](std::fmt::Display) which is used by
 /// the engine to convert the object into a string if needed.  Additionally
 /// [`Debug`](std::fmt::Debug) is required as well.
+#[allow(clippy::len_without_is_empty)]
 pub trait Object: fmt::Display + fmt::Debug + Any + Sync + Send {
     /// Invoked by the engine to get the attribute of an object.
     ///
@@ -51,10 +73,118 @@ pub trait Object: fmt::Display + fmt::Debug + Any + Sync + Send {
         Box::new(None.into_iter())
     }
 
+    /// Iterates over the object.
+    ///
+    /// This is used by the `for` loop and [`Value::try_iter`].  The default
+    /// implementation yields the names of the [`attributes`](Self::attributes).
+    /// Objects that represent sequences can override this to yield their items
+    /// instead.  Note that the `for` loop collects all items before the first
+    /// iteration so that `loop.length` and `loop.last` are available, so the
+    /// iterator must be finite.
+    fn iter(&self) -> Box<dyn Iterator<Item = Value> + '_> {
+        Box::new(self.attributes().map(Value::from))
+    }
+
+    /// Returns the number of items of the object.
+    ///
+    /// This is used by the `length` filter and [`Value::len`].  If `None` is
+    /// returned the [`attributes`](Self::attributes) are counted instead.
+    /// Objects with many attributes or that represent sequences should
+    /// implement this.  The default implementation returns `None`.
+    fn len(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the key/value pairs of objects that behave like maps.
+    ///
+    /// If this returns an iterator the `items` filter and the `items()` method
+    /// yield these pairs, which makes `{% for k, v in obj.items() %}` work.
+    /// The default implementation returns `None`.
+    fn pairs(&self) -> Option<Box<dyn Iterator<Item = (Value, Value)> + '_>> {
+        None
+    }
+
+    /// Returns the truthiness of the object.
+    ///
+    /// This is used by `{% if %}`, the `not` operator and everywhere else a
+    /// value is checked for truthiness.  If `None` is returned the object is
+    /// true unless its [`len`](Self::len) is zero.  The default implementation
+    /// returns `None`.
+    fn is_true(&self) -> Option<bool> {
+        None
+    }
+
+    /// Implements a binary operator for the object.
+    ///
+    /// This is invoked by the engine before the default arithmetic is attempted
+    /// if the object is the left operand of an arithmetic operator (`+`, `-`,
+    /// `*`, `/`, `//`, `%` or `**`).  `other` is the right operand.  If `None`
+    /// is returned the engine falls back to the default behavior which
+    /// typically fails for objects.  The default implementation returns `None`.
+    fn custom_op(&self, op: BinaryOp, other: &Value) -> Option<Result<Value, Error>> {
+        let _op = op;
+        let _other = other;
+        None
+    }
+
+    /// Compares the object with another value for equality.
+    ///
+    /// This is invoked by the engine for `==` and `!=` and when objects are
+    /// compared as part of sequences or maps.  `other` can be any value.  If
+    /// `None` is returned an object is only considered equal to itself.  The
+    /// default implementation returns `None`.  Objects that compare equal to
+    /// values which are not objects also need to implement
+    /// [`custom_hash`](Self::custom_hash).
+    fn custom_eq(&self, other: &Value) -> Option<bool> {
+        let _other = other;
+        None
+    }
+
+    /// Returns a value that is hashed in place of the object.
+    ///
+    /// Hashing has to agree with equality.  If [`custom_eq`](Self::custom_eq)
+    /// makes the object equal to values that are not objects this must return
+    /// a value that is equal to them too.  If `None` is returned all objects
+    /// share a single hash which is only correct for objects that compare
+    /// equal to other objects exclusively.  The default implementation returns
+    /// `None`.
+    fn custom_hash(&self) -> Option<Value> {
+        None
+    }
+
+    /// Returns the value that should be written to the template output.
+    ///
+    /// This is consulted when the object is printed with `{{ obj }}` and lets an
+    /// object render differently than its [`Display`](std::fmt::Display)
+    /// implementation.  The returned value is escaped according to the current
+    /// auto escape setting, so return a value created with
+    /// [`Value::from_safe_string`] to emit markup.  If `None` is returned the
+    /// object itself is written.  An object returned from here is written with
+    /// its `Display` implementation and not rendered again.  The default
+    /// implementation returns `None`.
+    fn render(&self) -> Option<Value> {
+        None
+    }
+
+    /// Returns `true` if the object can be invoked.
+    ///
+    /// This is used by the `callable` test.  Callable objects that do not
+    /// provide a value with [`render`](Self::render) are written to the
+    /// template output as `<function>` instead of with their
+    /// [`Display`](std::fmt::Display) implementation.  Objects that implement
+    /// [`call`](Self::call) should return `true`.  The default implementation
+    /// returns `false`.
+    fn is_callable(&self) -> bool {
+        false
+    }
+
     /// Called when the engine tries to call a method on the object.
     ///
     /// It's the responsibility of the implementer to ensure that an
-    /// error is generated if an invalid method is invoked.
+    /// error is generated if an invalid method is invoked.  Unknown methods
+    /// should be reported with [`ErrorKind::UnknownMethod`] like the default
+    /// implementation does, as only that error kind is handed to the
+    /// [unknown method callback](crate::Environment::set_unknown_method_callback).
     ///
     /// To convert the arguments into arguments use the
     /// [`from_args`](crate::value::from_args) function.
@@ -62,7 +192,7 @@ pub trait Object: fmt::Display + fmt::Debug + Any + Sync + Send {
         let _state = state;
         let _args = args;
         Err(Error::new(
-            ErrorKind::InvalidOperation,
+            ErrorKind::UnknownMethod,
             format!("object has no method named {}", name),
         ))
     }
@@ -93,6 +223,42 @@ impl<T: Object> Object for std::sync::Arc<T> {
         T::attributes(self)
     }
 
+    fn iter(&self) -> Box<dyn Iterator<Item = Value> + '_> {
+        T::iter(self)
+    }
+
+    fn len(&self) -> Option<usize> {
+        T::len(self)
+    }
+
+    fn pairs(&self) -> Option<Box<dyn Iterator<Item = (Value, Value)> + '_>> {
+        T::pairs(self)
+    }
+
+    fn is_true(&self) -> Option<bool> {
+        T::is_true(self)
+    }
+
+    fn custom_op(&self, op: BinaryOp, other: &Value) -> Option<Result<Value, Error>> {
+        T::custom_op(self, op, other)
+    }
+
+    fn custom_eq(&self, other: &Value) -> Option<bool> {
+        T::custom_eq(self, other)
+    }
+
+    fn custom_hash(&self) -> Option<Value> {
+        T::custom_hash(self)
+    }
+
+    fn render(&self) -> Option<Value> {
+        T::render(self)
+    }
+
+    fn is_callable(&self) -> bool {
+        T::is_callable(self)
+    }
+
     fn call_method(&self, state: &State, name: &str, args: &[Value]) -> Result<Value, Error> {
         T::call_method(self, state, name, args)
     }
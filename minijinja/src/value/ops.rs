@@ -1,8 +1,10 @@
+use std::cmp::Ordering;
 use std::convert::{TryFrom, TryInto};
 use std::fmt::Write;
 
 use crate::error::{Error, ErrorKind};
-use crate::value::{Arc, Value, ValueKind, ValueRepr};
+use crate::utils::{AutoEscape, HtmlEscape};
+use crate::value::{Arc, BinaryOp, StringType, Value, ValueKind, ValueRepr};
 
 pub enum CoerceResult {
     I128(i128, i128),
@@ -10,7 +12,7 @@ pub enum CoerceResult {
     String(String, String),
 }
 
-fn as_f64(value: &Value) -> Option<f64> {
+pub(crate) fn as_f64(value: &Value) -> Option<f64> {
     Some(match value.0 {
         ValueRepr::Bool(x) => x as i64 as f64,
         ValueRepr::U64(x) => x as f64,
@@ -48,6 +50,44 @@ pub fn coerce(a: &Value, b: &Value) -> Option<CoerceResult> {
     }
 }
 
+/// Compares an integer with a float by their mathematical value.
+///
+/// Going through `f64` would lose precision for large integers so this
+/// instead compares the integral part as integer and only then looks at
+/// the fraction.
+fn cmp_int_float(a: i128, b: f64) -> Option<Ordering> {
+    if b.is_nan() {
+        None
+    } else if b >= i128::MAX as f64 {
+        Some(Ordering::Less)
+    } else if b < i128::MIN as f64 {
+        Some(Ordering::Greater)
+    } else {
+        let trunc = b.trunc();
+        Some(a.cmp(&(trunc as i128)).then_with(|| {
+            if b > trunc {
+                Ordering::Less
+            } else if b < trunc {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        }))
+    }
+}
+
+/// Compares two numbers where at least one is a float.
+pub fn cmp_mixed_numbers(a: &Value, b: &Value) -> Option<Ordering> {
+    match (&a.0, &b.0) {
+        (ValueRepr::F64(a), ValueRepr::F64(b)) => a.partial_cmp(b),
+        (ValueRepr::F64(a), _) => {
+            cmp_int_float(some!(i128::try_from(b.clone()).ok()), *a).map(Ordering::reverse)
+        }
+        (_, ValueRepr::F64(b)) => cmp_int_float(some!(i128::try_from(a.clone()).ok()), *b),
+        _ => None,
+    }
+}
+
 fn get_offset_and_len<F: FnOnce() -> usize>(
     start: i64,
     stop: Option<i64>,
@@ -148,9 +188,20 @@ fn failed_op(op: &str, lhs: &Value, rhs: &Value) -> Error {
     )
 }
 
+/// Gives objects on the left side of an operator a chance to handle it.
+fn custom_op(op: BinaryOp, lhs: &Value, rhs: &Value) -> Option<Result<Value, Error>> {
+    match lhs.0 {
+        ValueRepr::Dynamic(ref obj) => obj.custom_op(op, rhs),
+        _ => None,
+    }
+}
+
 macro_rules! math_binop {
-    ($name:ident, $int:ident, $float:tt) => {
+    ($name:ident, $op:ident, $int:ident, $float:tt) => {
         pub fn $name(lhs: &Value, rhs: &Value) -> Result<Value, Error> {
+            if let Some(rv) = custom_op(BinaryOp::$op, lhs, rhs) {
+                return rv;
+            }
             match coerce(lhs, rhs) {
                 Some(CoerceResult::I128(a, b)) => match a.$int(b) {
                     Some(val) => Ok(int_as_value(val)),
@@ -164,6 +215,9 @@ macro_rules! math_binop {
 }
 
 pub fn add(lhs: &Value, rhs: &Value) -> Result<Value, Error> {
+    if let Some(rv) = custom_op(BinaryOp::Add, lhs, rhs) {
+        return rv;
+    }
     match coerce(lhs, rhs) {
         Some(CoerceResult::I128(a, b)) => Ok(int_as_value(a.wrapping_add(b))),
         Some(CoerceResult::F64(a, b)) => Ok((a + b).into()),
@@ -172,11 +226,14 @@ pub fn add(lhs: &Value, rhs: &Value) -> Result<Value, Error> {
     }
 }
 
-math_binop!(sub, checked_sub, -);
-math_binop!(mul, checked_mul, *);
-math_binop!(rem, checked_rem_euclid, %);
+math_binop!(sub, Sub, checked_sub, -);
+math_binop!(mul, Mul, checked_mul, *);
+math_binop!(rem, Rem, checked_rem_euclid, %);
 
 pub fn div(lhs: &Value, rhs: &Value) -> Result<Value, Error> {
+    if let Some(rv) = custom_op(BinaryOp::Div, lhs, rhs) {
+        return rv;
+    }
     fn do_it(lhs: &Value, rhs: &Value) -> Option<Value> {
         let a = some!(as_f64(lhs));
         let b = some!(as_f64(rhs));
@@ -186,6 +243,9 @@ pub fn div(lhs: &Value, rhs: &Value) -> Result<Value, Error> {
 }
 
 pub fn int_div(lhs: &Value, rhs: &Value) -> Result<Value, Error> {
+    if let Some(rv) = custom_op(BinaryOp::IntDiv, lhs, rhs) {
+        return rv;
+    }
     match coerce(lhs, rhs) {
         Some(CoerceResult::I128(a, b)) => {
             if b != 0 {
@@ -201,6 +261,9 @@ pub fn int_div(lhs: &Value, rhs: &Value) -> Result<Value, Error> {
 
 /// Implements a binary `pow` operation on values.
 pub fn pow(lhs: &Value, rhs: &Value) -> Result<Value, Error> {
+    if let Some(rv) = custom_op(BinaryOp::Pow, lhs, rhs) {
+        return rv;
+    }
     match coerce(lhs, rhs) {
         Some(CoerceResult::I128(a, b)) => {
             match TryFrom::try_from(b).ok().and_then(|b| a.checked_pow(b)) {
@@ -232,20 +295,40 @@ pub fn neg(val: &Value) -> Result<Value, Error> {
 }
 
 /// Attempts a string concatenation.
-pub fn string_concat(mut left: Value, right: &Value) -> Value {
-    match left.0 {
-        // if we're a string and we have a single reference to it, we can
-        // directly append into ourselves and reconstruct the value
-        ValueRepr::String(ref mut s, _) => {
-            write!(Arc::make_mut(s), "{}", right).ok();
-            left
+/// Concatenates two values as strings.
+///
+/// The result is only marked as safe if both operands are safe.  With HTML
+/// auto escaping a safe operand escapes the other operand instead so that
+/// the result can be safe.
+pub fn string_concat(mut left: Value, right: &Value, auto_escape: AutoEscape) -> Value {
+    let html = matches!(auto_escape, AutoEscape::Html);
+    match (left.is_safe(), right.is_safe()) {
+        (true, true) => Value::from_safe_string(format!("{}{}", left, right)),
+        (true, false) if html => {
+            Value::from_safe_string(format!("{}{}", left, HtmlEscape(&right.to_string())))
+        }
+        (false, true) if html => {
+            Value::from_safe_string(format!("{}{}", HtmlEscape(&left.to_string()), right))
         }
-        // otherwise we use format! to concat the two values
-        _ => Value::from(format!("{}{}", left, right)),
+        _ => match left.0 {
+            // if we're a string and we have a single reference to it, we can
+            // directly append into ourselves and reconstruct the value
+            ValueRepr::String(ref mut s, ref mut ty) => {
+                write!(Arc::make_mut(s), "{}", right).ok();
+                *ty = StringType::Normal;
+                left
+            }
+            // otherwise we use format! to concat the two values
+            _ => Value::from(format!("{}{}", left, right)),
+        },
     }
 }
 
 /// Implements a containment operation on values.
+///
+/// Strings check for substrings, sequences compare their items with `==`
+/// and maps check their keys.  Dynamic objects check the keys of their
+/// pairs if they provide them and the items they iterate over otherwise.
 pub fn contains(container: &Value, value: &Value) -> Result<Value, Error> {
     match container.0 {
         ValueRepr::Seq(ref values) => Ok(Value::from(values.contains(value))),
@@ -263,6 +346,12 @@ pub fn contains(container: &Value, value: &Value) -> Result<Value, Error> {
                 s.contains(&value.to_string())
             }));
         }
+        ValueRepr::Dynamic(ref obj) => {
+            if let Some(mut pairs) = obj.pairs() {
+                return Ok(Value::from(pairs.any(|(k, _)| &k == value)));
+            }
+            Ok(Value::from(obj.iter().any(|item| &item == value)))
+        }
         _ => Err(Error::new(
             ErrorKind::InvalidOperation,
             "cannot perform a containment check on this value",
@@ -331,11 +420,29 @@ fn test_dividing() {
 #[test]
 fn test_concat() {
     assert_eq!(
-        string_concat(Value::from("foo"), &Value::from(42)),
+        string_concat(Value::from("foo"), &Value::from(42), AutoEscape::None),
         Value::from("foo42")
     );
     assert_eq!(
-        string_concat(Value::from(23), &Value::from(42)),
+        string_concat(Value::from(23), &Value::from(42), AutoEscape::None),
         Value::from("2342")
     );
 }
+
+#[test]
+fn test_concat_safe() {
+    let safe = Value::from_safe_string("<b>".into());
+    let plain = Value::from("<i>");
+    let rv = string_concat(safe.clone(), &plain, AutoEscape::Html);
+    assert!(rv.is_safe());
+    assert_eq!(rv.as_str(), Some("<b>&lt;i&gt;"));
+    let rv = string_concat(plain.clone(), &safe, AutoEscape::Html);
+    assert!(rv.is_safe());
+    assert_eq!(rv.as_str(), Some("&lt;i&gt;<b>"));
+    let rv = string_concat(safe.clone(), &safe, AutoEscape::None);
+    assert!(rv.is_safe());
+    assert_eq!(rv.as_str(), Some("<b><b>"));
+    let rv = string_concat(safe, &plain, AutoEscape::None);
+    assert!(!rv.is_safe());
+    assert_eq!(rv.as_str(), Some("<b><i>"));
+}
@@ -4,7 +4,7 @@ use std::sync::Arc;
 
 use crate::environment::Environment;
 use crate::error::{Error, ErrorKind};
-use crate::value::{OwnedValueIterator, Value};
+use crate::value::{OwnedValueIterator, Value, ValueRepr};
 use crate::vm::loop_object::Loop;
 
 type Locals<'env> = BTreeMap<&'env str, Value>;
@@ -171,7 +171,8 @@ impl<'env> Context<'env> {
         for frame in self.stack.iter().rev() {
             // look at locals first
             if let Some(value) = frame.locals.get(key) {
-                if !value.is_undefined() {
+                // missing variables stay missing when assigned
+                if !value.is_undefined() || matches!(value.0, ValueRepr::Missing(_)) {
                     return Some(value.clone());
                 }
             }
@@ -196,6 +197,38 @@ impl<'env> Context<'env> {
         env.get_global(key)
     }
 
+    /// Returns all variables visible in the current scope.
+    ///
+    /// Inner frames shadow outer ones and globals of the environment are
+    /// only included if no frame defines a variable of the same name.
+    pub fn visible_locals<'a>(&'a self, env: &'a Environment) -> BTreeMap<&'a str, Value> {
+        let mut rv = BTreeMap::new();
+        for frame in self.stack.iter().rev() {
+            for (key, value) in frame.locals.iter() {
+                if !value.is_undefined() {
+                    rv.entry(*key).or_insert_with(|| value.clone());
+                }
+            }
+
+            if let Some(ref l) = frame.current_loop {
+                if l.with_loop_var {
+                    rv.entry("loop")
+                        .or_insert_with(|| Value::from(l.object.clone()));
+                }
+            }
+
+            for (key, value) in frame.ctx.iter_as_str_map() {
+                if !value.is_undefined() {
+                    rv.entry(key).or_insert(value);
+                }
+            }
+        }
+        for (key, value) in env.globals.iter() {
+            rv.entry(key as &str).or_insert_with(|| value.clone());
+        }
+        rv
+    }
+
     /// Pushes a new layer.
     pub fn push_frame(&mut self, layer: Frame<'env>) -> Result<(), Error> {
         self.check_depth()?;
@@ -233,9 +266,8 @@ impl<'env> Context<'env> {
     /// Increase the stack depth.
     #[allow(unused)]
     pub fn incr_depth(&mut self, delta: usize) -> Result<(), Error> {
-        self.check_depth()?;
         self.outer_stack_depth += delta;
-        Ok(())
+        self.check_depth()
     }
 
     /// Decrease the stack depth.
@@ -84,7 +84,7 @@ impl Object for Loop {
             }
         } else {
             Err(Error::new(
-                ErrorKind::InvalidOperation,
+                ErrorKind::UnknownMethod,
                 format!("loop object has no method named {}", name),
             ))
         }
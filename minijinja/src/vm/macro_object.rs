@@ -62,6 +62,14 @@ impl Object for Macro {
         }
     }
 
+    fn render(&self) -> Option<Value> {
+        Some(Value::from(self.to_string()))
+    }
+
+    fn is_callable(&self) -> bool {
+        true
+    }
+
     fn call(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
         let (args, kwargs) = match args.last() {
             Some(Value(ValueRepr::Map(kwargs, MapType::Kwargs))) => {
@@ -141,7 +149,19 @@ impl Object for Macro {
         // Because macros cannot return anything other than strings (most importantly they)
         // can't return other macros this is however not an issue, as modifications in the
         // macro cannot leak out.
-        ok!(vm.eval_macro(instructions, *offset, closure, &mut out, state, arg_values));
+        ok!(vm
+            .eval_macro(instructions, *offset, closure, &mut out, state, arg_values)
+            .map_err(|err| {
+                #[cfg(feature = "debug")]
+                {
+                    if state.env().debug() {
+                        let mut err = err;
+                        err.leave_template();
+                        return err;
+                    }
+                }
+                err
+            }));
 
         Ok(if !matches!(state.auto_escape(), AutoEscape::None) {
             Value::from_safe_string(rv)
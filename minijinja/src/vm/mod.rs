@@ -8,9 +8,10 @@ use crate::compiler::instructions::{
 };
 use crate::environment::Environment;
 use crate::error::{Error, ErrorKind};
+use crate::key::Key;
 use crate::output::{CaptureMode, Output};
-use crate::utils::AutoEscape;
-use crate::value::{self, ops, MapType, Value, ValueMap, ValueRepr};
+use crate::utils::{AutoEscape, UndefinedBehavior};
+use crate::value::{self, ops, MapType, Value, ValueKind, ValueMap, ValueRepr};
 use crate::vm::context::{Context, Frame, LoopState, Stack};
 use crate::vm::loop_object::Loop;
 use crate::vm::state::BlockStack;
@@ -26,9 +27,30 @@ mod loop_object;
 mod macro_object;
 mod state;
 
-// the cost of a single include against the stack limit.
-#[cfg(feature = "multi-template")]
-const INCLUDE_RECURSION_COST: usize = 10;
+// the cost of a single include or nested render against the stack limit.
+pub(crate) const INCLUDE_RECURSION_COST: usize = 10;
+
+/// Fails if the value stands in for a missing variable.
+///
+/// With the semi-strict undefined behavior missing variables are looked up
+/// as a marker that remembers the name of the variable.  The marker can be
+/// assigned, tested, defaulted, iterated over and used in conditions like
+/// any undefined value, every other use of it fails.
+#[inline(always)]
+pub(crate) fn ensure_defined(value: &Value) -> Result<(), Error> {
+    match value.0 {
+        ValueRepr::Missing(ref name) => Err(Error::new(
+            ErrorKind::UndefinedError,
+            format!("{} is undefined", name),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Fails if any of the values stands in for a missing variable.
+fn ensure_all_defined(values: &[Value]) -> Result<(), Error> {
+    values.iter().try_for_each(ensure_defined)
+}
 
 /// Helps to evaluate something.
 #[cfg_attr(feature = "internal_debug", derive(Debug))]
@@ -62,6 +84,29 @@ where
     }
 }
 
+/// Spreads arguments that were packed due to argument unpacking onto the stack.
+///
+/// Returns the number of values that make up the arguments on the stack,
+/// including `offset` values that precede the packed arguments (such as the
+/// object a method is called on).
+fn spread_args(stack: &mut Stack, arg_count: Option<u16>, offset: usize) -> usize {
+    let packed = match arg_count {
+        Some(count) => return count as usize,
+        None => stack.pop(),
+    };
+    let mut args = packed.as_slice().unwrap_or_default();
+    // unpacking an empty map must not pass empty kwargs
+    if let Some(last) = args.last() {
+        if last.is_kwargs() && last.len() == Some(0) {
+            args = &args[..args.len() - 1];
+        }
+    }
+    for arg in args {
+        stack.push(arg.clone());
+    }
+    args.len() + offset
+}
+
 impl<'env> Vm<'env> {
     /// Creates a new VM.
     pub fn new(env: &'env Environment<'env>) -> Vm<'env> {
@@ -77,11 +122,33 @@ impl<'env> Vm<'env> {
         out: &mut Output,
         auto_escape: AutoEscape,
     ) -> Result<Option<Value>, Error> {
-        value::with_value_optimization(|| {
+        self.eval_nested(instructions, root, blocks, out, auto_escape, 0)
+    }
+
+    /// Evaluates the given inputs on behalf of an outer evaluation.
+    ///
+    /// The `depth` is the recursion depth of the outer evaluation so that
+    /// the recursion limit is shared.
+    pub fn eval_nested(
+        &self,
+        instructions: &Instructions<'env>,
+        root: Value,
+        blocks: &BTreeMap<&'env str, Instructions<'env>>,
+        out: &mut Output,
+        auto_escape: AutoEscape,
+        depth: usize,
+    ) -> Result<Option<Value>, Error> {
+        if self.env.trim_output_whitespace() {
+            out.collapse_blank_lines();
+        }
+        out.set_max_size(self.env.max_output_size());
+        let rv = ok!(value::with_value_optimization(|| {
+            let mut ctx = Context::new(Frame::new(root));
+            ok!(ctx.incr_depth(depth));
             self.eval_state(
                 &mut State {
                     env: self.env,
-                    ctx: Context::new(Frame::new(root)),
+                    ctx,
                     current_block: None,
                     instructions,
                     auto_escape,
@@ -92,7 +159,9 @@ impl<'env> Vm<'env> {
                 },
                 out,
             )
-        })
+        }));
+        ok!(out.finish());
+        Ok(rv)
     }
 
     /// Evaluate a macro in a state.
@@ -107,6 +176,7 @@ impl<'env> Vm<'env> {
         state: &State,
         args: Vec<Value>,
     ) -> Result<Option<Value>, Error> {
+        out.set_max_size(self.env.max_output_size());
         value::with_value_optimization(|| {
             let mut ctx = Context::new(Frame::new(root));
             ok!(ctx.incr_depth(state.ctx.depth()));
@@ -187,6 +257,7 @@ impl<'env> Vm<'env> {
                 ($method:ident) => {{
                     b = stack.pop();
                     a = stack.pop();
+                    ctx_ok!(ensure_defined(&a).and_then(|_| ensure_defined(&b)));
                     stack.push(ctx_ok!(ops::$method(&a, &b)));
                 }};
             }
@@ -195,6 +266,7 @@ impl<'env> Vm<'env> {
                 ($op:tt) => {{
                     b = stack.pop();
                     a = stack.pop();
+                    ctx_ok!(ensure_defined(&a).and_then(|_| ensure_defined(&b)));
                     stack.push(Value::from(a $op b));
                 }};
             }
@@ -202,6 +274,11 @@ impl<'env> Vm<'env> {
             macro_rules! bail {
                 ($err:expr) => {{
                     err = $err;
+                    if err.kind() == ErrorKind::WriteFailure {
+                        if let Some(limit_err) = out.limit_error() {
+                            err = limit_err;
+                        }
+                    }
                     process_err(&mut err, pc, state);
                     return Err(err);
                 }};
@@ -218,29 +295,73 @@ impl<'env> Vm<'env> {
 
             match instr {
                 Instruction::EmitRaw(val) => {
-                    // this only produces a format error, no need to attach
-                    // location information.
-                    ok!(out.write_str(val).map_err(Error::from));
+                    ctx_ok!(out.write_template_data(val).map_err(Error::from));
+                }
+                Instruction::EmitRawVerbatim(val) => {
+                    ctx_ok!(out.write_str(val).map_err(Error::from));
+                }
+                Instruction::EmitComment(val) => {
+                    let (prefix, suffix) = self.env.comment_delimiters();
+                    ctx_ok!(out.write_template_data(prefix).map_err(Error::from));
+                    ctx_ok!(out.write_template_data(val).map_err(Error::from));
+                    ctx_ok!(out.write_template_data(suffix).map_err(Error::from));
+                }
+                Instruction::EmitTrailingNewline(val) => {
+                    if self.env.keep_trailing_newline() {
+                        ctx_ok!(out.write_template_data(val).map_err(Error::from));
+                    }
                 }
                 Instruction::Emit => {
-                    ctx_ok!(self.env.format(&stack.pop(), state, out));
+                    a = stack.pop();
+                    ctx_ok!(ensure_defined(&a));
+                    ctx_ok!(self.env.format(&a, state, out));
                 }
                 Instruction::StoreLocal(name) => {
                     state.ctx.store(name, stack.pop());
                 }
-                Instruction::Lookup(name) => {
-                    stack.push(state.ctx.load(self.env, name).unwrap_or(Value::UNDEFINED));
-                }
+                Instruction::Lookup(name) => match state.ctx.load(self.env, name) {
+                    // variables assigned from a missing variable keep the marker
+                    Some(value)
+                        if !value.is_undefined() || matches!(value.0, ValueRepr::Missing(_)) =>
+                    {
+                        stack.push(value)
+                    }
+                    _ => match self.env.resolve_undefined_variable(state, name) {
+                        Some(value) => stack.push(value),
+                        None => {
+                            if self.env.undefined_behavior() == UndefinedBehavior::SemiStrict {
+                                stack.push(Value(ValueRepr::Missing(Arc::from(*name))));
+                            } else {
+                                stack.push(Value::UNDEFINED);
+                            }
+                        }
+                    },
+                },
                 Instruction::GetAttr(name) => {
                     a = stack.pop();
-                    stack.push(ctx_ok!(a.get_attr(name)));
+                    ctx_ok!(ensure_defined(&a));
+                    if a.is_undefined()
+                        && self.env.undefined_behavior() == UndefinedBehavior::SemiStrict
+                    {
+                        stack.push(Value::UNDEFINED);
+                    } else {
+                        stack.push(ctx_ok!(a.get_attr(name)));
+                    }
                 }
                 Instruction::GetItem => {
                     a = stack.pop();
                     b = stack.pop();
-                    stack.push(ctx_ok!(b.get_item(&a)));
+                    ctx_ok!(ensure_defined(&b).and_then(|_| ensure_defined(&a)));
+                    if b.is_undefined()
+                        && self.env.undefined_behavior() == UndefinedBehavior::SemiStrict
+                    {
+                        stack.push(Value::UNDEFINED);
+                    } else {
+                        stack.push(ctx_ok!(b.get_item(&a)));
+                    }
                 }
                 Instruction::Slice => {
+                    ctx_ok!(ensure_all_defined(stack.slice_top(4)));
                     let step = stack.pop();
                     let stop = stack.pop();
                     b = stack.pop();
@@ -251,15 +372,16 @@ impl<'env> Vm<'env> {
                     stack.push(value.clone());
                 }
                 Instruction::BuildMap(pair_count) => {
+                    ctx_ok!(ensure_all_defined(stack.slice_top(*pair_count * 2)));
                     let mut map = ValueMap::new();
-                    for _ in 0..*pair_count {
-                        let value = stack.pop();
-                        let key = ctx_ok!(stack.pop().try_into_key());
-                        map.insert(key, value);
+                    for pair in stack.slice_top(*pair_count * 2).chunks_exact(2) {
+                        map.insert(ctx_ok!(pair[0].clone().try_into_key()), pair[1].clone());
                     }
+                    stack.drop_top(*pair_count * 2);
                     stack.push(Value(ValueRepr::Map(map.into(), MapType::Normal)))
                 }
                 Instruction::BuildKwargs(pair_count) => {
+                    ctx_ok!(ensure_all_defined(stack.slice_top(*pair_count * 2)));
                     let mut map = ValueMap::new();
                     for _ in 0..*pair_count {
                         let value = stack.pop();
@@ -269,6 +391,7 @@ impl<'env> Vm<'env> {
                     stack.push(Value(ValueRepr::Map(map.into(), MapType::Kwargs)))
                 }
                 Instruction::BuildList(count) => {
+                    ctx_ok!(ensure_all_defined(stack.slice_top(*count)));
                     let mut v = Vec::with_capacity(*count);
                     for _ in 0..*count {
                         v.push(stack.pop());
@@ -291,6 +414,78 @@ impl<'env> Vm<'env> {
                         ));
                     }
                 }
+                Instruction::ListExtend => {
+                    a = stack.pop();
+                    let iter = ctx_ok!(a.try_iter_owned());
+                    if let ValueRepr::Seq(mut v) = stack.pop().0 {
+                        Arc::make_mut(&mut v).extend(iter);
+                        stack.push(Value(ValueRepr::Seq(v)))
+                    } else {
+                        bail!(Error::new(
+                            ErrorKind::InvalidOperation,
+                            "cannot extend non-list"
+                        ));
+                    }
+                }
+                Instruction::MapUpdate => {
+                    a = stack.pop();
+                    if a.kind() != ValueKind::Map {
+                        bail!(Error::new(
+                            ErrorKind::InvalidOperation,
+                            format!("cannot unpack value of type {} into a map", a.kind())
+                        ));
+                    }
+                    if let ValueRepr::Map(mut map, MapType::Normal) = stack.pop().0 {
+                        let target = Arc::make_mut(&mut map);
+                        for key in ctx_ok!(a.try_iter()) {
+                            let value = ctx_ok!(a.get_item(&key));
+                            target.insert(ctx_ok!(key.try_into_key()), value);
+                        }
+                        stack.push(Value(ValueRepr::Map(map, MapType::Normal)))
+                    } else {
+                        bail!(Error::new(
+                            ErrorKind::InvalidOperation,
+                            "cannot update non-map"
+                        ));
+                    }
+                }
+                Instruction::MergeKwargs => {
+                    a = stack.pop();
+                    if a.kind() != ValueKind::Map {
+                        bail!(Error::new(
+                            ErrorKind::InvalidOperation,
+                            format!(
+                                "cannot unpack value of type {} as keyword arguments",
+                                a.kind()
+                            )
+                        ));
+                    }
+                    if let ValueRepr::Map(ref map, _) = a.0 {
+                        if map.keys().any(|key| key.as_str().is_none()) {
+                            bail!(Error::new(
+                                ErrorKind::InvalidOperation,
+                                "keywords must be strings"
+                            ));
+                        }
+                    }
+                    if let ValueRepr::Map(mut map, MapType::Kwargs) = stack.pop().0 {
+                        let kwargs = Arc::make_mut(&mut map);
+                        for (key, value) in a.iter_as_str_map() {
+                            if kwargs.insert(Key::make_string_key(key), value).is_some() {
+                                bail!(Error::new(
+                                    ErrorKind::InvalidOperation,
+                                    format!("got multiple values for keyword argument {}", key)
+                                ));
+                            }
+                        }
+                        stack.push(Value(ValueRepr::Map(map, MapType::Kwargs)))
+                    } else {
+                        bail!(Error::new(
+                            ErrorKind::InvalidOperation,
+                            "cannot merge into non-kwargs"
+                        ));
+                    }
+                }
                 Instruction::Add => func_binop!(add),
                 Instruction::Sub => func_binop!(sub),
                 Instruction::Mul => func_binop!(mul),
@@ -311,15 +506,18 @@ impl<'env> Vm<'env> {
                 Instruction::StringConcat => {
                     a = stack.pop();
                     b = stack.pop();
-                    stack.push(ops::string_concat(b, &a));
+                    ctx_ok!(ensure_defined(&b).and_then(|_| ensure_defined(&a)));
+                    stack.push(ops::string_concat(b, &a, state.auto_escape));
                 }
                 Instruction::In => {
                     a = stack.pop();
                     b = stack.pop();
+                    ctx_ok!(ensure_defined(&b).and_then(|_| ensure_defined(&a)));
                     stack.push(ctx_ok!(ops::contains(&a, &b)));
                 }
                 Instruction::Neg => {
                     a = stack.pop();
+                    ctx_ok!(ensure_defined(&a));
                     stack.push(ctx_ok!(ops::neg(&a)));
                 }
                 Instruction::PushWith => {
@@ -420,7 +618,8 @@ impl<'env> Vm<'env> {
                 Instruction::EndCapture => {
                     stack.push(out.end_capture(state.auto_escape));
                 }
-                Instruction::ApplyFilter(name, arg_count, local_id) => {
+                Instruction::ApplyFilter(name, arg_count, local_id)
+                | Instruction::ApplyChainedFilter(name, arg_count, local_id) => {
                     let filter =
                         ctx_ok!(get_or_lookup_local(&mut loaded_filters, *local_id, || {
                             state.env.get_filter(name)
@@ -432,7 +631,15 @@ impl<'env> Vm<'env> {
                             )
                         }));
                     let args = stack.slice_top(*arg_count);
-                    a = ctx_ok!(filter.apply_to(state, args));
+                    // the filtered value is checked when the filter converts
+                    // it so that filters taking any value, like `default`,
+                    // can handle missing variables.
+                    ctx_ok!(ensure_all_defined(args.get(1..).unwrap_or_default()));
+                    a = ctx_ok!(if let Instruction::ApplyChainedFilter(..) = instr {
+                        filter.apply_chained(state, args)
+                    } else {
+                        filter.apply_to(state, args)
+                    });
                     stack.drop_top(*arg_count);
                     stack.push(a);
                 }
@@ -449,9 +656,11 @@ impl<'env> Vm<'env> {
                     stack.push(Value::from(rv));
                 }
                 Instruction::CallFunction(function_name, arg_count) => {
+                    let arg_count = spread_args(&mut stack, *arg_count, 0);
+                    ctx_ok!(ensure_all_defined(stack.slice_top(arg_count)));
                     // super is a special function reserved for super-ing into blocks.
                     if *function_name == "super" {
-                        if *arg_count != 0 {
+                        if arg_count != 0 {
                             bail!(Error::new(
                                 ErrorKind::InvalidOperation,
                                 "super() takes no arguments",
@@ -460,18 +669,19 @@ impl<'env> Vm<'env> {
                         stack.push(ctx_ok!(self.perform_super(state, out, true)));
                     // loop is a special name which when called recurses the current loop.
                     } else if *function_name == "loop" {
-                        if *arg_count != 1 {
+                        if arg_count != 1 {
                             bail!(Error::new(
                                 ErrorKind::InvalidOperation,
-                                format!("loop() takes one argument, got {}", *arg_count)
+                                format!("loop() takes one argument, got {}", arg_count)
                             ));
                         }
                         // leave the one argument on the stack for the recursion
                         recurse_loop!(true);
                     } else if let Some(func) = state.ctx.load(self.env, function_name) {
-                        let args = stack.slice_top(*arg_count);
+                        ctx_ok!(ensure_defined(&func));
+                        let args = stack.slice_top(arg_count);
                         a = ctx_ok!(func.call(state, args));
-                        stack.drop_top(*arg_count);
+                        stack.drop_top(arg_count);
                         stack.push(a);
                     } else {
                         bail!(Error::new(
@@ -481,15 +691,19 @@ impl<'env> Vm<'env> {
                     }
                 }
                 Instruction::CallMethod(name, arg_count) => {
-                    let args = stack.slice_top(*arg_count);
+                    let arg_count = spread_args(&mut stack, *arg_count, 1);
+                    let args = stack.slice_top(arg_count);
+                    ctx_ok!(ensure_all_defined(args));
                     a = ctx_ok!(args[0].call_method(state, name, &args[1..]));
-                    stack.drop_top(*arg_count);
+                    stack.drop_top(arg_count);
                     stack.push(a);
                 }
                 Instruction::CallObject(arg_count) => {
-                    let args = stack.slice_top(*arg_count);
+                    let arg_count = spread_args(&mut stack, *arg_count, 1);
+                    let args = stack.slice_top(arg_count);
+                    ctx_ok!(ensure_all_defined(args));
                     a = ctx_ok!(args[0].call(state, &args[1..]));
-                    stack.drop_top(*arg_count);
+                    stack.drop_top(arg_count);
                     stack.push(a);
                 }
                 Instruction::DupTop => {
@@ -597,7 +811,8 @@ impl<'env> Vm<'env> {
                     "template name was not a string",
                 )
             }));
-            let tmpl = match self.env.get_template(name) {
+            let name = self.env.join_template_path(name, state.instructions.name());
+            let tmpl = match self.env.get_template(&name) {
                 Ok(tmpl) => tmpl,
                 Err(err) => {
                     if err.kind() == ErrorKind::TemplateNotFound {
@@ -610,7 +825,8 @@ impl<'env> Vm<'env> {
             };
             let old_escape = mem::replace(&mut state.auto_escape, tmpl.initial_auto_escape());
             let old_instructions = mem::replace(&mut state.instructions, tmpl.instructions());
-            let old_blocks = mem::replace(&mut state.blocks, prepare_blocks(tmpl.blocks()));
+            let old_blocks =
+                mem::replace(&mut state.blocks, prepare_blocks(tmpl.compiled_blocks()));
             ok!(state.ctx.incr_depth(INCLUDE_RECURSION_COST));
             let rv = self.eval_state(state, out);
             state.ctx.decr_depth(INCLUDE_RECURSION_COST);
@@ -618,11 +834,21 @@ impl<'env> Vm<'env> {
             state.instructions = old_instructions;
             state.blocks = old_blocks;
             ok!(rv.map_err(|err| {
-                Error::new(
+                let include_err = Error::new(
                     ErrorKind::BadInclude,
                     format!("error in \"{}\"", tmpl.name()),
-                )
-                .with_source(err)
+                );
+                #[cfg(feature = "debug")]
+                {
+                    if self.env.debug() {
+                        let mut err = err;
+                        let mut include_err = include_err;
+                        err.leave_template();
+                        include_err.take_trace(&mut err);
+                        return include_err.with_source(err);
+                    }
+                }
+                include_err.with_source(err)
             }));
             return Ok(());
         }
@@ -718,6 +944,8 @@ impl<'env> Vm<'env> {
                 ))
             }
         };
+        let name = self.env.join_template_path(name, state.instructions.name());
+        let name = &name as &str;
         if state.loaded_templates.contains(&name) {
             return Err(Error::new(
                 ErrorKind::InvalidOperation,
@@ -729,7 +957,7 @@ impl<'env> Vm<'env> {
         }
         let tmpl = ok!(self.env.get_template(name));
         state.loaded_templates.insert(tmpl.instructions().name());
-        for (name, instr) in tmpl.blocks().iter() {
+        for (name, instr) in tmpl.compiled_blocks().iter() {
             state
                 .blocks
                 .entry(name)
@@ -798,10 +1026,19 @@ impl<'env> Vm<'env> {
 
     fn unpack_list(&self, stack: &mut Stack, count: &usize) -> Result<(), Error> {
         let top = stack.pop();
-        let v =
-            ok!(top
-                .as_slice()
-                .map_err(|e| Error::new(ErrorKind::CannotUnpack, "not a sequence").with_source(e)));
+        let items;
+        let v = match top.0 {
+            // objects are unpacked by iterating over them
+            ValueRepr::Dynamic(ref obj) => {
+                items = obj.iter().collect::<Vec<_>>();
+                &items[..]
+            }
+            _ => ok!(top.as_slice().map_err(|e| Error::new(
+                ErrorKind::CannotUnpack,
+                "not a sequence"
+            )
+            .with_source(e))),
+        };
         if v.len() != *count {
             return Err(Error::new(
                 ErrorKind::CannotUnpack,
@@ -869,5 +1106,12 @@ fn process_err(err: &mut Error, pc: usize, state: &State) {
         if state.env.debug() && err.debug_info().is_none() {
             err.attach_debug_info(state.make_debug_info(pc, state.instructions));
         }
+        if let Some(lineno) = state.instructions.get_line(pc) {
+            err.add_caller_frame(
+                state.instructions.name(),
+                lineno,
+                state.instructions.source(),
+            );
+        }
     }
 }
@@ -1,11 +1,14 @@
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 
+use serde::Serialize;
+
 use crate::compiler::instructions::Instructions;
 use crate::environment::Environment;
 use crate::error::{Error, ErrorKind};
 use crate::value::{ArgType, Value};
 use crate::vm::context::Context;
+use crate::vm::INCLUDE_RECURSION_COST;
 use crate::AutoEscape;
 
 /// Provides access to the current execution state of the engine.
@@ -71,6 +74,83 @@ impl<'vm, 'env> State<'vm, 'env> {
         self.ctx.load(self.env(), name)
     }
 
+    /// Looks up a global of the environment by name.
+    ///
+    /// Unlike [`lookup`](Self::lookup) this ignores the template context and
+    /// all locals, so a variable of the same name in the context does not
+    /// shadow the global.  This is useful for objects that need to reach
+    /// shared services registered with [`add_global`](Environment::add_global).
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.env.get_global(name)
+    }
+
+    /// Returns a map of all variables visible at the current point.
+    ///
+    /// This includes the locals of all enclosing scopes, the template context
+    /// and the globals of the environment where inner definitions shadow outer
+    /// ones.  Objects are represented by their debug string so the returned
+    /// value can be printed or serialized as-is.  This is mostly useful for
+    /// debugging, for instance from a custom `debug()` function:
+    ///
+    /// ```
+    /// # use minijinja::{Environment, State};
+    /// fn debug(state: &State) -> String {
+    ///     format!("{:#?}", state.dump_locals())
+    /// }
+    ///
+    /// let mut env = Environment::new();
+    /// env.add_function("debug", debug);
+    /// ```
+    pub fn dump_locals(&self) -> Value {
+        Value::from(
+            self.ctx
+                .visible_locals(self.env)
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = if value.as_object().is_some() {
+                        Value::from(format!("{:?}", value))
+                    } else {
+                        value
+                    };
+                    (key, value)
+                })
+                .collect::<BTreeMap<_, _>>(),
+        )
+    }
+
+    /// Renders another template of the environment into a string.
+    ///
+    /// This lets functions and objects compose output from other templates.
+    /// The template is rendered with the given context and not the current
+    /// one.  The rendering counts towards the recursion limit of the current
+    /// evaluation so a template rendering itself fails with an error instead
+    /// of overflowing the stack.  The output is a plain string, so it has to
+    /// be marked as safe to keep it from being escaped again by an HTML
+    /// template.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, State, Error, context};
+    /// # use minijinja::value::Value;
+    /// fn card(state: &State, title: String) -> Result<Value, Error> {
+    ///     let rv = state.render_template("card.html", context!(title))?;
+    ///     Ok(Value::from_safe_string(rv))
+    /// }
+    ///
+    /// let mut env = Environment::new();
+    /// env.add_template("card.html", "<div>{{ title }}</div>").unwrap();
+    /// env.add_template("index.html", "{{ card('<Hello>') }}").unwrap();
+    /// env.add_function("card", card);
+    /// let rv = env.get_template("index.html").unwrap().render(()).unwrap();
+    /// assert_eq!(rv, "<div>&lt;Hello&gt;</div>");
+    /// ```
+    pub fn render_template<S: Serialize>(&self, name: &str, ctx: S) -> Result<String, Error> {
+        let tmpl = ok!(self.env.get_template(name));
+        tmpl.render_nested(
+            Value::from_serializable(&ctx),
+            self.ctx.depth() + INCLUDE_RECURSION_COST,
+        )
+    }
+
     #[cfg(test)]
     pub(crate) fn with_dummy<R, F: FnOnce(&State) -> R>(env: &'env Environment<'env>, f: F) -> R {
         f(&State {
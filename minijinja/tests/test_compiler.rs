@@ -1,6 +1,7 @@
 #![cfg(feature = "unstable_machinery")]
-use minijinja::machinery::{CodeGenerator, Instruction};
+use minijinja::machinery::{get_compiled_template, CodeGenerator, Instruction};
 use minijinja::value::Value;
+use minijinja::Environment;
 
 #[test]
 fn test_for_loop() {
@@ -57,3 +58,13 @@ fn test_const() {
 
     insta::assert_debug_snapshot!(&c.finish());
 }
+
+#[test]
+fn test_compiled_template_instructions() {
+    let mut env = Environment::new();
+    env.add_template("add.txt", "{{ a + b }}").unwrap();
+    let tmpl = env.get_template("add.txt").unwrap();
+    let compiled = get_compiled_template(&tmpl);
+
+    insta::assert_debug_snapshot!(&compiled.instructions);
+}
@@ -2,8 +2,8 @@ use std::collections::BTreeMap;
 
 use similar_asserts::assert_eq;
 
-use minijinja::value::Value;
-use minijinja::Environment;
+use minijinja::value::{merge_maps, Value};
+use minijinja::{context, AutoEscape, Environment, Error, ErrorKind, State, UndefinedBehavior};
 
 #[test]
 fn test_basic() {
@@ -27,6 +27,70 @@ fn test_expression() {
     assert_eq!(expr.eval(&ctx).unwrap(), Value::from(65));
 }
 
+#[test]
+fn test_expression_static_check() {
+    let env = Environment::new();
+
+    let err = env.compile_expression("name|upper|shout(2)").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownFilter);
+    assert_eq!(
+        err.to_string(),
+        "unknown filter: filter shout is unknown (in <expression>:1)"
+    );
+
+    let err = env.compile_expression("value is evens").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownTest);
+
+    let expr = env
+        .compile_expression("nmae|upper ~ ' ' ~ range(count)|join if user.active")
+        .unwrap();
+    let mut names = expr.referenced_names().into_iter().collect::<Vec<_>>();
+    names.sort_unstable();
+    assert_eq!(names, ["count", "nmae", "range", "user"]);
+}
+
+#[test]
+#[cfg(feature = "deserialization")]
+fn test_expression_eval_as() {
+    let env = Environment::new();
+
+    let expr = env.compile_expression("(a + b) * 2 - 1").unwrap();
+    let rv: i64 = expr.eval_as(context!(a => 20, b => 1)).unwrap();
+    assert_eq!(rv, 41);
+
+    let expr = env.compile_expression("[name, name|upper]").unwrap();
+    let rv: Vec<String> = expr.eval_as(context!(name => "foo")).unwrap();
+    assert_eq!(rv, ["foo", "FOO"]);
+
+    let rv: Option<BTreeMap<String, u32>> = env
+        .compile_expression("{'a': 1, 'b': 2} if flag")
+        .unwrap()
+        .eval_as(context!(flag => true))
+        .unwrap();
+    assert_eq!(rv.unwrap().get("b"), Some(&2));
+
+    let err = env
+        .compile_expression("'nope'")
+        .unwrap()
+        .eval_as::<f64, _>(())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::CannotDeserialize);
+    assert_eq!(
+        err.to_string(),
+        "cannot deserialize: invalid type: string \"nope\", expected f64"
+    );
+
+    let err = env
+        .compile_expression("[1, 2]")
+        .unwrap()
+        .eval_as::<Vec<String>, _>(())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "cannot deserialize: invalid type: integer `1`, expected a string"
+    );
+}
+
 #[test]
 fn test_expression_lifetimes() {
     let mut env = Environment::new();
@@ -57,6 +121,174 @@ fn test_globals() {
     env.add_template("test", "{{ a }}").unwrap();
     let tmpl = env.get_template("test").unwrap();
     assert_eq!(tmpl.render(()).unwrap(), "42");
+    assert_eq!(tmpl.render(context!(a => 23)).unwrap(), "23");
+}
+
+#[test]
+fn test_keep_trailing_newline() {
+    let mut env = Environment::new();
+    env.add_template("test", "foo\n").unwrap();
+    env.add_template("crlf", "foo\r\n").unwrap();
+    env.add_template("include", "{% include 'test' %}bar")
+        .unwrap();
+    let render = |env: &Environment, name| env.get_template(name).unwrap().render(()).unwrap();
+    assert_eq!(render(&env, "test"), "foo");
+    assert_eq!(render(&env, "include"), "foobar");
+    env.set_keep_trailing_newline(true);
+    assert_eq!(render(&env, "test"), "foo\n");
+    assert_eq!(render(&env, "crlf"), "foo\r\n");
+    assert_eq!(render(&env, "include"), "foo\nbar");
+    assert_eq!(env.render_str("foo\n\n", ()).unwrap(), "foo\n\n");
+
+    // whitespace control and trim_blocks remove the trailing newline
+    assert_eq!(env.render_str("{{ 1 -}}\n", ()).unwrap(), "1");
+    assert_eq!(
+        env.render_str("{% if true -%}a{% endif -%}\n", ()).unwrap(),
+        "a"
+    );
+    env.set_trim_blocks(true);
+    assert_eq!(
+        env.render_str("{% if true %}a{% endif %}\n", ()).unwrap(),
+        "a"
+    );
+}
+
+#[test]
+fn test_trim_and_lstrip_blocks() {
+    let mut env = Environment::new();
+    env.add_template(
+        "test",
+        "<ul>\n  {% for x in [1, 2] %}\n  <li>{{ x }}</li>\n  {% endfor %}\n</ul>",
+    )
+    .unwrap();
+    let render = |env: &Environment| env.get_template("test").unwrap().render(()).unwrap();
+    assert_eq!(
+        render(&env),
+        "<ul>\n  \n  <li>1</li>\n  \n  <li>2</li>\n  \n</ul>"
+    );
+    env.set_trim_blocks(true);
+    assert_eq!(
+        render(&env),
+        "<ul>\n    <li>1</li>\n    <li>2</li>\n  </ul>"
+    );
+    env.set_lstrip_blocks(true);
+    assert_eq!(render(&env), "<ul>\n  <li>1</li>\n  <li>2</li>\n</ul>");
+
+    // opting out per block
+    assert_eq!(
+        env.render_str("  {%+ if true %}x{% endif +%}\ny", ())
+            .unwrap(),
+        "  x\ny"
+    );
+    assert_eq!(env.render_str("a\n  {# comment #}\nb", ()).unwrap(), "a\nb");
+}
+
+#[test]
+fn test_set_capture_whitespace() {
+    let source = "{% set x %}\n    <b>foo</b>\n    {% endset %}[{{ x }}]\n\
+                  {% set y -%}\n    <b>bar</b>\n    {%- endset %}[{{ y }}]";
+    let mut env = Environment::new();
+    assert_eq!(
+        env.render_str(source, ()).unwrap(),
+        "[\n    <b>foo</b>\n    ]\n[<b>bar</b>]"
+    );
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    assert_eq!(
+        env.render_str(source, ()).unwrap(),
+        "[    <b>foo</b>\n]\n[<b>bar</b>]"
+    );
+}
+
+#[test]
+fn test_trim_blocks_with_inheritance() {
+    let mut env = Environment::new();
+    env.add_template(
+        "layout.html",
+        "<div>\n{% block body %}\ndefault\n{% endblock %}\n</div>\n",
+    )
+    .unwrap();
+    env.add_template(
+        "empty.html",
+        "{% extends 'layout.html' %}\n{% block body %}{% endblock %}\n",
+    )
+    .unwrap();
+    env.add_template(
+        "blank.html",
+        "{% extends 'layout.html' %}\n{% block body %}\n{% endblock %}\n",
+    )
+    .unwrap();
+    env.add_template(
+        "filled.html",
+        "{% extends 'layout.html' %}\n{% block body %}\n  hi\n{% endblock %}\n",
+    )
+    .unwrap();
+    let render =
+        |env: &Environment, name: &str| env.get_template(name).unwrap().render(()).unwrap();
+
+    assert_eq!(render(&env, "layout.html"), "<div>\n\ndefault\n\n</div>");
+    assert_eq!(render(&env, "empty.html"), "<div>\n\n</div>");
+
+    env.set_trim_blocks(true);
+    assert_eq!(render(&env, "layout.html"), "<div>\ndefault\n</div>");
+    assert_eq!(render(&env, "empty.html"), "<div>\n</div>");
+    assert_eq!(render(&env, "blank.html"), "<div>\n</div>");
+    assert_eq!(render(&env, "filled.html"), "<div>\n  hi\n</div>");
+}
+
+#[test]
+fn test_text_mode() {
+    let mut env = Environment::new();
+    env.set_text_mode(true);
+    env.add_template(
+        "config.yml",
+        r#"services:
+{% for name, port in services|dictsort %}
+  {{ name }}:
+    {% if port %}
+    port: {{ port }}
+    {% endif %}
+    label: "{{ name ~ ' & co' }}"
+{% endfor %}
+"#,
+    )
+    .unwrap();
+    let tmpl = env.get_template("config.yml").unwrap();
+    let rv = tmpl
+        .render(context!(services => context!(web => 80, worker => 0)))
+        .unwrap();
+    assert_eq!(
+        rv,
+        "services:\n  web:\n    port: 80\n    label: \"web & co\"\n  worker:\n    label: \"worker & co\"\n"
+    );
+}
+
+#[test]
+fn test_text_mode_restores_auto_escape_callback() {
+    let mut env = Environment::new();
+    env.set_auto_escape_callback(|_| AutoEscape::Html);
+    env.add_template("x.txt", "{{ '<b>' }}").unwrap();
+    let render = |env: &Environment| env.get_template("x.txt").unwrap().render(()).unwrap();
+    assert_eq!(render(&env), "&lt;b&gt;");
+    env.set_text_mode(true);
+    env.set_text_mode(true);
+    assert_eq!(render(&env), "<b>");
+    env.set_text_mode(false);
+    assert_eq!(render(&env), "&lt;b&gt;");
+}
+
+#[test]
+fn test_template_handle_reuse() {
+    let mut env = Environment::new();
+    env.add_template("hello", "Hello {{ name }}!").unwrap();
+    let tmpl = env.get_template("hello").unwrap();
+    let copy = tmpl;
+    let rv = ["A", "B", "C"]
+        .iter()
+        .map(|name| copy.render(context!(name)).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(rv, ["Hello A!", "Hello B!", "Hello C!"]);
+    assert_eq!(tmpl.name(), "hello");
 }
 
 #[test]
@@ -66,3 +298,751 @@ fn test_template_removal() {
     env.remove_template("test");
     assert!(env.get_template("test").is_err());
 }
+
+#[test]
+fn test_test_with_state() {
+    let mut env = Environment::new();
+    env.add_test("enabled", |state: &State, value: String| {
+        state
+            .lookup("features")
+            .and_then(|features| features.get_attr(&value).ok())
+            .map_or(false, |enabled| enabled.is_true())
+    });
+    env.add_test("short", |value: String| value.len() < 5);
+    let ctx = context!(features => context!(search => true, export => false));
+    assert_eq!(
+        env.render_str(
+            "{{ 'search' is enabled }}|{{ 'export' is enabled }}|{{ 'other' is enabled }}|{{ 'foo' is short }}",
+            ctx
+        )
+        .unwrap(),
+        "true|false|false|true"
+    );
+    assert_eq!(
+        env.render_str("{{ 'search' is enabled }}", ()).unwrap(),
+        "false"
+    );
+}
+
+#[test]
+fn test_helper_listing() {
+    let mut env = Environment::new();
+    env.add_filter("slugify", |value: String| value.replace(' ', "-"));
+    env.add_filter("slug", |value: String| value.replace(' ', "-"));
+    env.add_test("short", |value: String| value.len() < 5);
+    env.add_function("hello", || "Hello!");
+    env.add_global("answer", Value::from(42));
+
+    let filters = env.filters().collect::<Vec<_>>();
+    assert!(filters.contains(&"slugify"));
+    assert!(filters.contains(&"slug"));
+    assert!(filters.contains(&"e"));
+    assert!(env.tests().any(|x| x == "short"));
+    let functions = env.functions().collect::<Vec<_>>();
+    assert!(functions.contains(&"hello"));
+    assert!(!functions.contains(&"answer"));
+}
+
+#[test]
+fn test_merged_context() {
+    let base = context!(site => "example.com", title => "Index");
+    let overrides = context!(title => "Downloads");
+    let env = Environment::new();
+    let rv = env
+        .render_str("{{ site }}|{{ title }}", merge_maps([base, overrides]))
+        .unwrap();
+    assert_eq!(rv, "example.com|Downloads");
+}
+
+#[test]
+fn test_semi_strict_undefined() {
+    let mut env = Environment::new();
+    let ctx = context!(user => context!(name => "Peter"));
+    let render = |env: &Environment, source| env.render_str(source, ctx.clone());
+
+    // lenient: printing missing values is fine, looking up attributes is not
+    assert_eq!(render(&env, "[{{ missing }}]").unwrap(), "[]");
+    assert_eq!(
+        render(&env, "{{ user.profile.bio }}").unwrap_err().kind(),
+        ErrorKind::UndefinedError
+    );
+
+    env.set_undefined_behavior(UndefinedBehavior::SemiStrict);
+    assert_eq!(env.undefined_behavior(), UndefinedBehavior::SemiStrict);
+
+    // deep chains below an existing variable resolve to undefined
+    assert_eq!(render(&env, "[{{ user.profile.bio }}]").unwrap(), "[]");
+    assert_eq!(render(&env, "[{{ user['profile'].bio }}]").unwrap(), "[]");
+    assert_eq!(render(&env, "[{{ user.name }}]").unwrap(), "[Peter]");
+
+    // missing top-level variables fail wherever they are used
+    for source in [
+        "{{ missing }}",
+        "{{ missing.name }}",
+        "{{ missing['name'] }}",
+        "{{ missing|upper }}",
+        "{{ missing ~ 'x' }}",
+        "{{ 'x' ~ missing }}",
+        "{{ missing + 1 }}",
+        "{{ missing if true }}",
+        "{{ [missing] }}",
+        "{{ range(missing) }}",
+        "{{ missing|truncatewords(2) }}",
+        "{{ 'x'|replace('x', missing) }}",
+        "{{ missing|default(missing) }}",
+        "{% set x = missing %}{{ x }}",
+    ] {
+        let err = render(&env, source).expect_err(source);
+        assert_eq!(err.kind(), ErrorKind::UndefinedError);
+        assert!(
+            err.to_string().contains("missing is undefined"),
+            "{}",
+            source
+        );
+    }
+
+    // but can still be tested, defaulted and iterated over
+    assert_eq!(
+        render(
+            &env,
+            "{{ missing is defined }}|{{ missing|default('x') }}|{% for x in missing %}{% endfor %}"
+        )
+        .unwrap(),
+        "false|x|"
+    );
+    assert_eq!(
+        render(
+            &env,
+            "{% set x = missing %}{% if not x %}{{ x is undefined }}|{{ x|d('y') }}{% endif %}"
+        )
+        .unwrap(),
+        "true|y"
+    );
+
+    // the filtered value is passed on by string filters and can be handled
+    // by any filter that accepts undefined values
+    env.add_filter("fallback", minijinja::filters::default);
+    assert_eq!(
+        render(
+            &env,
+            "{{ missing|upper|default('z') }}|{{ missing|trim|d('y') }}|{{ missing|fallback('f') }}"
+        )
+        .unwrap(),
+        "z|y|f"
+    );
+}
+
+#[test]
+fn test_random_seed() {
+    let mut env = Environment::new();
+    let ctx = context!(items => (0..10).collect::<Vec<_>>());
+    let render = |env: &Environment, source| env.render_str(source, ctx.clone()).unwrap();
+
+    let shuffled = render(&env, "{{ items|shuffle(seed=42) }}");
+    assert_eq!(shuffled, "[8, 3, 6, 5, 4, 0, 9, 2, 1, 7]");
+    assert_eq!(render(&env, "{{ items|shuffle(42) }}"), shuffled);
+    assert_eq!(render(&env, "{{ items|shuffle|length }}"), "10");
+
+    env.set_random_seed(Some(42));
+    assert_eq!(render(&env, "{{ items|shuffle }}"), shuffled);
+    assert_eq!(render(&env, "{{ items|random }}"), "7");
+    assert_eq!(
+        render(&env, "{{ items|shuffle(seed=1) }}"),
+        "[9, 0, 1, 4, 8, 2, 3, 7, 6, 5]"
+    );
+    assert_eq!(render(&env, "{{ []|random is undefined }}"), "true");
+}
+
+#[test]
+fn test_template_limits() {
+    let mut env = Environment::new();
+    let deep = format!(
+        "{}{}",
+        "{% if true %}".repeat(10000),
+        "{% endif %}".repeat(10000)
+    );
+    let err = env.add_template("deep.html", &deep).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::LimitExceeded);
+    assert_eq!(
+        err.to_string(),
+        "template exceeds limits: template exceeds maximum nesting depth of 150 (in deep.html:1)"
+    );
+
+    let nested = format!("{}{}", "{% if true %}".repeat(20), "{% endif %}".repeat(20));
+    assert!(env.add_template("nested.html", &nested).is_ok());
+    env.set_max_template_nesting(10);
+    let err = env.add_template("nested.html", &nested).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::LimitExceeded);
+
+    env.set_max_template_size(Some(10));
+    assert!(env.add_template("small.html", "0123456789").is_ok());
+    let err = env.add_template("big.html", "0123456789x").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::LimitExceeded);
+    assert_eq!(
+        err.to_string(),
+        "template exceeds limits: template exceeds maximum size of 10 bytes (in big.html:1)"
+    );
+
+    // recompiling after a whitespace change applies the new limits
+    env.set_trim_blocks(true);
+    let err = env.get_template("nested.html").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::LimitExceeded);
+    assert!(env.get_template("small.html").is_ok());
+    env.set_max_template_nesting(150);
+    env.set_max_template_size(None);
+    env.set_trim_blocks(false);
+    assert!(env.get_template("nested.html").is_ok());
+}
+
+#[test]
+fn test_max_output_size() {
+    let mut env = Environment::new();
+    env.set_max_output_size(Some(1000));
+    env.add_template(
+        "huge.txt",
+        "{% for x in range(100) %}{% for y in range(100) %}{{ x }}{% endfor %}{% endfor %}",
+    )
+    .unwrap();
+    env.add_template("small.txt", "{% for x in range(100) %}{{ x }}{% endfor %}")
+        .unwrap();
+    env.add_template(
+        "captured.txt",
+        "{% set s %}{% for x in range(1000) %}xx{% endfor %}{% endset %}done",
+    )
+    .unwrap();
+
+    let tmpl = env.get_template("huge.txt").unwrap();
+    let err = tmpl.render(()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::LimitExceeded);
+    assert_eq!(
+        err.to_string(),
+        "template exceeds limits: output exceeds maximum size of 1000 bytes (in huge.txt:1)"
+    );
+    let mut buf = Vec::new();
+    let err = tmpl.render_to_write((), &mut buf).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::LimitExceeded);
+    assert!(buf.len() <= 1000);
+
+    let err = env
+        .get_template("captured.txt")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::LimitExceeded);
+
+    let rv = env.get_template("small.txt").unwrap().render(()).unwrap();
+    assert_eq!(rv.len(), 190);
+    env.set_max_output_size(Some(190));
+    let mut buf = Vec::new();
+    env.get_template("small.txt")
+        .unwrap()
+        .render_to_write((), &mut buf)
+        .unwrap();
+    assert_eq!(buf.len(), 190);
+}
+
+#[test]
+fn test_path_join_callback() {
+    let mut env = Environment::new();
+    env.set_path_join_callback(|parent, name| {
+        if let Some(name) = name.strip_prefix('/') {
+            return name.to_string();
+        }
+        let mut rv = parent.split('/').collect::<Vec<_>>();
+        rv.pop();
+        for segment in name.split('/') {
+            match segment {
+                ".." => {
+                    rv.pop();
+                }
+                "." => {}
+                segment => rv.push(segment),
+            }
+        }
+        rv.join("/")
+    });
+    env.add_template("layout.html", "[{% block body %}{% endblock %}]")
+        .unwrap();
+    env.add_template("pages/partials/b.html", "b").unwrap();
+    env.add_template("pages/macros.html", "{% macro x() %}x{% endmacro %}")
+        .unwrap();
+    env.add_template(
+        "pages/a.html",
+        "{% extends '/layout.html' %}{% block body %}\
+         {% include 'partials/b.html' %}\
+         {% from 'macros.html' import x %}{{ x() }}\
+         {% endblock %}",
+    )
+    .unwrap();
+    let rv = env
+        .get_template("pages/a.html")
+        .unwrap()
+        .render(())
+        .unwrap();
+    assert_eq!(rv, "[bx]");
+
+    env.add_template("pages/missing.html", "{% include 'nope.html' %}")
+        .unwrap();
+    let err = env
+        .get_template("pages/missing.html")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "template not found: tried to include non-existing template \"pages/nope.html\" (in pages/missing.html:1)"
+    );
+}
+
+#[test]
+fn test_filter_error_location() {
+    fn positive(value: i64) -> Result<i64, Error> {
+        if value < 0 {
+            Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("expected a positive number, got {}", value),
+            ))
+        } else {
+            Ok(value)
+        }
+    }
+
+    let mut env = Environment::new();
+    env.add_filter("positive", positive);
+    env.add_template(
+        "check.txt",
+        "ok: {{ 1|positive }}\n\nbad: {{ num|positive }}",
+    )
+    .unwrap();
+    let err = env
+        .get_template("check.txt")
+        .unwrap()
+        .render(context!(num => -2))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert_eq!(err.name(), Some("check.txt"));
+    assert_eq!(err.line(), Some(3));
+    assert_eq!(
+        err.to_string(),
+        "invalid operation: expected a positive number, got -2 (in check.txt:3)"
+    );
+}
+
+#[test]
+fn test_state_dump_locals() {
+    let mut env = Environment::new();
+    env.add_function("dump", |state: &State| state.dump_locals());
+    env.add_global("name", Value::from("global"));
+    let rv = env
+        .render_str(
+            "{% for item in [1, 2] %}{% set d = dump() %}\
+             [{{ d.item }} {{ d.name }} {{ d.user }} {{ d.loop is string }} {{ d.range is string }}]\
+             {% endfor %}{% set name = 'local' %}{{ dump().name }} {{ dump().item is undefined }}",
+            context!(user => "peter"),
+        )
+        .unwrap();
+    assert_eq!(
+        rv,
+        "[1 global peter true true][2 global peter true true]local true"
+    );
+}
+
+#[test]
+fn test_aliases() {
+    let mut env = Environment::new();
+    env.add_test("admin", |value: String| value == "admin");
+    env.add_test_alias("superuser", "admin").unwrap();
+    env.add_test_alias("root", "superuser").unwrap();
+    env.add_filter("shout", |value: String| value.to_uppercase());
+    env.add_filter_alias("yell", "shout").unwrap();
+
+    let tmpl = "{{ user is admin }} {{ user is superuser }} {{ user is root }} \
+                {{ user is not admin }} {{ user is not superuser }} {{ user is not root }} \
+                {{ user|shout }} {{ user|yell }}";
+    assert_eq!(
+        env.render_str(tmpl, context!(user => "admin")).unwrap(),
+        "true true true false false false ADMIN ADMIN"
+    );
+    assert_eq!(
+        env.render_str(tmpl, context!(user => "peter")).unwrap(),
+        "false false false true true true PETER PETER"
+    );
+
+    let err = env.add_test_alias("nobody", "missing").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownTest);
+    let err = env.add_filter_alias("whisper", "missing").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownFilter);
+}
+
+#[test]
+fn test_state_get_global() {
+    #[derive(Debug)]
+    struct Page;
+
+    impl std::fmt::Display for Page {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "page")
+        }
+    }
+
+    impl minijinja::value::Object for Page {
+        fn call_method(&self, state: &State, name: &str, args: &[Value]) -> Result<Value, Error> {
+            match name {
+                "url" => {
+                    let registry = state.get_global("urls").unwrap_or_default();
+                    let base = registry.get_attr("base").unwrap();
+                    Ok(Value::from(format!("{}/{}", base, args[0])))
+                }
+                _ => Err(Error::new(
+                    ErrorKind::UnknownMethod,
+                    format!("page has no method named {}", name),
+                )),
+            }
+        }
+    }
+
+    let mut env = Environment::new();
+    env.add_global("urls", context!(base => "https://example.com"));
+    env.add_global("page", Value::from_object(Page));
+    let rv = env
+        .render_str(
+            "{{ page.url('about') }}|{{ urls.base }}",
+            context!(urls => context!(base => "shadowed")),
+        )
+        .unwrap();
+    assert_eq!(rv, "https://example.com/about|shadowed");
+}
+
+#[test]
+fn test_debug_function() {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::SemiStrict);
+    let rv = env
+        .render_str(
+            "{% for item in items %}{{ debug() }}{% endfor %}",
+            context!(items => vec!["first"], username => "peter"),
+        )
+        .unwrap();
+    assert!(rv.contains("\"username\": \"peter\""));
+    assert!(rv.contains("\"item\": \"first\""));
+    assert!(rv.contains("\"upper\""));
+    assert!(rv.contains("\"defined\""));
+}
+
+#[test]
+fn test_float_precision() {
+    let mut env = Environment::new();
+    let tmpl = "{{ 0.1 + 0.2 }}|{{ 2.0 }}|{{ 1 / 3 }}|{{ (0.1 + 0.2) == 0.3 }}|{{ 42 }}";
+    assert_eq!(
+        env.render_str(tmpl, ()).unwrap(),
+        "0.30000000000000004|2.0|0.3333333333333333|false|42"
+    );
+    env.set_float_precision(Some(15));
+    assert_eq!(
+        env.render_str(tmpl, ()).unwrap(),
+        "0.3|2.0|0.333333333333333|false|42"
+    );
+    env.set_float_precision(Some(2));
+    assert_eq!(
+        env.render_str("{{ 1234.5 }}|{{ 0.000123 }}|{{ [0.1 + 0.2] }}", ())
+            .unwrap(),
+        "1200.0|0.00012|[0.30000000000000004]"
+    );
+    // only direct output is rounded
+    assert_eq!(
+        env.render_str("{{ (0.1 + 0.2) ~ '' }}|{{ (0.1 + 0.2)|string }}", ())
+            .unwrap(),
+        "0.30000000000000004|0.30000000000000004"
+    );
+}
+
+#[test]
+#[cfg(feature = "time")]
+fn test_fixed_clock() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let mut env = Environment::new();
+    // 2023-03-05T14:07:09.25Z, a Sunday
+    env.set_clock(|| UNIX_EPOCH + Duration::from_millis(1_678_025_229_250));
+    let render = |env: &Environment, source: &str| env.render_str(source, ()).unwrap();
+    assert_eq!(render(&env, "{{ now() }}"), "2023-03-05T14:07:09Z");
+    assert_eq!(render(&env, "{{ now()|datetimeformat('%H:%M') }}"), "14:07");
+    assert_eq!(
+        render(
+            &env,
+            "{{ utcnow()|datetimeformat('%a, %d %b %Y %I:%M:%S.%f %p %Z (%j)') }}"
+        ),
+        "Sun, 05 Mar 2023 02:07:09.250000 PM UTC (064)"
+    );
+    assert_eq!(
+        render(
+            &env,
+            "{{ now()|datetimeformat('%F %T %z', tz='-11:30') }}|\
+             {{ now()|datetimeformat('%A %Z', '+10') }}"
+        ),
+        "2023-03-05 02:37:09 -1130|Monday UTC+10:00"
+    );
+    assert_eq!(
+        render(
+            &env,
+            "{{ now().year }}-{{ now().month }}-{{ now().day }} {{ now().weekday }} {{ now().timestamp }}"
+        ),
+        "2023-3-5 6 1678025229.25"
+    );
+    assert_eq!(
+        render(
+            &env,
+            "{{ 0|datetimeformat('%Y-%m-%d %H:%M:%S') }}|{{ -86400.5|datetimeformat('%F %T.%f') }}"
+        ),
+        "1970-01-01 00:00:00|1969-12-30 23:59:59.500000"
+    );
+
+    for (source, msg) in [
+        (
+            "{{ now()|datetimeformat('%Q') }}",
+            "unsupported format specifier %Q",
+        ),
+        (
+            "{{ now()|datetimeformat('%H', tz='Europe/Vienna') }}",
+            "unknown time zone \"Europe/Vienna\", only UTC and fixed offsets are supported",
+        ),
+        (
+            "{{ 'today'|datetimeformat('%H') }}",
+            "datetimeformat expects a datetime or a timestamp",
+        ),
+    ] {
+        let err = env.render_str(source, ()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+        assert!(err.to_string().contains(msg), "{}", err);
+    }
+}
+
+#[test]
+fn test_undefined_variable_callback() {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::SemiStrict);
+    env.set_undefined_variable_callback(|state, name| {
+        if name.starts_with("opt_") {
+            None
+        } else {
+            Some(Value::from(format!(
+                "[{} missing in {}]",
+                name,
+                state.name()
+            )))
+        }
+    });
+    env.add_template(
+        "page.html",
+        "Hi {{ user }}, {{ greeting }}! {{ opt_x is defined }}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("page.html").unwrap();
+    assert_eq!(
+        tmpl.render(context!(greeting => "welcome")).unwrap(),
+        "Hi [user missing in page.html], welcome! false"
+    );
+    assert_eq!(
+        env.render_str("{% for x in [1] %}{{ x }}{{ loop.index }}{% endfor %}", ())
+            .unwrap(),
+        "11"
+    );
+    let err = env.render_str("{{ opt_x }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UndefinedError);
+}
+
+#[test]
+fn test_unknown_method_callback() {
+    #[derive(Debug)]
+    struct Api;
+
+    impl std::fmt::Display for Api {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "api")
+        }
+    }
+
+    impl minijinja::value::Object for Api {
+        fn call_method(&self, _state: &State, name: &str, _args: &[Value]) -> Result<Value, Error> {
+            match name {
+                "version" => Ok(Value::from(1)),
+                "get_legacy" => Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "legacy api is gone",
+                )),
+                _ => Err(Error::new(
+                    ErrorKind::UnknownMethod,
+                    format!("api has no method named {}", name),
+                )),
+            }
+        }
+    }
+
+    let mut env = Environment::new();
+    env.add_global("api", Value::from_object(Api));
+    env.set_unknown_method_callback(|_state, value, name, args| {
+        if !name.starts_with("get_") {
+            return None;
+        }
+        Some(Ok(Value::from(format!(
+            "{}.{}({})",
+            value,
+            &name[4..],
+            args.iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))))
+    });
+
+    assert_eq!(
+        env.render_str("{{ api.version() }}|{{ api.get_user(42, 'x') }}", ())
+            .unwrap(),
+        "1|api.user(42, x)"
+    );
+    assert_eq!(
+        env.render_str("{{ {'a': 1}.get_a() }}", ()).unwrap(),
+        "{\"a\": 1}.a()"
+    );
+    let err = env.render_str("{{ api.missing() }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownMethod);
+    assert!(err.to_string().contains("api has no method named missing"));
+
+    // other error kinds are not handed to the callback
+    let err = env.render_str("{{ api.get_legacy() }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert!(err.to_string().contains("legacy api is gone"));
+}
+
+#[test]
+fn test_downcast_filter_error() {
+    #[derive(Debug, PartialEq)]
+    struct NotFound(String);
+
+    impl std::fmt::Display for NotFound {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "no record {:?}", self.0)
+        }
+    }
+
+    impl std::error::Error for NotFound {}
+
+    fn lookup(key: String) -> Result<String, Error> {
+        Err(Error::new(ErrorKind::InvalidOperation, "lookup failed").with_source(NotFound(key)))
+    }
+
+    let mut env = Environment::new();
+    env.add_filter("lookup", lookup);
+    let err = env.render_str("{{ 'missing'|lookup }}", ()).unwrap_err();
+    assert_eq!(
+        err.downcast_source_ref::<NotFound>(),
+        Some(&NotFound("missing".into()))
+    );
+    assert!(err.downcast_source_ref::<std::fmt::Error>().is_none());
+
+    // sources further down the chain are found too
+    let err = Error::new(ErrorKind::InvalidOperation, "outer").with_source(
+        Error::new(ErrorKind::InvalidOperation, "inner").with_source(NotFound("x".into())),
+    );
+    assert_eq!(
+        err.downcast_source_ref::<NotFound>(),
+        Some(&NotFound("x".into()))
+    );
+    assert_eq!(
+        err.downcast_source_ref::<Error>().map(|x| x.to_string()),
+        Some("invalid operation: inner".into())
+    );
+}
+
+#[test]
+fn test_keep_comments() {
+    let mut env = Environment::new();
+    env.add_template("test", "[server]\n{# the port #}\nport = {{ port }}")
+        .unwrap();
+    let render = |env: &Environment| {
+        env.get_template("test")
+            .unwrap()
+            .render(context!(port => 80))
+            .unwrap()
+    };
+    assert_eq!(render(&env), "[server]\n\nport = 80");
+    env.set_keep_comments(true);
+    assert_eq!(render(&env), "[server]\n the port \nport = 80");
+    env.set_comment_delimiters("#", "");
+    assert_eq!(render(&env), "[server]\n# the port \nport = 80");
+
+    // kept comments are not trimmed
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    assert_eq!(
+        env.render_str("  {#<x>#}\n{% if true %}\ny{% endif %}", ())
+            .unwrap(),
+        "  #<x>\ny"
+    );
+
+    env.set_keep_comments(false);
+    assert_eq!(render(&env), "[server]\nport = 80");
+}
+
+#[test]
+fn test_django_compat() {
+    let env = Environment::new_django_compat();
+    let render = |source, ctx| env.render_str(source, ctx).unwrap();
+    let ctx = context!(yes => true, no => false, unknown => (), nickname => "<j>");
+    assert_eq!(
+        render(
+            "{{ yes|yesno }} {{ no|yesno }} {{ unknown|yesno }} {{ unknown|yesno('y,n') }}",
+            ctx.clone()
+        ),
+        "yes no maybe n"
+    );
+    assert_eq!(
+        render(
+            "{{ unknown|default_if_none('anonymous') }} {{ nickname|default_if_none('x') }} \
+             [{{ missing|default_if_none('x') }}]",
+            ctx
+        ),
+        "anonymous &lt;j&gt; []"
+    );
+    assert_eq!(
+        render("{{ text|linebreaks }}", context!(text => "a<b>\nc\n\n\nd")),
+        "<p>a&lt;b&gt;<br>c</p>\n\n<p>d</p>"
+    );
+    assert_eq!(render("{{ 2|pluralize('y,ies') }}", context!()), "ies");
+
+    let err = env.render_str("{{ true|yesno('yes') }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+
+    // the filters are only registered by the preset
+    let err = Environment::new()
+        .render_str("{{ true|yesno }}", ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownFilter);
+}
+
+#[test]
+#[cfg(feature = "time")]
+fn test_django_compat_date() {
+    let env = Environment::new_django_compat();
+    let rv = env
+        .render_str(
+            "{{ ts|date }}|{{ ts|date('D jS F Y, G:i') }}|{{ ts|date('H:i', tz='+02:00') }}",
+            context!(ts => 951_829_509),
+        )
+        .unwrap();
+    assert_eq!(rv, "Feb. 29, 2000|Tue 29th February 2000, 13:05|15:05");
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn test_django_compat_linebreaks_in_json() {
+    let mut env = Environment::new_django_compat();
+    env.add_template("data.json", "{{ text|linebreaks }}")
+        .unwrap();
+    let rv = env
+        .get_template("data.json")
+        .unwrap()
+        .render(context!(text => "a & b\n\nc"))
+        .unwrap();
+    assert_eq!(rv, r#""<p>a & b</p>\n\n<p>c</p>""#);
+}
@@ -1,6 +1,8 @@
 #![cfg(feature = "source")]
 
-use minijinja::{Environment, Source};
+use std::sync::{Arc, Mutex};
+
+use minijinja::{context, Environment, ErrorKind, Source};
 
 use similar_asserts::assert_eq;
 
@@ -40,3 +42,92 @@ fn test_dynamic() {
         "template not found: template \"missing\" does not exist"
     );
 }
+
+#[test]
+fn test_source_whitespace_config() {
+    let mut source = Source::new();
+    source
+        .add_template("hello", "{% if true %}\nHello World!{% endif %}")
+        .unwrap();
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_source(source);
+    let t = env.get_template("hello").unwrap();
+    assert_eq!(t.render(()).unwrap(), "Hello World!");
+    env.set_trim_blocks(false);
+    let t = env.get_template("hello").unwrap();
+    assert_eq!(t.render(()).unwrap(), "\nHello World!");
+}
+
+#[test]
+fn test_not_found_vs_cannot_load() {
+    let dir = std::env::temp_dir().join(format!("minijinja-test-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("folder.html")).unwrap();
+    std::fs::write(dir.join("hello.html"), "Hello!").unwrap();
+
+    let mut env = Environment::new();
+    env.set_source(Source::from_path(&dir));
+    assert_eq!(
+        env.get_template("hello.html").unwrap().render(()).unwrap(),
+        "Hello!"
+    );
+    assert_eq!(
+        env.get_template("missing.html").unwrap_err().kind(),
+        ErrorKind::TemplateNotFound
+    );
+    // a directory exists but cannot be read as a template
+    assert_eq!(
+        env.get_template("folder.html").unwrap_err().kind(),
+        ErrorKind::CannotLoad
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_reload_on_change() {
+    let contents = Arc::new(Mutex::new(String::from("Hello {{ name }}!")));
+    let mut source = Source::with_loader({
+        let contents = contents.clone();
+        move |name| match name {
+            "hello" => Ok(Some(contents.lock().unwrap().clone())),
+            _ => Ok(None),
+        }
+    });
+    source.set_reload_on_change(true);
+    let mut env = Environment::new();
+    env.set_source(source);
+
+    let first = env.get_template("hello").unwrap();
+    assert_eq!(first.render(context!(name => "A")).unwrap(), "Hello A!");
+
+    // identical content reuses the compiled template
+    let second = env.get_template("hello").unwrap();
+    assert!(std::ptr::eq(first.source(), second.source()));
+
+    *contents.lock().unwrap() = String::from("Bye {{ name }}!");
+    let third = env.get_template("hello").unwrap();
+    assert!(!std::ptr::eq(first.source(), third.source()));
+    assert_eq!(third.render(context!(name => "A")).unwrap(), "Bye A!");
+    assert_eq!(first.render(context!(name => "A")).unwrap(), "Hello A!");
+}
+
+#[test]
+fn test_recompile_error() {
+    let mut source = Source::new();
+    let nested = format!("{}{}", "{% if true %}".repeat(20), "{% endif %}".repeat(20));
+    source.add_template("nested.html", nested).unwrap();
+    source.add_template("flat.html", "Hello!").unwrap();
+    let mut env = Environment::new();
+    env.set_max_template_nesting(10);
+    env.set_trim_blocks(true);
+    env.set_source(source);
+
+    let err = env.get_template("nested.html").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::LimitExceeded);
+    assert!(env.get_template("flat.html").is_ok());
+
+    env.set_max_template_nesting(150);
+    env.set_trim_blocks(false);
+    assert!(env.get_template("nested.html").is_ok());
+}
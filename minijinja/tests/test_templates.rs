@@ -1,11 +1,27 @@
 use std::collections::BTreeMap;
 use std::fmt::Write;
 use std::fs;
+use std::path::Path;
 
+use minijinja::value::Value;
 use minijinja::{context, Environment, Error, State};
 
 use similar_asserts::assert_eq;
 
+/// Inputs in a folder named after a feature are only rendered if that
+/// feature is enabled.
+fn is_input_enabled(path: &Path) -> bool {
+    let folder = path.parent().and_then(|x| x.file_name());
+    match folder.and_then(|x| x.to_str()) {
+        Some("refs") => false,
+        Some("json") => cfg!(feature = "json"),
+        Some("preserve_order") => cfg!(feature = "preserve_order"),
+        Some("regex") => cfg!(feature = "regex"),
+        Some("yaml") => cfg!(feature = "yaml"),
+        _ => true,
+    }
+}
+
 #[test]
 fn test_vm() {
     let mut refs = Vec::new();
@@ -20,14 +36,19 @@ fn test_vm() {
         refs.push((entry.path().clone(), source));
     }
 
-    insta::glob!("inputs/*", |path| {
-        if !path.metadata().unwrap().is_file() {
+    insta::glob!("inputs/**/*", |path| {
+        if !path.metadata().unwrap().is_file() || !is_input_enabled(path) {
             return;
         }
         let filename = path.file_name().unwrap().to_str().unwrap();
         let contents = std::fs::read_to_string(path).unwrap();
         let mut iter = contents.splitn(2, "\n---\n");
         let mut env = Environment::new();
+        // the time functions only exist with the time feature and are tested
+        // separately, keep them out of the shared debug() snapshot.
+        env.remove_global("now");
+        env.remove_global("utcnow");
+        env.remove_filter("datetimeformat");
         let ctx: serde_json::Value = serde_json::from_str(iter.next().unwrap()).unwrap();
 
         for (path, source) in &refs {
@@ -91,6 +112,98 @@ fn test_custom_filter() {
     assert_eq!(rv, "[42]");
 }
 
+#[test]
+fn test_tuple_return() {
+    fn divmod(_: &State, a: i64, b: i64) -> Value {
+        Value::from_serializable(&(a / b, a % b))
+    }
+
+    let mut env = Environment::new();
+    env.add_function("divmod", divmod);
+    env.add_template(
+        "test",
+        "{% set q, r = divmod(7, 2) %}{{ q }}|{{ r }}|{{ divmod(7, 2)[0] }}|\
+         {{ divmod(7, 2)|first }}|{{ divmod(7, 2)|last }}",
+    )
+    .unwrap();
+    let rv = env.get_template("test").unwrap().render(()).unwrap();
+    assert_eq!(rv, "3|1|3|3|1");
+}
+
+#[test]
+fn test_block_names() {
+    let mut env = Environment::new();
+    env.add_template(
+        "base.html",
+        "{% block title %}{% endblock %}{% block body %}{% endblock %}",
+    )
+    .unwrap();
+    env.add_template(
+        "layout.html",
+        "{% extends 'base.html' %}{% block body %}{% block content %}{% endblock %}{% endblock %}",
+    )
+    .unwrap();
+    env.add_template(
+        "index.html",
+        "{% extends 'layout.html' %}{% block content %}{% endblock %}{% block footer %}{% endblock %}",
+    )
+    .unwrap();
+    env.add_template(
+        "dynamic.html",
+        "{% extends layout %}{% block content %}{% endblock %}",
+    )
+    .unwrap();
+
+    let blocks = |name| {
+        env.get_template(name)
+            .unwrap()
+            .blocks()
+            .unwrap()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(blocks("index.html"), ["body", "content", "footer", "title"]);
+    assert_eq!(blocks("base.html"), ["body", "title"]);
+    assert_eq!(blocks("dynamic.html"), ["content"]);
+
+    env.add_template("orphan.html", "{% extends 'missing.html' %}")
+        .unwrap();
+    let err = env
+        .get_template("orphan.html")
+        .unwrap()
+        .blocks()
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), minijinja::ErrorKind::TemplateNotFound);
+}
+
+#[test]
+fn test_render_template_from_function() {
+    fn render(state: &State, name: String, value: Value) -> Result<String, Error> {
+        state.render_template(&name, context!(value))
+    }
+
+    let mut env = Environment::new();
+    env.add_function("render", render);
+    env.add_template("item.txt", "[{{ value }}]").unwrap();
+    env.add_template("loop.txt", "{{ render('loop.txt', value) }}")
+        .unwrap();
+    env.add_template(
+        "index.txt",
+        "{% for x in [1, 2] %}{{ render('item.txt', x) }}{% endfor %}",
+    )
+    .unwrap();
+
+    let rv = env.get_template("index.txt").unwrap().render(()).unwrap();
+    assert_eq!(rv, "[1][2]");
+
+    let err = env
+        .get_template("loop.txt")
+        .unwrap()
+        .render(context!(value => 1))
+        .unwrap_err();
+    assert!(err.to_string().contains("recursion limit exceeded"));
+}
+
 #[test]
 fn test_single() {
     let mut env = Environment::new();
@@ -118,7 +231,6 @@ fn test_auto_escaping() {
     // JSON
     #[cfg(feature = "json")]
     {
-        use minijinja::value::Value;
         let tmpl = env.get_template("index.js").unwrap();
         let rv = tmpl.render(context!(var => "foo\"bar'baz")).unwrap();
         insta::assert_snapshot!(rv, @r###""foo\"bar'baz""###);
@@ -126,6 +238,31 @@ fn test_auto_escaping() {
             .render(context!(var => [Value::from(true), Value::from("<foo>"), Value::from(())]))
             .unwrap();
         insta::assert_snapshot!(rv, @r###"[true,"<foo>",null]"###);
+        let rv = tmpl
+            .render(context!(var => "back\\slash\nnew\tline\u{1}"))
+            .unwrap();
+        insta::assert_snapshot!(rv, @r###""back\\slash\nnew\tline\u0001""###);
+
+        // explicitly selected through the callback and the autoescape tag
+        let mut env = Environment::new();
+        env.set_auto_escape_callback(|name| {
+            if name.ends_with(".tmpl") {
+                minijinja::AutoEscape::Json
+            } else {
+                minijinja::AutoEscape::None
+            }
+        });
+        env.add_template("config.tmpl", "{{ var }}").unwrap();
+        env.add_template(
+            "block.txt",
+            "{% autoescape 'json' %}{{ var }}{% endautoescape %}",
+        )
+        .unwrap();
+        for name in ["config.tmpl", "block.txt"] {
+            let tmpl = env.get_template(name).unwrap();
+            let rv = tmpl.render(context!(var => "say \"hi\"")).unwrap();
+            assert_eq!(rv, r#""say \"hi\"""#);
+        }
     }
 
     // Text
@@ -134,6 +271,38 @@ fn test_auto_escaping() {
     insta::assert_snapshot!(rv, @r###"foo"bar'baz"###);
 }
 
+#[test]
+fn test_auto_escape_for_extension() {
+    let mut env = Environment::empty();
+    env.set_auto_escape_for_extension("html", minijinja::AutoEscape::Html);
+    #[cfg(feature = "json")]
+    {
+        env.set_auto_escape_for_extension(".json", minijinja::AutoEscape::Json);
+    }
+    for name in ["index.html", "data.json", "notes.txt", "html"] {
+        env.add_template(name, "{{ var }}").unwrap();
+    }
+    fn render(env: &Environment, name: &str) -> String {
+        env.get_template(name)
+            .unwrap()
+            .render(context!(var => "<a & \"b\">"))
+            .unwrap()
+    }
+
+    assert_eq!(render(&env, "index.html"), "&lt;a &amp; &quot;b&quot;&gt;");
+    #[cfg(feature = "json")]
+    {
+        assert_eq!(render(&env, "data.json"), r#""<a & \"b\">""#);
+    }
+    assert_eq!(render(&env, "notes.txt"), "<a & \"b\">");
+    assert_eq!(render(&env, "html"), "<a & \"b\">");
+
+    // later calls override the mode for an extension
+    env.set_auto_escape_for_extension("html", minijinja::AutoEscape::None);
+    env.add_template("other.html", "{{ var }}").unwrap();
+    assert_eq!(render(&env, "other.html"), "<a & \"b\">");
+}
+
 #[test]
 fn test_loop_changed() {
     let rv = minijinja::render!(
@@ -146,3 +315,259 @@ fn test_loop_changed() {
     );
     assert_eq!(rv, "12345");
 }
+
+#[test]
+fn test_call_macro_from_host() {
+    fn invoke(state: &State, value: Value, arg: Value) -> Result<Value, Error> {
+        value.call(state, &[arg])
+    }
+
+    let mut env = Environment::new();
+    env.add_filter("invoke", invoke);
+    env.add_template(
+        "test",
+        "{% macro greet(name) %}Hello {{ name }}!{% endmacro %}{{ greet|invoke('Peter') }}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("test").unwrap();
+    assert_eq!(tmpl.render(()).unwrap(), "Hello Peter!");
+}
+
+#[test]
+#[cfg(feature = "debug")]
+fn test_error_template_trace() {
+    let mut env = Environment::new();
+    env.set_debug(true);
+    env.add_template(
+        "macros.html",
+        "{% macro fail(x) %}\n  {{ x.missing.attr }}\n{% endmacro %}",
+    )
+    .unwrap();
+    env.add_template(
+        "partial.html",
+        "{% from 'macros.html' import fail %}\nbefore\n{{ fail(1) }}",
+    )
+    .unwrap();
+    env.add_template(
+        "layout.html",
+        "<html>\n{% include 'partial.html' %}\n</html>",
+    )
+    .unwrap();
+
+    let err = env
+        .get_template("layout.html")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    insta::assert_snapshot!(err.to_string(), @r###"
+    could not render include: error in "partial.html" (in layout.html:2)
+    template trace (most recent call last):
+      layout.html:2
+        {% include 'partial.html' %}
+      partial.html:3
+        {{ fail(1) }}
+      macros.html:2
+        {{ x.missing.attr }}
+    "###);
+
+    // without debug mode no trace is recorded
+    env.set_debug(false);
+    let err = env
+        .get_template("layout.html")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "could not render include: error in \"partial.html\" (in layout.html:2)"
+    );
+}
+
+#[test]
+fn test_trim_output_whitespace() {
+    let mut env = Environment::new();
+    env.set_trim_output_whitespace(true);
+    env.add_template(
+        "list.html",
+        "<ul>\n{% for item in items %}\n  {% if item.visible %}\n\n    <li>{{ item.name }}</li>\n  {% endif %}\n\n{% endfor %}\n</ul>\n{% raw %}\n\n\n{% endraw %}\n{{ pre }}\n\n  \n\nend",
+    )
+    .unwrap();
+    let tmpl = env.get_template("list.html").unwrap();
+    let rv = tmpl
+        .render(context! {
+            items => vec![
+                context!(name => "a", visible => true),
+                context!(name => "b", visible => false),
+                context!(name => "c", visible => false),
+                context!(name => "d", visible => true),
+            ],
+            pre => Value::from_safe_string("<pre>\n\n\n</pre>".into()),
+        })
+        .unwrap();
+    assert_eq!(
+        rv,
+        "<ul>\n\n    <li>a</li>\n\n    <li>d</li>\n\n</ul>\n{% raw %}\n\n\n{% endraw %}\n<pre>\n\n\n</pre>\n\nend"
+    );
+
+    // captured output is not collapsed and trailing whitespace is kept
+    let rv = env
+        .render_str("a {% set x %}  {% endset %}[{{ x }}]", ())
+        .unwrap();
+    assert_eq!(rv, "a [  ]");
+    let rv = env
+        .render_str("{% set x %}a\n\n\n  {% endset %}{{ x }}", ())
+        .unwrap();
+    assert_eq!(rv, "a\n\n\n  ");
+    let rv = env.render_str("a\n   ", ()).unwrap();
+    assert_eq!(rv, "a\n   ");
+
+    // macro output is a value like any other and is not collapsed
+    #[cfg(feature = "macros")]
+    {
+        let rv = env
+            .render_str(
+                "{% macro m() %}c\n\n\n\nd{% endmacro %}{% set x = m() %}{{ x|length }}|{{ x }}",
+                (),
+            )
+            .unwrap();
+        assert_eq!(rv, "6|c\n\n\n\nd");
+    }
+
+    env.set_trim_output_whitespace(false);
+    let rv = env
+        .get_template("list.html")
+        .unwrap()
+        .render(context!(items => Vec::<Value>::new()))
+        .unwrap();
+    assert!(rv.ends_with("\n\n  \n\nend"));
+}
+
+#[test]
+fn test_loop_variables() {
+    let env = Environment::new();
+    let fields = [
+        "index",
+        "index0",
+        "revindex",
+        "revindex0",
+        "first",
+        "last",
+        "length",
+    ];
+    let tmpl = format!(
+        "{{% for item in seq %}}{}\n{{% endfor %}}",
+        fields
+            .iter()
+            .map(|field| format!("{{{{ loop.{} }}}}", field))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    let expected = [
+        "1 0 5 4 true false 5",
+        "2 1 4 3 false false 5",
+        "3 2 3 2 false false 5",
+        "4 3 2 1 false false 5",
+        "5 4 1 0 false true 5",
+    ];
+
+    let iterables = [
+        Value::from(vec!["a", "b", "c", "d", "e"]),
+        Value::from("abcde"),
+        Value::from_iter((0..5).map(|x| (x, x))),
+    ];
+    for seq in iterables {
+        let rv = env.render_str(&tmpl, context!(seq)).unwrap();
+        assert_eq!(rv.lines().collect::<Vec<_>>(), expected);
+    }
+
+    let rv = env
+        .render_str(
+            "{% for x in seq if x is odd %}{{ loop.index }}/{{ loop.length }} {% endfor %}",
+            context!(seq => vec![1, 2, 3, 4, 5]),
+        )
+        .unwrap();
+    assert_eq!(rv, "1/3 2/3 3/3 ");
+}
+
+#[test]
+fn test_debug_instructions() {
+    let mut env = Environment::new();
+    env.add_template("add.txt", "{{ a + b }}").unwrap();
+    let tmpl = env.get_template("add.txt").unwrap();
+    assert_eq!(
+        tmpl.debug_instructions(),
+        "00000 | Lookup(\"a\")  [line 1]\n\
+         00001 | Lookup(\"b\")\n\
+         00002 | Add\n\
+         00003 | Emit\n"
+    );
+
+    env.add_template("block.txt", "{% block body %}{{ x|upper }}{% endblock %}")
+        .unwrap();
+    let listing = env.get_template("block.txt").unwrap().debug_instructions();
+    let block = listing.split("block body:\n").nth(1).unwrap();
+    assert!(block.contains("ApplyFilter(\"upper\""));
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn test_html_producing_filters_in_json() {
+    let mut env = Environment::new();
+    env.add_template("nl2br.json", "{{ text|nl2br }}").unwrap();
+    let render = |name| {
+        env.get_template(name)
+            .unwrap()
+            .render(context!(text => "a & b\nc"))
+            .unwrap()
+    };
+    assert_eq!(render("nl2br.json"), r#""a & b<br>\nc""#);
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn test_join_in_json() {
+    let mut env = Environment::new();
+    env.add_template("join.json", "{{ items|join(', ') }}")
+        .unwrap();
+    env.add_template("join.html", "{{ items|join(', ') }}")
+        .unwrap();
+    let render = |name| {
+        env.get_template(name)
+            .unwrap()
+            .render(context!(items => vec!["a", "<b>", "\"c\""]))
+            .unwrap()
+    };
+    assert_eq!(render("join.json"), r#""a, <b>, \"c\"""#);
+    assert_eq!(render("join.html"), "a, &lt;b&gt;, &quot;c&quot;");
+}
+
+#[test]
+fn test_join_escapes_joiner() {
+    let mut env = Environment::new();
+    env.add_template("join.html", "{{ items|join(sep) }}")
+        .unwrap();
+    env.add_template("join_safe.html", "{{ items|join(sep|safe) }}")
+        .unwrap();
+    env.add_template("join.txt", "{{ items|join(sep) }}")
+        .unwrap();
+    let render = |name| {
+        env.get_template(name)
+            .unwrap()
+            .render(context!(items => vec!["a", "b"], sep => "<script>"))
+            .unwrap()
+    };
+    assert_eq!(render("join.html"), "a&lt;script&gt;b");
+    assert_eq!(render("join_safe.html"), "a<script>b");
+    assert_eq!(render("join.txt"), "a<script>b");
+}
+
+#[test]
+fn test_too_many_call_arguments() {
+    let env = Environment::new();
+    let source = format!("{{{{ range({}) }}}}", vec!["1"; 70_000].join(", "));
+    let err = env.render_str(&source, ()).unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::SyntaxError);
+    assert!(err
+        .to_string()
+        .contains("too many arguments in function call"));
+}
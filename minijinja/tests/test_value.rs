@@ -1,9 +1,12 @@
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
 
 use insta::assert_snapshot;
-use minijinja::value::{Object, Value};
-use minijinja::ErrorKind;
+use minijinja::value::{merge_maps, BinaryOp, Object, Value};
+use minijinja::{Environment, Error, ErrorKind, State};
 
 #[test]
 fn test_sort() {
@@ -47,10 +50,29 @@ fn test_undefined_roundtrip() {
     assert!(v2.is_undefined());
 }
 
+#[test]
+fn test_none_and_undefined() {
+    let none = Value::from(());
+    assert!(none.is_none());
+    assert!(!none.is_undefined());
+    assert!(Value::UNDEFINED.is_undefined());
+    assert!(!Value::UNDEFINED.is_none());
+    assert_eq!(none, Value::from(()));
+    assert_eq!(Value::UNDEFINED, Value::UNDEFINED);
+    assert_ne!(none, Value::UNDEFINED);
+    assert_ne!(Value::UNDEFINED, none);
+    assert_eq!(
+        Value::UNDEFINED.partial_cmp(&Value::UNDEFINED),
+        Some(Ordering::Equal)
+    );
+    assert_eq!(none.partial_cmp(&Value::UNDEFINED), None);
+}
+
 #[test]
 fn test_value_serialization() {
     // make sure if we serialize to json we get regular values
     assert_eq!(serde_json::to_string(&Value::UNDEFINED).unwrap(), "null");
+    assert_eq!(serde_json::to_string(&Value::from(())).unwrap(), "null");
     assert_eq!(
         serde_json::to_string(&Value::from_safe_string("foo".to_string())).unwrap(),
         "\"foo\""
@@ -128,3 +150,1127 @@ fn test_object_iteration() {
     z: 3
     "###);
 }
+
+#[test]
+fn test_int_float_comparisons() {
+    assert_eq!(Value::from(1), Value::from(1.0));
+    assert!(Value::from(1.5) < Value::from(2));
+    assert!(Value::from(-1.5) > Value::from(-2));
+    assert_ne!(Value::from(u64::MAX), Value::from(u64::MAX as f64));
+    assert!(Value::from(u64::MAX) < Value::from(u64::MAX as f64));
+    assert_eq!(Value::from(1).partial_cmp(&Value::from(f64::NAN)), None);
+}
+
+#[test]
+fn test_non_string_map_keys() {
+    let mut m = std::collections::BTreeMap::new();
+    m.insert(1i64, "one");
+    m.insert(2i64, "two");
+    let value = Value::from_serializable(&m);
+    assert_eq!(value.get_item(&Value::from(1)).unwrap(), Value::from("one"));
+    assert_eq!(
+        value.get_item(&Value::from(2.0)).unwrap(),
+        Value::from("two")
+    );
+    assert!(value.get_item(&Value::from(3)).unwrap().is_undefined());
+    assert_eq!(value.to_string(), "{1: \"one\", 2: \"two\"}");
+}
+
+#[test]
+fn test_value_try_iter() {
+    fn collect(value: &Value) -> Vec<String> {
+        value.try_iter().unwrap().map(|x| x.to_string()).collect()
+    }
+
+    assert_eq!(collect(&Value::from(vec![1, 2, 3])), ["1", "2", "3"]);
+    assert_eq!(
+        collect(&Value::from_serializable(
+            &std::collections::BTreeMap::from([("a", 1), ("b", 2)])
+        )),
+        ["a", "b"]
+    );
+    assert_eq!(collect(&Value::from("Grüß")), ["G", "r", "ü", "ß"]);
+    assert_eq!(
+        Value::from("Grüß").try_iter().unwrap().size_hint(),
+        (4, Some(4))
+    );
+    assert!(collect(&Value::UNDEFINED).is_empty());
+    assert_eq!(
+        Value::from(42).try_iter().err().unwrap().kind(),
+        ErrorKind::InvalidOperation
+    );
+
+    #[derive(Debug)]
+    struct Range3;
+
+    impl fmt::Display for Range3 {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "range3")
+        }
+    }
+
+    impl Object for Range3 {
+        fn iter(&self) -> Box<dyn Iterator<Item = Value> + '_> {
+            Box::new((0..3).map(Value::from))
+        }
+    }
+
+    let obj = Value::from_object(Range3);
+    assert_eq!(collect(&obj), ["0", "1", "2"]);
+    let rv = minijinja::render!("{% for x in obj %}[{{ x }}]{% endfor %}", obj => obj);
+    assert_eq!(rv, "[0][1][2]");
+    let rv = minijinja::render!("{{ obj|list }}|{{ (obj|list)[1] }}", obj => obj);
+    assert_eq!(rv, "[0, 1, 2]|1");
+}
+
+#[test]
+fn test_nested_host_lookups() {
+    let value = Value::from_serializable(&serde_json::json!({
+        "user": {
+            "name": "Peter",
+            "tags": ["admin", "staff"],
+        }
+    }));
+    let user = value.get_attr("user").unwrap();
+    assert_eq!(user.get_attr("name").unwrap(), Value::from("Peter"));
+    let tags = user.get_item(&Value::from("tags")).unwrap();
+    assert_eq!(
+        tags.get_item(&Value::from(-1)).unwrap(),
+        Value::from("staff")
+    );
+    assert_eq!(tags.get_item_by_index(0).unwrap(), Value::from("admin"));
+
+    // missing keys resolve to undefined, looking up on undefined fails
+    let missing = user.get_attr("missing").unwrap();
+    assert!(missing.is_undefined());
+    assert_eq!(
+        missing.get_attr("name").unwrap_err().kind(),
+        ErrorKind::UndefinedError
+    );
+}
+
+#[test]
+fn test_object_pairs() {
+    #[derive(Debug)]
+    struct OrderedMap(Vec<(&'static str, i32)>);
+
+    impl fmt::Display for OrderedMap {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "ordered map")
+        }
+    }
+
+    impl Object for OrderedMap {
+        fn get_attr(&self, name: &str) -> Option<Value> {
+            self.0
+                .iter()
+                .find(|x| x.0 == name)
+                .map(|x| Value::from(x.1))
+        }
+
+        fn attributes(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+            Box::new(self.0.iter().map(|x| x.0))
+        }
+
+        fn pairs(&self) -> Option<Box<dyn Iterator<Item = (Value, Value)> + '_>> {
+            Some(Box::new(
+                self.0
+                    .iter()
+                    .map(|(k, v)| (Value::from(*k), Value::from(*v))),
+            ))
+        }
+    }
+
+    let map = Value::from_object(OrderedMap(vec![("z", 1), ("a", 2), ("m", 3)]));
+    let rv = minijinja::render!(
+        "{% for k, v in map.items() %}{{ k }}={{ v }};{% endfor %}|\
+         {% for k, v in map|items %}{{ k }}={{ v }};{% endfor %}",
+        map => map
+    );
+    assert_eq!(rv, "z=1;a=2;m=3;|z=1;a=2;m=3;");
+
+    let rv = minijinja::render!(
+        "{% for k, v in map|dictsort %}{{ k }}={{ v }};{% endfor %}",
+        map => map
+    );
+    assert_eq!(rv, "a=2;m=3;z=1;");
+
+    let merged = merge_maps([
+        Value::from_object(OrderedMap(vec![("a", 1), ("b", 2)])),
+        map,
+    ]);
+    assert_eq!(merged.get_attr("a").unwrap(), Value::from(2));
+    assert_eq!(merged.get_attr("b").unwrap(), Value::from(2));
+    assert_eq!(merged.get_attr("z").unwrap(), Value::from(1));
+
+    #[derive(Debug)]
+    struct NoPairs;
+
+    impl fmt::Display for NoPairs {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "no pairs")
+        }
+    }
+
+    impl Object for NoPairs {}
+
+    let env = Environment::new();
+    for filter in ["dictsort", "items"] {
+        let err = env
+            .render_str(
+                &format!("{{{{ obj|{} }}}}", filter),
+                minijinja::context!(obj => Value::from_object(NoPairs)),
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+        assert_eq!(
+            err.to_string(),
+            "invalid operation: cannot convert object into pair list, it does not \
+             provide pairs (in <string>:1)"
+        );
+    }
+}
+
+#[test]
+fn test_object_custom_op() {
+    #[derive(Debug)]
+    struct Money {
+        cents: i64,
+        currency: &'static str,
+    }
+
+    impl fmt::Display for Money {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "{}.{:02} {}",
+                self.cents / 100,
+                self.cents % 100,
+                self.currency
+            )
+        }
+    }
+
+    impl Object for Money {
+        fn custom_op(&self, op: BinaryOp, other: &Value) -> Option<Result<Value, Error>> {
+            let other = other.downcast_object_ref::<Money>()?;
+            if op != BinaryOp::Add {
+                return None;
+            }
+            if other.currency != self.currency {
+                return Some(Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "currency mismatch",
+                )));
+            }
+            Some(Ok(Value::from_object(Money {
+                cents: self.cents + other.cents,
+                currency: self.currency,
+            })))
+        }
+    }
+
+    let env = Environment::new();
+    let ctx = minijinja::context! {
+        a => Value::from_object(Money { cents: 1050, currency: "EUR" }),
+        b => Value::from_object(Money { cents: 275, currency: "EUR" }),
+        c => Value::from_object(Money { cents: 100, currency: "USD" }),
+    };
+    assert_eq!(env.render_str("{{ a + b }}", &ctx).unwrap(), "13.25 EUR");
+    let err = env.render_str("{{ a + c }}", &ctx).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert!(err.to_string().contains("currency mismatch"));
+    let err = env.render_str("{{ a - b }}", &ctx).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+}
+
+#[test]
+fn test_map_and_selectattr_methods() {
+    #[derive(Debug)]
+    struct User {
+        name: &'static str,
+        active: bool,
+        calls: AtomicUsize,
+    }
+
+    impl fmt::Display for User {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.name)
+        }
+    }
+
+    impl Object for User {
+        fn get_attr(&self, name: &str) -> Option<Value> {
+            match name {
+                "name" => Some(Value::from(self.name)),
+                _ => None,
+            }
+        }
+
+        fn call_method(&self, _state: &State, name: &str, args: &[Value]) -> Result<Value, Error> {
+            self.calls.fetch_add(1, atomic::Ordering::Relaxed);
+            match (name, args) {
+                ("greeting", []) => Ok(Value::from(format!("Hello {}!", self.name))),
+                ("is_active", []) => Ok(Value::from(self.active)),
+                ("name", _) => panic!("fields must not be invoked as methods"),
+                ("broken", []) => Err(Error::new(ErrorKind::InvalidOperation, "broken")),
+                _ => Err(Error::new(
+                    ErrorKind::UnknownMethod,
+                    format!("object has no method named {}", name),
+                )),
+            }
+        }
+    }
+
+    let users = [
+        Arc::new(User {
+            name: "Alice",
+            active: true,
+            calls: AtomicUsize::new(0),
+        }),
+        Arc::new(User {
+            name: "Bob",
+            active: false,
+            calls: AtomicUsize::new(0),
+        }),
+    ];
+    let env = Environment::new();
+    let ctx = minijinja::context! {
+        users => users.iter().cloned().map(Value::from).collect::<Vec<_>>(),
+    };
+    let render = |source| env.render_str(source, &ctx).unwrap();
+
+    assert_eq!(
+        render("{{ users|map(attribute='greeting')|join(' ') }}"),
+        "Hello Alice! Hello Bob!"
+    );
+    assert_eq!(
+        render("{{ users|selectattr('is_active')|map(attribute='name')|join(',') }}"),
+        "Alice"
+    );
+    assert_eq!(
+        render("{{ users|map(attribute='missing', default='-')|join(',') }}"),
+        "-,-"
+    );
+    assert_eq!(
+        render("{{ users|map(attribute='name')|map('upper')|join(',') }}"),
+        "ALICE,BOB"
+    );
+    assert_eq!(users[0].calls.load(atomic::Ordering::Relaxed), 3);
+
+    // errors other than unknown methods are not swallowed
+    let err = env
+        .render_str("{{ users|map(attribute='broken')|join(',') }}", &ctx)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+}
+
+#[test]
+fn test_function_in_context() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let now = Value::from_function({
+        let counter = counter.clone();
+        move || counter.fetch_add(1, atomic::Ordering::Relaxed) as u64
+    });
+    let greet = Value::from_function(|name: &str, greeting: Option<&str>| {
+        format!("{}, {}!", greeting.unwrap_or("Hello"), name)
+    });
+    let env = Environment::new();
+    let ctx = minijinja::context!(now, greet);
+    assert_eq!(
+        env.render_str(
+            "{{ now() }}|{{ now() }}|{{ greet('World') }}|{{ greet('Peter', 'Hi') }}",
+            &ctx
+        )
+        .unwrap(),
+        "0|1|Hello, World!|Hi, Peter!"
+    );
+    assert_eq!(counter.load(atomic::Ordering::Relaxed), 2);
+    // with the time feature `now` is also a global function
+    #[cfg(not(feature = "time"))]
+    assert!(env.functions().all(|name| name != "now"));
+}
+
+#[test]
+#[cfg(feature = "time")]
+fn test_function_in_context_shadows_global() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let mut env = Environment::new();
+    env.set_clock(|| UNIX_EPOCH + Duration::from_secs(86_400));
+    let now = Value::from_function(|| "from context");
+    assert_eq!(
+        env.render_str("{{ now() }}", minijinja::context!(now))
+            .unwrap(),
+        "from context"
+    );
+    assert_eq!(
+        env.render_str("{{ now() }}", ()).unwrap(),
+        "1970-01-02T00:00:00Z"
+    );
+    assert!(env.functions().any(|name| name == "now"));
+}
+
+#[test]
+fn test_loop_over_lazy_iterators() {
+    #[derive(Debug)]
+    struct Evens(u32);
+
+    impl fmt::Display for Evens {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "evens")
+        }
+    }
+
+    impl Object for Evens {
+        fn iter(&self) -> Box<dyn Iterator<Item = Value> + '_> {
+            // the filter means the iterator cannot know its length up front
+            Box::new((0..self.0).filter(|x| x % 2 == 0).map(Value::from))
+        }
+    }
+
+    let template = "{% for x in items %}{{ x }}:{{ loop.first }}/{{ loop.last }}/\
+                    {{ loop.length }}/{{ loop.revindex }}{% if not loop.last %} {% endif %}\
+                    {% endfor %}";
+    let env = Environment::new();
+    assert_eq!(
+        env.render_str(
+            template,
+            minijinja::context!(items => Value::from_object(Evens(5)))
+        )
+        .unwrap(),
+        "0:true/false/3/3 2:false/false/3/2 4:false/true/3/1"
+    );
+    assert_eq!(
+        env.render_str(
+            &template.replace("items", "range(3)"),
+            minijinja::context!()
+        )
+        .unwrap(),
+        "0:true/false/3/3 1:false/false/3/2 2:false/true/3/1"
+    );
+}
+
+#[test]
+fn test_as_object() {
+    #[derive(Debug)]
+    struct Point(i32, i32);
+
+    impl fmt::Display for Point {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}, {}", self.0, self.1)
+        }
+    }
+
+    impl Object for Point {
+        fn get_attr(&self, name: &str) -> Option<Value> {
+            match name {
+                "x" => Some(Value::from(self.0)),
+                "y" => Some(Value::from(self.1)),
+                _ => None,
+            }
+        }
+
+        fn attributes(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+            Box::new(["x", "y"].into_iter())
+        }
+    }
+
+    let point = Value::from_object(Point(1, 2));
+    let map = Value::from_serializable(&std::collections::BTreeMap::from([("x", 1), ("y", 2)]));
+
+    assert!(point.is_object());
+    assert!(!map.is_object());
+    assert!(map.as_object().is_none());
+
+    let obj = point.as_object().unwrap();
+    assert_eq!(obj.attributes().collect::<Vec<_>>(), ["x", "y"]);
+    assert_eq!(obj.get_attr("y"), Some(Value::from(2)));
+}
+
+#[test]
+fn test_object_custom_eq() {
+    #[derive(Debug)]
+    struct Id(i64);
+
+    impl fmt::Display for Id {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "#{}", self.0)
+        }
+    }
+
+    impl Object for Id {
+        fn custom_eq(&self, other: &Value) -> Option<bool> {
+            match other.downcast_object_ref::<Id>() {
+                Some(other) => Some(self.0 == other.0),
+                None => Some(Value::from(self.0) == *other),
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct Opaque;
+
+    impl fmt::Display for Opaque {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "opaque")
+        }
+    }
+
+    impl Object for Opaque {}
+
+    let env = Environment::new();
+    let ctx = minijinja::context! {
+        a => Value::from_object(Id(1)),
+        b => Value::from_object(Id(1)),
+        c => Value::from_object(Id(2)),
+        opaque => Value::from_object(Opaque),
+        other_opaque => Value::from_object(Opaque),
+    };
+    let rv = env
+        .render_str(
+            "{{ a == b }}|{{ a == c }}|{{ [a, c] == [b, 2] }}|{{ {'x': a} == {'x': 1} }}|\
+             {{ opaque == opaque }}|{{ opaque == other_opaque }}",
+            ctx,
+        )
+        .unwrap();
+    assert_eq!(rv, "true|false|true|true|true|false");
+}
+
+#[test]
+fn test_object_render() {
+    #[derive(Debug)]
+    struct Link(&'static str);
+
+    impl fmt::Display for Link {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Link({:?})", self.0)
+        }
+    }
+
+    impl Object for Link {
+        fn render(&self) -> Option<Value> {
+            Some(Value::from_safe_string(format!(
+                "<a href=\"{}\">{}</a>",
+                self.0, self.0
+            )))
+        }
+    }
+
+    let link = Value::from_object(Link("/about"));
+    assert_eq!(format!("{}", link), "Link(\"/about\")");
+
+    let mut env = Environment::new();
+    env.add_template("link.html", "{{ link }}|{{ link ~ '' }}")
+        .unwrap();
+    let rv = env
+        .get_template("link.html")
+        .unwrap()
+        .render(minijinja::context! { link })
+        .unwrap();
+    assert_eq!(
+        rv,
+        "<a href=\"/about\">/about</a>|Link(&quot;&#x2f;about&quot;)"
+    );
+
+    // an object that renders as an object again is written with Display
+    #[derive(Debug)]
+    struct Nested;
+
+    impl fmt::Display for Nested {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "<nested>")
+        }
+    }
+
+    impl Object for Nested {
+        fn render(&self) -> Option<Value> {
+            Some(Value::from_object(Nested))
+        }
+    }
+
+    env.add_template("nested.html", "{{ nested }}").unwrap();
+    let rv = env
+        .get_template("nested.html")
+        .unwrap()
+        .render(minijinja::context! { nested => Value::from_object(Nested) })
+        .unwrap();
+    assert_eq!(rv, "&lt;nested&gt;");
+}
+
+#[test]
+fn test_value_default() {
+    assert!(Value::default().is_undefined());
+    assert!(Value::NONE.is_none());
+    assert_eq!(Value::NONE, Value::from(()));
+
+    let ctx = minijinja::context! {
+        missing => Value::default(),
+        nothing => Value::NONE,
+    };
+    let mut env = Environment::new();
+    assert_eq!(
+        env.render_str("[{{ missing }}]|{{ nothing }}", &ctx)
+            .unwrap(),
+        "[]|none"
+    );
+
+    env.set_undefined_behavior(minijinja::UndefinedBehavior::SemiStrict);
+    let err = env.render_str("{{ missing }}", &ctx).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UndefinedError);
+    assert_eq!(env.render_str("{{ nothing }}", &ctx).unwrap(), "none");
+}
+
+#[test]
+fn test_value_from_conversions() {
+    use std::collections::{BTreeMap, HashMap};
+
+    let mut address = HashMap::new();
+    address.insert("city", Value::from("Vienna"));
+    address.insert("zip", 1010u16.into());
+
+    let mut user = BTreeMap::new();
+    user.insert("name", Value::from(String::from("Peter")));
+    user.insert("age", 42i32.into());
+    user.insert("score", 9.5f32.into());
+    user.insert("admin", false.into());
+    user.insert("tags", vec!["a", "b"].into());
+    user.insert("nickname", Option::<&str>::None.into());
+    user.insert("email", Some("peter@example.com").into());
+    user.insert("offset", (-3isize).into());
+    user.insert("address", address.into());
+
+    let ctx: Value = vec![("user", Value::from(user))].into_iter().collect();
+    let env = Environment::new();
+    let rv = env
+        .render_str(
+            "{{ user.name }}|{{ user.age + 1 }}|{{ user.score }}|{{ user.admin }}|\
+             {{ user.tags|join(',') }}|{{ user.nickname == none }}|{{ user.email }}|\
+             {{ user.offset }}|{{ user.address.city }} {{ user.address.zip }}",
+            ctx,
+        )
+        .unwrap();
+    assert_eq!(
+        rv,
+        "Peter|43|9.5|false|a,b|true|peter@example.com|-3|Vienna 1010"
+    );
+    assert_eq!(Value::from(Option::<i32>::None), Value::NONE);
+    assert_eq!(Value::from(Some(1)), Value::from(1));
+}
+
+#[test]
+fn test_object_shared_handle() {
+    #[derive(Debug, Default)]
+    struct Queue(std::sync::Mutex<Vec<String>>);
+
+    impl fmt::Display for Queue {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.0.lock().unwrap().join(", "))
+        }
+    }
+
+    impl Object for Queue {
+        fn iter(&self) -> Box<dyn Iterator<Item = Value> + '_> {
+            let items = self.0.lock().unwrap().clone();
+            Box::new(items.into_iter().map(Value::from))
+        }
+    }
+
+    let handle = Arc::new(Queue::default());
+    let value = Value::from(handle.clone());
+    assert!(std::ptr::eq(
+        value.downcast_object_ref::<Queue>().unwrap(),
+        &*handle
+    ));
+
+    let mut env = Environment::new();
+    env.add_template("queue.txt", "{% for job in queue %}[{{ job }}]{% endfor %}")
+        .unwrap();
+    let tmpl = env.get_template("queue.txt").unwrap();
+    let ctx = minijinja::context!(queue => value);
+    assert_eq!(tmpl.render(&ctx).unwrap(), "");
+    handle.0.lock().unwrap().push("build".into());
+    handle.0.lock().unwrap().push("test".into());
+    assert_eq!(tmpl.render(&ctx).unwrap(), "[build][test]");
+    handle.0.lock().unwrap().remove(0);
+    assert_eq!(tmpl.render(&ctx).unwrap(), "[test]");
+}
+
+#[test]
+fn test_object_len() {
+    #[derive(Debug)]
+    struct Big {
+        attributes_calls: AtomicUsize,
+    }
+
+    impl fmt::Display for Big {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "big")
+        }
+    }
+
+    impl Object for Big {
+        fn attributes(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+            self.attributes_calls
+                .fetch_add(1, atomic::Ordering::Relaxed);
+            Box::new(std::iter::repeat("x").take(100_000))
+        }
+
+        fn len(&self) -> Option<usize> {
+            Some(100_000)
+        }
+    }
+
+    let obj = Arc::new(Big {
+        attributes_calls: AtomicUsize::new(0),
+    });
+    let value = Value::from(obj.clone());
+    assert_eq!(value.len(), obj.len());
+
+    let env = Environment::new();
+    let rv = env
+        .render_str(
+            "{{ obj|length }}|{{ obj|count }}",
+            minijinja::context! { obj => value },
+        )
+        .unwrap();
+    assert_eq!(rv, "100000|100000");
+    assert_eq!(obj.attributes_calls.load(atomic::Ordering::Relaxed), 0);
+}
+
+#[test]
+fn test_object_is_true() {
+    #[derive(Debug)]
+    struct Form {
+        errors: Vec<&'static str>,
+    }
+
+    impl fmt::Display for Form {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "form")
+        }
+    }
+
+    impl Object for Form {
+        fn is_true(&self) -> Option<bool> {
+            Some(self.errors.is_empty())
+        }
+
+        fn len(&self) -> Option<usize> {
+            Some(self.errors.len())
+        }
+    }
+
+    #[derive(Debug)]
+    struct Empty;
+
+    impl fmt::Display for Empty {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "empty")
+        }
+    }
+
+    impl Object for Empty {
+        fn len(&self) -> Option<usize> {
+            Some(0)
+        }
+    }
+
+    let invalid = Value::from_object(Form {
+        errors: vec!["name is required"],
+    });
+    let valid = Value::from_object(Form { errors: vec![] });
+    assert!(!invalid.is_true());
+    assert!(valid.is_true());
+    assert!(!Value::from_object(Empty).is_true());
+
+    let env = Environment::new();
+    let tmpl = "{% if form %}submitted{% else %}invalid{% endif %}|{{ not form }}";
+    assert_eq!(
+        env.render_str(tmpl, minijinja::context! { form => invalid })
+            .unwrap(),
+        "invalid|true"
+    );
+    assert_eq!(
+        env.render_str(tmpl, minijinja::context! { form => valid })
+            .unwrap(),
+        "submitted|false"
+    );
+}
+
+#[test]
+fn test_object_contains() {
+    #[derive(Debug)]
+    struct Headers;
+
+    impl fmt::Display for Headers {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "headers")
+        }
+    }
+
+    impl Object for Headers {
+        fn pairs(&self) -> Option<Box<dyn Iterator<Item = (Value, Value)> + '_>> {
+            Some(Box::new(
+                [("Host", "example.com"), ("Accept", "*/*")]
+                    .into_iter()
+                    .map(|(k, v)| (Value::from(k), Value::from(v))),
+            ))
+        }
+    }
+
+    #[derive(Debug)]
+    struct Evens;
+
+    impl fmt::Display for Evens {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "evens")
+        }
+    }
+
+    impl Object for Evens {
+        fn iter(&self) -> Box<dyn Iterator<Item = Value> + '_> {
+            Box::new((0..10).step_by(2).map(Value::from))
+        }
+    }
+
+    let env = Environment::new();
+    let rv = env
+        .render_str(
+            "{{ 'Host' in headers }}|{{ 'example.com' in headers }}|{{ 4 in evens }}|{{ 5 in evens }}",
+            minijinja::context! {
+                headers => Value::from_object(Headers),
+                evens => Value::from_object(Evens),
+            },
+        )
+        .unwrap();
+    assert_eq!(rv, "true|false|true|false");
+}
+
+#[test]
+fn test_value_from_dyn_object() {
+    #[derive(Debug)]
+    struct Greeter;
+
+    impl fmt::Display for Greeter {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "greeter")
+        }
+    }
+
+    impl Object for Greeter {
+        fn call_method(&self, _state: &State, name: &str, args: &[Value]) -> Result<Value, Error> {
+            match name {
+                "greet" => Ok(Value::from(format!("Hello {}!", args[0]))),
+                _ => Err(Error::new(ErrorKind::InvalidOperation, "no such method")),
+            }
+        }
+    }
+
+    let obj: Arc<dyn Object> = Arc::new(Greeter);
+    let value = Value::from_dyn_object(obj.clone());
+    assert!(std::ptr::eq(
+        value.as_object().unwrap() as *const dyn Object as *const u8,
+        &*obj as *const dyn Object as *const u8
+    ));
+
+    let env = Environment::new();
+    let rv = env
+        .render_str(
+            "{{ obj.greet('World') }}",
+            minijinja::context! { obj => value },
+        )
+        .unwrap();
+    assert_eq!(rv, "Hello World!");
+}
+
+#[test]
+fn test_get_path() {
+    let value = Value::from_serializable(&serde_json::json!({
+        "a": {"b": [{"c": 1}, {"c": 2}]}
+    }));
+    assert_eq!(value.get_path("a.b.1.c"), Value::from(2));
+    assert!(value.get_path("a.b.2.c").is_undefined());
+    assert!(value.get_path("a.missing.0").is_undefined());
+    assert!(Value::UNDEFINED.get_path("a").is_undefined());
+}
+
+#[test]
+fn test_shared_map_keys() {
+    let items = (0..100_000)
+        .map(|idx| {
+            let mut m = BTreeMap::new();
+            m.insert("id", Value::from(idx % 10));
+            m.insert("name", Value::from("item"));
+            m
+        })
+        .collect::<Vec<_>>();
+    let value = Value::from_serializable(&items);
+    assert_eq!(value.len(), Some(100_000));
+    assert_eq!(
+        value.get_item_by_index(3).unwrap(),
+        value.get_item_by_index(99_993).unwrap()
+    );
+    assert_ne!(
+        value.get_item_by_index(3).unwrap(),
+        value.get_item_by_index(4).unwrap()
+    );
+
+    let env = Environment::new();
+    let rv = env
+        .render_str(
+            "{{ items|selectattr('id', 'even')|map(attribute='name')|list|length }}",
+            minijinja::context! { items => value },
+        )
+        .unwrap();
+    assert_eq!(rv, "50000");
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn test_to_json() {
+    use minijinja::value::{to_json, to_json_pretty};
+
+    #[derive(Debug)]
+    struct Point(i32, i32);
+
+    impl fmt::Display for Point {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "({}, {})", self.0, self.1)
+        }
+    }
+
+    impl Object for Point {
+        fn get_attr(&self, name: &str) -> Option<Value> {
+            match name {
+                "x" => Some(Value::from(self.0)),
+                "y" => Some(Value::from(self.1)),
+                _ => None,
+            }
+        }
+
+        fn attributes(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+            Box::new(["x", "y"].into_iter())
+        }
+    }
+
+    let value = Value::from(vec![
+        Value::from_object(Point(1, 2)),
+        Value::from_safe_string("<em>origin</em>".into()),
+    ]);
+    assert_eq!(
+        to_json(&value).unwrap(),
+        r#"[{"x":1,"y":2},"<em>origin</em>"]"#
+    );
+    assert_eq!(
+        to_json_pretty(&Value::from_object(Point(1, 2))).unwrap(),
+        "{\n  \"x\": 1,\n  \"y\": 2\n}"
+    );
+
+    let env = Environment::new();
+    let rv = env
+        .render_str("{{ value|tojson }}", minijinja::context! { value })
+        .unwrap();
+    assert_eq!(
+        rv,
+        r#"[{"x":1,"y":2},"\u003cem\u003eorigin\u003c/em\u003e"]"#
+    );
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn test_to_json_non_finite_in_objects() {
+    #[derive(Debug)]
+    struct Floats;
+
+    impl fmt::Display for Floats {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "floats")
+        }
+    }
+
+    impl Object for Floats {
+        fn iter(&self) -> Box<dyn Iterator<Item = Value> + '_> {
+            Box::new([1.0, f64::NAN].into_iter().map(Value::from))
+        }
+
+        fn len(&self) -> Option<usize> {
+            Some(2)
+        }
+    }
+
+    #[derive(Debug)]
+    struct Pairs;
+
+    impl fmt::Display for Pairs {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "pairs")
+        }
+    }
+
+    impl Object for Pairs {
+        fn pairs(&self) -> Option<Box<dyn Iterator<Item = (Value, Value)> + '_>> {
+            Some(Box::new(
+                [(Value::from("x"), Value::from(f64::INFINITY))].into_iter(),
+            ))
+        }
+    }
+
+    let env = Environment::new();
+    for value in [Value::from_object(Floats), Value::from_object(Pairs)] {
+        let err = env
+            .render_str(
+                "{{ [value]|tojson(allow_nan=false) }}",
+                minijinja::context! { value },
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    }
+}
+
+#[test]
+fn test_value_hash_consistent_with_eq() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    fn hash(value: &Value) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[derive(Debug)]
+    struct Code(&'static str);
+
+    impl fmt::Display for Code {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl Object for Code {
+        fn custom_eq(&self, other: &Value) -> Option<bool> {
+            Some(other.as_str() == Some(self.0))
+        }
+
+        fn custom_hash(&self) -> Option<Value> {
+            Some(Value::from(self.0))
+        }
+    }
+
+    let equal_pairs = [
+        (Value::from(1), Value::from(1.0)),
+        (Value::from(1u64), Value::from(true)),
+        (Value::from(-0.0), Value::from(0)),
+        (Value::from(i128::MAX), Value::from(i128::MAX)),
+        (Value::from("a"), Value::from_safe_string("a".into())),
+        (Value::from(vec![1, 2]), Value::from(vec![1.0, 2.0])),
+        (
+            Value::from_serializable(&BTreeMap::from([("a", 1), ("b", 2)])),
+            Value::from_serializable(&BTreeMap::from([("b", 2.0), ("a", 1.0)])),
+        ),
+        (Value::from_object(Code("x")), Value::from("x")),
+        (
+            Value::from(vec![Value::from_object(Code("y"))]),
+            Value::from(vec!["y"]),
+        ),
+    ];
+    for (a, b) in equal_pairs.iter() {
+        assert_eq!(a, b);
+        assert_eq!(hash(a), hash(b), "{:?} and {:?} hash differently", a, b);
+    }
+}
+
+#[test]
+fn test_unique_many_duplicates() {
+    #[derive(Debug)]
+    struct Token(i64);
+
+    impl fmt::Display for Token {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "token-{}", self.0)
+        }
+    }
+
+    impl Object for Token {
+        fn custom_eq(&self, other: &Value) -> Option<bool> {
+            Some(i64::try_from(other.clone()).ok() == Some(self.0))
+        }
+
+        fn custom_hash(&self) -> Option<Value> {
+            Some(Value::from(self.0))
+        }
+    }
+
+    let mut items = Vec::new();
+    for idx in 0..20_000i64 {
+        let value = match idx % 4 {
+            0 => Value::from(idx % 97),
+            1 => Value::from((idx % 89) as f64),
+            2 => Value::from(format!("s{}", idx % 53)),
+            _ => Value::from(vec![Value::from(idx % 7), Value::from("x")]),
+        };
+        items.push(value);
+    }
+    // this object equals a number already seen
+    items.push(Value::from_object(Token(5)));
+    items.push(Value::from_object(Token(1000)));
+    items.push(Value::from(1000));
+
+    let mut expected: Vec<Value> = Vec::new();
+    for item in &items {
+        if !expected.contains(item) {
+            expected.push(item.clone());
+        }
+    }
+
+    let env = Environment::new();
+    let rv = env
+        .compile_expression("items|unique(case_sensitive=true)")
+        .unwrap()
+        .eval(minijinja::context! { items => items })
+        .unwrap();
+    assert_eq!(rv.len(), Some(expected.len()));
+    assert_eq!(rv.to_string(), Value::from(expected).to_string());
+}
+
+#[test]
+fn test_callable_object() {
+    #[derive(Debug)]
+    struct Double;
+
+    impl fmt::Display for Double {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Double {{ .. }}")
+        }
+    }
+
+    impl Object for Double {
+        fn is_callable(&self) -> bool {
+            true
+        }
+
+        fn call(&self, _state: &State, args: &[Value]) -> Result<Value, Error> {
+            let (x,): (i64,) = minijinja::value::from_args(args)?;
+            Ok(Value::from(x * 2))
+        }
+    }
+
+    let double = Value::from_object(Double);
+    assert!(double.is_callable());
+    assert!(!Value::from(42).is_callable());
+    // only the template output uses the function-like display
+    assert_eq!(double.to_string(), "Double { .. }");
+
+    let mut env = Environment::new();
+    env.add_global("double", double);
+    env.add_template(
+        "test.html",
+        "{% macro m() %}{% endmacro %}\
+         {{ double }}|{{ double(21) }}|{{ double is callable }}|\
+         {{ m is callable }}|{{ m is not callable }}|{{ 42 is callable }}",
+    )
+    .unwrap();
+    let rv = env.get_template("test.html").unwrap().render(()).unwrap();
+    assert_eq!(rv, "&lt;function&gt;|42|true|true|false|false");
+}